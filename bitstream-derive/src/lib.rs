@@ -0,0 +1,529 @@
+//! bitstream-derive: `#[derive(BitDecode, BitEncode)]` for structs, generating the field by field
+//! reading and writing code that would otherwise be written out by hand.
+//!
+//! Fields are read in declaration order. By default, each field uses the `BitDecode` and
+//! `BitEncode` implementations of its type. The encoding can be changed with these attributes:
+//!
+//! * `#[bits(N)]`: An unsigned integer of `N` bits, cast to the type of the field.
+//! * `#[coord]`: A coordinate, as read by `BitRead::read_coord`.
+//! * `#[varint]`: A protobuf-style varint. `i32` and `i64` fields use the zigzag encoding.
+//! * `#[since(protocol = N)]`: The field is only present in protocol version `N` and later.
+//!   Otherwise, the field is skipped and set to its `Default` value when read.
+//!
+//! The encoding attributes also apply to fields of type `Option<T>` or tuples, in which case the
+//! encoding is applied to each value inside of them. As with the `BitDecode` implementation for
+//! `Option<T>`, an option is prefixed with a bit indicating whether the value is present.
+//!
+//! Generic structs are supported, with a `BitDecode` or `BitEncode` bound added to each of their
+//! type parameters.
+//!
+//! This is written against the compiler's `proc_macro` API alone, so it only understands the
+//! subset of Rust syntax that is needed for packet structs: structs with named or unnamed fields.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+#[proc_macro_derive(BitDecode, attributes(bits, coord, varint, since))]
+pub fn derive_bit_decode(input: TokenStream) -> TokenStream {
+	expand(input, decode_impl)
+}
+
+#[proc_macro_derive(BitEncode, attributes(bits, coord, varint, since))]
+pub fn derive_bit_encode(input: TokenStream) -> TokenStream {
+	expand(input, encode_impl)
+}
+
+fn expand(input: TokenStream, generate: fn(&Struct) -> String) -> TokenStream {
+	let code = match parse_struct(input) {
+		Ok(parsed) => generate(&parsed),
+		Err(message) => format!("compile_error!({:?});", message)
+	};
+
+	// Infallible: the generated code is always syntactically valid
+	code.parse().unwrap()
+}
+
+// -- Code generation --
+
+fn decode_impl(parsed: &Struct) -> String {
+	let mut body = String::new();
+
+	for (index, field) in parsed.fields.iter().enumerate() {
+		let result = match (field.encoding, field.since) {
+			(Encoding::Default, None) => format!("<{} as ::bitstream::BitDecode>::decode(bits, protocol)", field.ty.text),
+			(encoding, since) => {
+				let mut value = match encoding {
+					Encoding::Default => format!("<{} as ::bitstream::BitDecode>::decode(bits, protocol)?", field.ty.text),
+					_ => decode_value(&field.ty, encoding)
+				};
+
+				if let Some(since) = since {
+					value = format!("if protocol >= {} {{ {} }} else {{ ::core::default::Default::default() }}", since, value);
+				}
+
+				format!("(|| -> ::core::result::Result<{}, ::bitstream::BitParseError> {{ Ok({}) }})()", field.ty.text, value)
+			}
+		};
+
+		// The field name labels both errors and traced reads
+		body += &format!(
+			"let field_{} = ::bitstream::ParseContext::context(bits.with_label({:?}, |bits| {}), {:?})?;\n",
+			index, field.name, result, field.name
+		);
+	}
+
+	let fields = (0..parsed.fields.len()).map(|index| format!("field_{}", index));
+
+	let construct = match parsed.kind {
+		Kind::Named => {
+			let fields: Vec<String> = parsed.fields.iter().zip(fields).map(|(field, local)| format!("{}: {}", field.name, local)).collect();
+			format!("{} {{ {} }}", parsed.name, fields.join(", "))
+		},
+		Kind::Unnamed => format!("{}({})", parsed.name, fields.collect::<Vec<_>>().join(", ")),
+		Kind::Unit => parsed.name.clone()
+	};
+
+	format!(
+		"{} {{
+			#[allow(unused_variables)]
+			fn decode(bits: &mut ::bitstream::BitReader, protocol: u32) -> ::core::result::Result<Self, ::bitstream::BitParseError> {{
+				use ::bitstream::BitRead as _;
+
+				bits.try_parse(|bits| {{
+					{}
+					Ok({})
+				}})
+			}}
+		}}",
+		impl_header(parsed, "BitDecode"), body, construct
+	)
+}
+
+/// The `impl` line for the given trait, bounding each type parameter by the trait.
+fn impl_header(parsed: &Struct, trait_name: &str) -> String {
+	let generics = &parsed.generics;
+
+	if generics.params.is_empty() {
+		return format!("impl ::bitstream::{} for {}", trait_name, parsed.name);
+	}
+
+	let mut predicates: Vec<String> = generics.types.iter().map(|ty| format!("{}: ::bitstream::{}", ty, trait_name)).collect();
+	predicates.extend(generics.where_clause.clone());
+
+	format!(
+		"impl<{}> ::bitstream::{} for {}<{}> where {}",
+		generics.params.join(", "), trait_name, parsed.name, generics.arguments.join(", "), predicates.join(", ")
+	)
+}
+
+fn decode_value(ty: &Type, encoding: Encoding) -> String {
+	match ty.shape {
+		Shape::Option(ref inner) => format!(
+			"if bits.read_bit()? {{ ::core::option::Option::Some({}) }} else {{ ::core::option::Option::None }}",
+			decode_value(inner, encoding)
+		),
+		Shape::Tuple(ref items) => {
+			let items: Vec<String> = items.iter().map(|item| decode_value(item, encoding)).collect();
+			format!("({},)", items.join(", "))
+		},
+		Shape::Leaf => match encoding {
+			Encoding::Default => format!("<{} as ::bitstream::BitDecode>::decode(bits, protocol)?", ty.text),
+			// `as` cannot convert integers to bool
+			Encoding::Bits(count) if ty.text == "bool" => format!("(bits.read_bits({})? != 0)", count),
+			Encoding::Bits(count) => format!("(bits.read_bits({})? as {})", count, ty.text),
+			Encoding::Coord => "bits.read_coord()?".to_string(),
+			Encoding::VarInt => match ty.text.as_str() {
+				"i32" => "bits.read_var_i32()?".to_string(),
+				"i64" => "bits.read_var_i64()?".to_string(),
+				"u64" => "bits.read_var_u64()?".to_string(),
+				_ => format!("(bits.read_var_u32()? as {})", ty.text)
+			}
+		}
+	}
+}
+
+fn encode_impl(parsed: &Struct) -> String {
+	let mut body = String::new();
+
+	for field in &parsed.fields {
+		let mut statement = match field.encoding {
+			Encoding::Default => "::bitstream::BitEncode::encode(value, bits, protocol);".to_string(),
+			_ => encode_value(&field.ty, field.encoding)
+		};
+
+		if let Some(since) = field.since {
+			statement = format!("if protocol >= {} {{ {} }}", since, statement);
+		}
+
+		body += &format!("{{ let value = &self.{}; {} }}\n", field.name, statement);
+	}
+
+	format!(
+		"{} {{
+			#[allow(unused_variables)]
+			fn encode(&self, bits: &mut ::bitstream::BitWriter, protocol: u32) {{
+				{}
+			}}
+		}}",
+		impl_header(parsed, "BitEncode"), body
+	)
+}
+
+/// Generates statements writing `value`, which is a reference to a value of type `ty`.
+fn encode_value(ty: &Type, encoding: Encoding) -> String {
+	match ty.shape {
+		Shape::Option(ref inner) => format!(
+			"match value {{
+				::core::option::Option::Some(value) => {{ bits.write_bit(true); {} }},
+				::core::option::Option::None => bits.write_bit(false)
+			}}",
+			encode_value(inner, encoding)
+		),
+		Shape::Tuple(ref items) => {
+			let names: Vec<String> = (0..items.len()).map(|index| format!("item_{}", index)).collect();
+			let statements: Vec<String> = items.iter().zip(&names).map(|(item, name)| format!("{{ let value = {}; {} }}", name, encode_value(item, encoding))).collect();
+
+			format!("let ({},) = value; {}", names.join(", "), statements.join(" "))
+		},
+		Shape::Leaf => match encoding {
+			Encoding::Default => "::bitstream::BitEncode::encode(value, bits, protocol);".to_string(),
+			Encoding::Bits(count) => format!("bits.write_bits(*value as u32, {});", count),
+			Encoding::Coord => "bits.write_coord(*value);".to_string(),
+			Encoding::VarInt => match ty.text.as_str() {
+				"i32" => "bits.write_var_i32(*value);".to_string(),
+				"i64" => "bits.write_var_i64(*value);".to_string(),
+				"u64" => "bits.write_var_u64(*value);".to_string(),
+				_ => "bits.write_var_u32(*value as u32);".to_string()
+			}
+		}
+	}
+}
+
+// -- Parsing --
+
+struct Struct {
+	name: String,
+	generics: Generics,
+	kind: Kind,
+	fields: Vec<Field>
+}
+
+#[derive(Default)]
+struct Generics {
+	/// Parameters as declared, without defaults, such as `'a` or `T: Clone`.
+	params: Vec<String>,
+	/// Parameters as used in the type of the struct, such as `'a` or `T`.
+	arguments: Vec<String>,
+	/// Names of the type parameters.
+	types: Vec<String>,
+	/// Predicates of the `where` clause.
+	where_clause: Vec<String>
+}
+
+enum Kind {
+	Named,
+	Unnamed,
+	Unit
+}
+
+struct Field {
+	/// Field name, or the index of the field within a tuple struct.
+	name: String,
+	ty: Type,
+	encoding: Encoding,
+	since: Option<u32>
+}
+
+#[derive(Copy, Clone)]
+enum Encoding {
+	Default,
+	Bits(u8),
+	Coord,
+	VarInt
+}
+
+struct Type {
+	text: String,
+	shape: Shape
+}
+
+enum Shape {
+	Option(Box<Type>),
+	Tuple(Vec<Type>),
+	Leaf
+}
+
+fn parse_struct(input: TokenStream) -> Result<Struct, String> {
+	let mut tokens = input.into_iter();
+
+	// Skip attributes and visibility until the item keyword
+	let keyword = loop {
+		match tokens.next() {
+			Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => break "struct",
+			Some(TokenTree::Ident(ident)) if ident.to_string() == "enum" || ident.to_string() == "union" => break "other",
+			Some(_) => (),
+			None => return Err("expected a struct".to_string())
+		}
+	};
+
+	if keyword != "struct" {
+		return Err("BitDecode and BitEncode can only be derived for structs".to_string());
+	}
+
+	let name = match tokens.next() {
+		Some(TokenTree::Ident(ident)) => ident.to_string(),
+		_ => return Err("expected the name of the struct".to_string())
+	};
+
+	let rest: Vec<TokenTree> = tokens.collect();
+	let mut position = 0;
+	let mut generics = Generics::default();
+
+	if let Some(TokenTree::Punct(punct)) = rest.first() {
+		if punct.as_char() == '<' {
+			let end = closing_angle(&rest).ok_or("expected the end of the generic parameters")?;
+
+			parse_generics(&rest[1..end], &mut generics);
+			position = end + 1;
+		}
+	}
+
+	// A where clause comes after the fields of tuple structs, and before those of other structs
+	let (kind, fields, clause) = match (rest.get(position), rest.last()) {
+		(Some(TokenTree::Group(group)), _) if group.delimiter() == Delimiter::Parenthesis => {
+			(Kind::Unnamed, parse_fields(group.stream(), false)?, &rest[position + 1..])
+		},
+		(_, Some(TokenTree::Group(group))) if group.delimiter() == Delimiter::Brace => {
+			(Kind::Named, parse_fields(group.stream(), true)?, &rest[position..rest.len() - 1])
+		},
+		(_, Some(TokenTree::Punct(punct))) if punct.as_char() == ';' => (Kind::Unit, Vec::new(), &rest[position..]),
+		_ => return Err("expected the fields of the struct".to_string())
+	};
+
+	if let Some(TokenTree::Ident(ident)) = clause.first() {
+		if ident.to_string() == "where" {
+			let predicates = match clause.last() {
+				Some(TokenTree::Punct(punct)) if punct.as_char() == ';' => &clause[1..clause.len() - 1],
+				_ => &clause[1..]
+			};
+
+			generics.where_clause.extend(split_commas(predicates).into_iter().map(tokens_to_string));
+		}
+	}
+
+	Ok(Struct { name, generics, kind, fields })
+}
+
+/// Finds the `>` that closes the `<` at the start of `tokens`.
+fn closing_angle(tokens: &[TokenTree]) -> Option<usize> {
+	let mut depth = 0usize;
+
+	for (index, token) in tokens.iter().enumerate() {
+		if let TokenTree::Punct(punct) = token {
+			match punct.as_char() {
+				'<' => depth += 1,
+				'>' if index > 0 && is_joint_minus(&tokens[index - 1]) => (),
+				'>' => {
+					depth -= 1;
+
+					if depth == 0 {
+						return Some(index);
+					}
+				},
+				_ => ()
+			}
+		}
+	}
+
+	None
+}
+
+fn parse_generics(tokens: &[TokenTree], generics: &mut Generics) {
+	for param in split_commas(tokens) {
+		// Defaults are only allowed on the declaration of the struct, not on impls
+		let declaration = match param.iter().position(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == '=')) {
+			Some(index) => &param[..index],
+			None => param
+		};
+
+		let argument = match declaration {
+			[TokenTree::Punct(quote), TokenTree::Ident(lifetime), ..] if quote.as_char() == '\'' => format!("'{}", lifetime),
+			[TokenTree::Ident(keyword), TokenTree::Ident(name), ..] if keyword.to_string() == "const" => name.to_string(),
+			[TokenTree::Ident(name), ..] => {
+				generics.types.push(name.to_string());
+
+				name.to_string()
+			},
+			_ => continue
+		};
+
+		generics.params.push(tokens_to_string(declaration));
+		generics.arguments.push(argument);
+	}
+}
+
+fn tokens_to_string(tokens: &[TokenTree]) -> String {
+	tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn parse_fields(stream: TokenStream, named: bool) -> Result<Vec<Field>, String> {
+	let tokens: Vec<TokenTree> = stream.into_iter().collect();
+	let mut fields = Vec::new();
+
+	for (index, field) in split_commas(&tokens).into_iter().enumerate() {
+		let mut encoding = Encoding::Default;
+		let mut since = None;
+		let mut position = 0;
+
+		// Attributes: `#` followed by a bracketed group
+		while let Some(TokenTree::Punct(punct)) = field.get(position) {
+			if punct.as_char() != '#' {
+				break;
+			}
+
+			if let Some(TokenTree::Group(group)) = field.get(position + 1) {
+				parse_attribute(group.stream(), &mut encoding, &mut since)?;
+			}
+
+			position += 2;
+		}
+
+		// Visibility: `pub`, optionally followed by a restriction such as `(crate)`
+		if let Some(TokenTree::Ident(ident)) = field.get(position) {
+			if ident.to_string() == "pub" {
+				position += 1;
+
+				if let Some(TokenTree::Group(group)) = field.get(position) {
+					if group.delimiter() == Delimiter::Parenthesis {
+						position += 1;
+					}
+				}
+			}
+		}
+
+		let name = if named {
+			let name = match field.get(position) {
+				Some(TokenTree::Ident(ident)) => ident.to_string(),
+				_ => return Err("expected a field name".to_string())
+			};
+
+			position += 2;
+			name
+		} else {
+			index.to_string()
+		};
+
+		let ty = &field[position.min(field.len())..];
+
+		if ty.is_empty() {
+			return Err(format!("expected a type for field {}", name));
+		}
+
+		fields.push(Field { name, ty: parse_type(ty), encoding, since });
+	}
+
+	Ok(fields)
+}
+
+fn parse_attribute(stream: TokenStream, encoding: &mut Encoding, since: &mut Option<u32>) -> Result<(), String> {
+	let tokens: Vec<TokenTree> = stream.into_iter().collect();
+
+	let name = match tokens.first() {
+		Some(TokenTree::Ident(ident)) => ident.to_string(),
+		_ => return Ok(())
+	};
+
+	let arguments: Vec<TokenTree> = match tokens.get(1) {
+		Some(TokenTree::Group(group)) => group.stream().into_iter().collect(),
+		_ => Vec::new()
+	};
+
+	match name.as_str() {
+		"bits" => {
+			let count = match arguments.as_slice() {
+				[TokenTree::Literal(literal)] => literal.to_string().parse::<u8>().ok().filter(|&count| count <= 32),
+				_ => None
+			};
+
+			*encoding = Encoding::Bits(count.ok_or("expected #[bits(N)], where N is a bit count from 0 to 32")?);
+		},
+		"coord" => *encoding = Encoding::Coord,
+		"varint" => *encoding = Encoding::VarInt,
+		"since" => {
+			let version = match arguments.as_slice() {
+				[TokenTree::Ident(key), TokenTree::Punct(equals), TokenTree::Literal(literal)] if key.to_string() == "protocol" && equals.as_char() == '=' => {
+					literal.to_string().parse::<u32>().ok()
+				},
+				_ => None
+			};
+
+			*since = Some(version.ok_or("expected #[since(protocol = N)]")?);
+		},
+		// Doc comments and attributes meant for other macros
+		_ => ()
+	}
+
+	Ok(())
+}
+
+fn parse_type(tokens: &[TokenTree]) -> Type {
+	let text = tokens_to_string(tokens);
+
+	let shape = match tokens {
+		[TokenTree::Group(group)] if group.delimiter() == Delimiter::Parenthesis => {
+			let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+			let items = split_commas(&inner);
+
+			let trailing_comma = matches!(inner.last(), Some(TokenTree::Punct(punct)) if punct.as_char() == ',');
+
+			if items.len() == 1 && !trailing_comma {
+				// Parenthesized type, not a tuple
+				return parse_type(items[0]);
+			}
+
+			Shape::Tuple(items.into_iter().map(parse_type).collect())
+		},
+		[TokenTree::Ident(ident), TokenTree::Punct(open), inner @ .., TokenTree::Punct(close)]
+			if ident.to_string() == "Option" && open.as_char() == '<' && close.as_char() == '>' => {
+
+			Shape::Option(Box::new(parse_type(inner)))
+		},
+		_ => Shape::Leaf
+	};
+
+	Type { text, shape }
+}
+
+/// Splits a token list at commas that are not nested within generic arguments. Empty entries,
+/// such as the one after a trailing comma, are skipped.
+fn split_commas(tokens: &[TokenTree]) -> Vec<&[TokenTree]> {
+	let mut parts = Vec::new();
+	let mut depth = 0usize;
+	let mut start = 0;
+
+	for (index, token) in tokens.iter().enumerate() {
+		if let TokenTree::Punct(punct) = token {
+			match punct.as_char() {
+				'<' => depth += 1,
+				// Skip the `>` of `->`, which does not close a generic argument list
+				'>' if index > 0 && is_joint_minus(&tokens[index - 1]) => (),
+				'>' => depth = depth.saturating_sub(1),
+				',' if depth == 0 => {
+					parts.push(&tokens[start..index]);
+					start = index + 1;
+				},
+				_ => ()
+			}
+		}
+	}
+
+	parts.push(&tokens[start..]);
+	parts.retain(|part| !part.is_empty());
+
+	parts
+}
+
+fn is_joint_minus(token: &TokenTree) -> bool {
+	matches!(token, TokenTree::Punct(punct) if punct.as_char() == '-' && punct.spacing() == Spacing::Joint)
+}
@@ -0,0 +1,103 @@
+//! Measures BitReader throughput on synthetic payloads shaped like the ones that dominate demo
+//! parsing: string table updates (short strings and small byte arrays) and voice data (large byte
+//! arrays, usually not byte-aligned).
+//!
+//! Run with `cargo run --release --example throughput`.
+
+use bitstream::{BitRead, BitWriter, BitReader};
+use std::time::{Duration, Instant};
+
+const ROWS: usize = 200_000;
+const VOICE_FRAMES: usize = 20_000;
+const VOICE_FRAME_BYTES: usize = 1024;
+
+fn string_table() -> Vec<u8> {
+	let mut writer = BitWriter::new();
+
+	for index in 0..ROWS {
+		writer.write_bit(index % 3 == 0);
+		writer.write_bits(index as u32, 5);
+		writer.write_string("models/player/items/scout/hat");
+		writer.write_bits(8, 14);
+		writer.write_u8_array(&[index as u8; 8]);
+	}
+
+	writer.finish().raw_bytes().to_vec()
+}
+
+fn read_string_table(data: &[u8]) -> usize {
+	let mut bits = BitReader::new(data);
+	let mut extra = Vec::new();
+	let mut total = 0;
+
+	for _ in 0..ROWS {
+		bits.read_bit().unwrap();
+		bits.read_bits(5).unwrap();
+		total += bits.read_string().unwrap().len();
+
+		let len = bits.read_bits(14).unwrap() as usize;
+
+		extra.clear();
+		bits.read_u8_array_into(&mut extra, len).unwrap();
+		total += extra.len();
+	}
+
+	total
+}
+
+fn voice(prefix_bits: u8) -> Vec<u8> {
+	let mut writer = BitWriter::new();
+	let frame: Vec<u8> = (0..VOICE_FRAME_BYTES).map(|index| index as u8).collect();
+
+	for _ in 0..VOICE_FRAMES {
+		writer.write_bits(0, prefix_bits);
+		writer.write_u8_array(&frame);
+	}
+
+	writer.finish().raw_bytes().to_vec()
+}
+
+fn read_voice(data: &[u8], prefix_bits: u8) -> usize {
+	let mut bits = BitReader::new(data);
+	let mut frame = Vec::with_capacity(VOICE_FRAME_BYTES);
+	let mut total = 0;
+
+	for _ in 0..VOICE_FRAMES {
+		bits.read_bits(prefix_bits).unwrap();
+
+		frame.clear();
+		bits.read_u8_array_into(&mut frame, VOICE_FRAME_BYTES).unwrap();
+		total += frame.len();
+	}
+
+	total
+}
+
+fn measure<F>(name: &str, bytes: usize, mut run: F) where F: FnMut() -> usize {
+	const ITERATIONS: u32 = 10;
+
+	// Warm up caches before timing
+	let mut checksum = run();
+	let mut best = Duration::MAX;
+
+	for _ in 0..ITERATIONS {
+		let start = Instant::now();
+		checksum = checksum.wrapping_add(run());
+		best = best.min(start.elapsed());
+	}
+
+	let throughput = (bytes as f64) / best.as_secs_f64() / (1024.0 * 1024.0);
+
+	println!("{:>16}: {:>8.2} ms, {:>9.1} MiB/s (checksum {})", name, best.as_secs_f64() * 1000.0, throughput, checksum);
+}
+
+fn main() {
+	let table = string_table();
+	measure("string table", table.len(), || read_string_table(&table));
+
+	let aligned = voice(8);
+	measure("voice, aligned", aligned.len(), || read_voice(&aligned, 8));
+
+	let unaligned = voice(3);
+	measure("voice, unaligned", unaligned.len(), || read_voice(&unaligned, 3));
+}
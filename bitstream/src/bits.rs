@@ -1,19 +1,127 @@
-use std::string::FromUtf8Error;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{FromUtf8Error, String};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::error::Error;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::marker::PhantomData;
+use crate::{BitOrder, BitRead, LsbFirst};
+#[cfg(feature = "trace")]
+use crate::Trace;
 
 /// Error generated when less bits are available than needed for the given operation
 #[derive(Debug)]
 pub struct InsufficientBits {
-	// PERF: this is 16 bytes on most platforms, which hurts the happy path way more than it
+	// PERF: this is 24 bytes on most platforms, which hurts the happy path way more than it
 	// should, since we should never actually encounter this error on regular demo files.
+	/// Bit offset where the failed read started, relative to the outermost buffer that was
+	/// being read (the start of the stream for `StreamBitReader`).
+	pub offset: usize,
 	pub requested: usize,
 	pub available: usize
 }
 
+impl Display for InsufficientBits {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "tried to read {} bits at bit offset {}, but only {} bits were available", self.requested, self.offset, self.available)
+	}
+}
+
+impl Error for InsufficientBits {}
+
+impl InsufficientBits {
+	/// Turns the error of a read that was part of a larger read of `requested` bits, after `read`
+	/// bits of it had already been read, into the error of the larger read.
+	pub(crate) fn within(self, read: usize, requested: usize) -> Self {
+		InsufficientBits {
+			offset: self.offset - read,
+			requested,
+			available: self.available + read
+		}
+	}
+}
+
 /// An error generated while reading a string from a bit stream
 #[derive(Debug)]
 pub enum BitParseError {
 	InsufficientBits(InsufficientBits),
-	Utf8(FromUtf8Error)
+	Utf8(FromUtf8Error),
+	/// The bits were read, but do not form a valid value, such as a message that does not exist
+	/// in the protocol being parsed.
+	Invalid(&'static str),
+	/// Error that occurred while parsing the named field or structure.
+	Context {
+		name: &'static str,
+		source: Box<BitParseError>
+	}
+}
+
+impl BitParseError {
+	/// Records that the error occurred while parsing the named field or structure. Parsers call
+	/// this on the way out, so the outermost name is pushed last.
+	pub fn context(self, name: &'static str) -> Self {
+		BitParseError::Context { name, source: Box::new(self) }
+	}
+
+	/// Names of the fields and structures that were being parsed, from outermost to innermost.
+	pub fn path(&self) -> Vec<&'static str> {
+		let mut path = Vec::new();
+		let mut current = self;
+
+		while let BitParseError::Context { name, ref source } = *current {
+			path.push(name);
+			current = source;
+		}
+
+		path
+	}
+
+	/// The underlying error, without any context.
+	pub fn root(&self) -> &BitParseError {
+		match *self {
+			BitParseError::Context { ref source, .. } => source.root(),
+			ref root => root
+		}
+	}
+}
+
+impl Display for BitParseError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let path = self.path();
+
+		if !path.is_empty() {
+			write!(f, "{}: ", path.join("."))?;
+		}
+
+		match *self.root() {
+			BitParseError::InsufficientBits(ref err) => write!(f, "{}", err),
+			BitParseError::Utf8(ref err) => write!(f, "invalid UTF-8 in string: {}", err),
+			BitParseError::Invalid(reason) => write!(f, "{}", reason),
+			BitParseError::Context { .. } => unreachable!()
+		}
+	}
+}
+
+impl Error for BitParseError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self.root() {
+			BitParseError::InsufficientBits(ref err) => Some(err),
+			BitParseError::Utf8(ref err) => Some(err),
+			BitParseError::Invalid(_) | BitParseError::Context { .. } => None
+		}
+	}
+}
+
+/// Extension for attaching context to the errors of parsers.
+pub trait ParseContext<T> {
+	fn context(self, name: &'static str) -> Result<T, BitParseError>;
+}
+
+impl<T, E> ParseContext<T> for Result<T, E> where E: Into<BitParseError> {
+	fn context(self, name: &'static str) -> Result<T, BitParseError> {
+		self.map_err(|err| err.into().context(name))
+	}
 }
 
 impl From<InsufficientBits> for BitParseError {
@@ -22,32 +130,108 @@ impl From<InsufficientBits> for BitParseError {
 	}
 }
 
-pub struct BitReader<'i> {
+impl From<FromUtf8Error> for BitParseError {
+	fn from(err: FromUtf8Error) -> Self {
+		Self::Utf8(err)
+	}
+}
+
+pub struct BitReader<'i, O = LsbFirst> {
+	/// The complete input, kept around so that slices of it can be handed out.
+	data: &'i [u8],
 	input: &'i [u8],
-	bits: u32,
-	/// Available bits. Must always be above 0.
-	available: u8
+	/// Buffered bits, loaded from `input` up to 8 bytes at a time. Only the `available` bits at
+	/// the end that `O` reads from are meaningful.
+	bits: u64,
+	/// Available bits in the buffer. This can be 0 even when there is more input, in which case
+	/// the buffer is refilled on the next read.
+	available: u8,
+	/// Amount of bits at the end of the last byte of the input that are not part of the stream.
+	unused_tail: u8,
+	/// Position of the first bit of the stream within `data`.
+	start: u8,
+	/// Position of the first bit of `data` within the outermost buffer, for error reporting.
+	origin: usize,
+	/// Log of the reads made so far, if tracing is enabled.
+	#[cfg(feature = "trace")]
+	trace: Option<Box<Trace>>,
+	order: PhantomData<O>
 }
 
+/// A saved reader position, created by `BitRead::checkpoint`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Checkpoint(pub(crate) usize);
+
 impl<'i> BitReader<'i> {
 	pub fn new(input: &'i [u8]) -> Self {
+		Self::new_in(input, LsbFirst)
+	}
+}
+
+impl<'i, O> BitReader<'i, O> where O: BitOrder {
+	/// Creates a reader that reads the bits of each byte in the given order.
+	pub fn new_in(input: &'i [u8], _order: O) -> Self {
 		let mut reader = BitReader {
+			data: input,
 			input,
 			bits: 0,
-			available: 0
+			available: 0,
+			unused_tail: 0,
+			start: 0,
+			origin: 0,
+			#[cfg(feature = "trace")]
+			trace: None,
+			order: PhantomData
 		};
 
-		reader.fetch();
+		reader.refill();
 
 		reader
 	}
 
+	/// Creates a reader over the `len` bits of `data` starting at the bit `offset`, which is at
+	/// the bit `origin` of the outermost buffer.
+	fn with_bounds(data: &'i [u8], offset: u8, len: usize, origin: usize) -> Self {
+		let end = offset as usize + len;
+		let data = &data[..end.div_ceil(8)];
+
+		let mut reader = BitReader {
+			data,
+			input: data,
+			bits: 0,
+			available: 0,
+			unused_tail: ((8 - end % 8) % 8) as u8,
+			start: offset,
+			origin: origin - (offset as usize),
+			#[cfg(feature = "trace")]
+			trace: None,
+			order: PhantomData
+		};
+
+		reader.seek_to(offset as usize);
+
+		reader
+	}
+
+	/// Creates a reader over all of `data`, at the bit `position`, where `data` starts at the bit
+	/// `origin` of the outermost buffer.
+	#[cfg(feature = "std")]
+	pub(crate) fn resume(data: &'i [u8], position: usize, origin: usize) -> Self {
+		let mut reader = Self::with_bounds(data, 0, data.len() * 8, origin);
+		reader.seek_to(position);
+
+		reader
+	}
+
+	/// Amount of whole bytes of input that no bit has been read from yet. This only depends on
+	/// the position of the reader, not on how much of the input has been buffered, so it is 0 once
+	/// a parser has reached the last byte.
 	pub fn unread_bytes(&self) -> usize {
-		self.input.len()
+		self.data.len() - self.data_position().div_ceil(8)
 	}
 
 	pub fn has_remaining(&self, needed: usize) -> bool {
-		if self.unread_bytes() >= usize::max_value() / 8 {
+		if self.unread_bytes() >= usize::MAX / 8 {
 			true
 		} else {
 			needed <= self.remaining_bits()
@@ -55,286 +239,352 @@ impl<'i> BitReader<'i> {
 	}
 
 	pub fn has_remaining_bytes(&self, needed: usize) -> bool {
-		self.unread_bytes().saturating_add((self.available / 8) as usize) >= needed
+		self.remaining_bytes() >= needed
 	}
 
 	pub fn remaining_bits(&self) -> usize {
-		self.unread_bytes() * 8 + (self.available as usize)
+		self.data.len() * 8 - (self.unused_tail as usize) - self.data_position()
 	}
 
 	pub fn remaining_bytes(&self) -> usize {
-		self.unread_bytes() + ((self.available / 8) as usize)
+		self.remaining_bits() / 8
 	}
 
 	pub fn available_now(&self) -> u8 {
 		self.available
 	}
 
-	fn fetch(&mut self) {
-		if self.available > 0 {
-			return;
-		}
+	/// Returns the amount of bits that have been consumed so far.
+	pub fn position(&self) -> usize {
+		self.data_position() - (self.start as usize)
+	}
 
-		match self.input.len() {
-			0 => (),
-			1 => {
-				self.bits = self.input[0] as u32;
-				self.input = &[];
-
-				self.available = 8;
-			},
-			2 => {
-				self.bits = (self.input[0] as u32) | ((self.input[1] as u32) << 8);
-				self.input = &[];
-
-				self.available = 16;
-			},
-			3 => {
-				self.bits = (self.input[0] as u32) | ((self.input[1] as u32) << 8) | ((self.input[2] as u32) << 16);
-				self.input = &[];
-
-				self.available = 24;
-			},
-			_ => {
-				self.bits = (self.input[0] as u32) | ((self.input[1] as u32) << 8) | ((self.input[2] as u32) << 16) | ((self.input[3] as u32) << 24);
-				self.input = &self.input[4..];
-
-				self.available = 32;
-			}
+	/// Returns the position of the next bit within the outermost buffer. This differs from
+	/// `position` for readers over nested `BitSlice`s, which count from the start of the slice.
+	pub fn absolute_position(&self) -> usize {
+		self.origin + self.data_position()
+	}
+
+	fn insufficient(&self, requested: usize) -> InsufficientBits {
+		InsufficientBits {
+			offset: self.absolute_position(),
+			requested,
+			available: self.remaining_bits()
 		}
 	}
 
-	pub fn read_bit(&mut self) -> Result<bool, InsufficientBits> {
-		if self.available < 1 {
-			return Err(InsufficientBits {
-				requested: 1,
-				available: self.available as usize
-			});
+	/// Starts recording every read into a `Trace`, discarding any previous trace. This is only
+	/// available with the `trace` feature, and without it, tracing costs nothing.
+	#[cfg(feature = "trace")]
+	pub fn enable_trace(&mut self) {
+		self.trace = Some(Box::default());
+	}
+
+	#[cfg(feature = "trace")]
+	pub fn trace(&self) -> Option<&Trace> {
+		self.trace.as_deref()
+	}
+
+	/// Stops tracing, returning the reads recorded so far.
+	#[cfg(feature = "trace")]
+	pub fn take_trace(&mut self) -> Option<Trace> {
+		self.trace.take().map(|trace| *trace)
+	}
+
+	/// Renders the trace as an annotated dump of the whole input of this reader, see
+	/// `Trace::render`. Returns None if tracing is not enabled.
+	#[cfg(feature = "trace")]
+	pub fn annotate(&self) -> Option<String> {
+		let len = self.data.len() * 8 - (self.unused_tail as usize) - (self.start as usize);
+		let input = BitSlice::from_parts_in(self.data, self.start, len, O::default());
+
+		self.trace.as_ref().map(|trace| trace.render(&input))
+	}
+
+	/// Runs the parser with `label` added to the labels of any reads that it makes while tracing.
+	/// Labels nest, so that reads can be attributed to fields such as `ServerInfo.hostname`.
+	#[cfg(feature = "trace")]
+	pub fn with_label<T, F>(&mut self, label: &'static str, parse: F) -> T where F: FnOnce(&mut Self) -> T {
+		if let Some(ref mut trace) = self.trace {
+			trace.labels.push(label);
 		}
 
-		let bit = (self.bits & 1) == 1;
+		let result = parse(self);
 
-		self.available -= 1;
-		self.bits >>= 1;
+		if let Some(ref mut trace) = self.trace {
+			trace.labels.pop();
+		}
 
-		self.fetch();
+		result
+	}
 
-		Ok(bit)
+	#[cfg(not(feature = "trace"))]
+	#[inline(always)]
+	pub fn with_label<T, F>(&mut self, _label: &'static str, parse: F) -> T where F: FnOnce(&mut Self) -> T {
+		parse(self)
 	}
 
-	fn read_bits_direct(&mut self, count: u8) -> u32 {
-		assert!(count > 0);
-		assert!(count <= self.available);
+	/// Position of the next bit to be read, counted from the start of `data`.
+	fn data_position(&self) -> usize {
+		let loaded = self.data.len() - self.input.len();
 
-		let bits = if count == 32 {
-			let bits = self.bits;
-			self.bits = 0;
-			self.available = 0;
+		// Once the last chunk has been loaded, the unused tail is no longer counted as available.
+		let tail = if self.input.is_empty() && loaded > 0 { self.unused_tail } else { 0 };
 
-			bits
-		} else {
-			let bits = self.bits & ((1 << count) - 1);
-			self.bits >>= count;
-			self.available -= count;
+		loaded * 8 - (tail as usize) - (self.available as usize)
+	}
 
-			bits
-		};
+	/// Moves the reader to an arbitrary bit position within `data`.
+	fn seek_to(&mut self, position: usize) {
+		self.input = &self.data[position / 8..];
+		self.bits = 0;
+		self.available = 0;
 
-		self.fetch();
+		self.refill();
 
-		bits
+		let skip = (position % 8) as u8;
+
+		if skip != 0 {
+			self.consume(skip);
+		}
 	}
 
-	pub fn read_bits(&mut self, count: u8) -> Result<u32, InsufficientBits> {
+	/// Loads as many whole bytes from the input as will fit into the buffer.
+	fn refill(&mut self) {
+		let room = ((64 - self.available) / 8) as usize;
+		let count = room.min(self.input.len());
+
 		if count == 0 {
-			return Ok(0);
+			return;
 		}
 
-		assert!(count <= 32, "cannot read more than 32 bits from a BitReader at a time.");
-
-		if count <= self.available {
-			Ok(self.read_bits_direct(count))
+		let bytes = if self.input.len() >= 8 {
+			// Infallible: the slice is exactly 8 bytes long
+			self.input[..8].try_into().unwrap()
 		} else {
-			let taken = self.available;
-			let needed = count - taken;
-
-			if self.unread_bytes() < 4 && (self.unread_bytes() as u8) * 8 < needed {
-				return Err(InsufficientBits {
-					requested: count as usize,
-					available: taken as usize + self.unread_bytes() * 8
-				});
-			}
+			let mut bytes = [0u8; 8];
+			bytes[..self.input.len()].copy_from_slice(self.input);
+
+			bytes
+		};
 
-			let parts = (
-				self.read_bits_direct(taken ),
-				self.read_bits_direct(needed)
-			);
+		self.bits = O::fill(self.bits, self.available, bytes, count);
+		self.available += (count * 8) as u8;
+		self.input = &self.input[count..];
 
-			Ok(parts.0 | (parts.1 << taken))
+		if self.input.is_empty() {
+			self.available -= self.unused_tail;
 		}
 	}
 
-	pub fn read_u8(&mut self) -> Result<u8, InsufficientBits> {
-		self.read_bits(8).map(|x| x as u8)
-	}
+	/// Takes `count` bits from the buffer. The caller must ensure that enough bits are available.
+	fn consume(&mut self, count: u8) -> u64 {
+		debug_assert!(count < 64);
+		debug_assert!(count <= self.available);
 
-	pub fn read_u8_array(&mut self, len: usize) -> Result<Vec<u8>, InsufficientBits> {
-		let mut data = Vec::with_capacity(len);
+		debug_assert!(count > 0);
 
-		self.read_u8_array_into(&mut data, len)?;
+		let bits = O::take(&mut self.bits, count);
+		self.available -= count;
 
-		Ok(data)
+		bits
 	}
 
-	pub fn read_u8_array_into(&mut self, data: &mut Vec<u8>, len: usize) -> Result<(), InsufficientBits> {
-		if !self.has_remaining_bytes(len) {
-			return Err(InsufficientBits {
-				requested: len,
-				available: self.remaining_bytes()
-			});
-		}
+	/// Makes sure that at least `count` bits are in the buffer, if possible.
+	fn ensure(&mut self, count: u8) -> Result<(), InsufficientBits> {
+		if self.available < count {
+			self.refill();
 
-		for _ in 0..len {
-			data.push(self.read_u8()?);
+			if self.available < count {
+				return Err(self.insufficient(count as usize));
+			}
 		}
 
 		Ok(())
 	}
 
-	pub fn read_u16(&mut self) -> Result<u16, InsufficientBits> {
-		self.read_bits(16).map(|x| x as u16)
-	}
+	fn copy_u8_array_into(&mut self, data: &mut Vec<u8>, len: usize) -> Result<(), InsufficientBits> {
+		if !self.has_remaining_bytes(len) {
+			return Err(self.insufficient(len * 8));
+		}
 
-	pub fn read_u32(&mut self) -> Result<u32, InsufficientBits> {
-		self.read_bits(32).map(|x| x as u32)
-	}
+		let position = self.data_position();
+		let source = &self.data[position / 8..];
+		let shift = (position % 8) as u32;
 
-	pub fn read_f32(&mut self) -> Result<f32, InsufficientBits> {
-		self.read_u32().map(f32::from_bits)
-	}
+		if shift == 0 {
+			data.extend_from_slice(&source[..len]);
+		} else {
+			data.reserve(len);
 
-	pub fn read_i8(&mut self) -> Result<i8, InsufficientBits> {
-		self.read_u8().map(|x| x as i8)
-	}
+			// Each output byte straddles two input bytes. Since the bits extend into the byte
+			// after the last full byte, source[len] always exists, so the lookahead is safe.
+			let mut index = 0;
+
+			while index + 8 <= len {
+				// Infallible: the slice is exactly 8 bytes long
+				let bytes = source[index..index + 8].try_into().unwrap();
+
+				data.extend_from_slice(&O::realign(bytes, source[index + 8], shift));
+				index += 8;
+			}
+
+			while index < len {
+				data.push(O::realign_byte(source[index], source[index + 1], shift));
+				index += 1;
+			}
+		}
 
-	pub fn read_i16(&mut self) -> Result<i16, InsufficientBits> {
-		self.read_u16().map(|x| x as i16)
+		self.seek_to(position + len * 8);
+
+		Ok(())
 	}
 
-	pub fn read_i32(&mut self) -> Result<i32, InsufficientBits> {
-		self.read_u32().map(|x| x as i32)
+	/// Returns a slice referencing the next `count` bits of the input without copying them, and
+	/// advances the reader past those bits.
+	pub fn read_slice(&mut self, count: usize) -> Result<BitSlice<'i, O>, InsufficientBits> {
+		self.traced_with("slice", |bits| {
+			if !bits.has_remaining(count) {
+				return Err(bits.insufficient(count));
+			}
+
+			let position = bits.data_position();
+			let mut slice = BitSlice::from_parts_in(&bits.data[position / 8..], (position % 8) as u8, count, O::default());
+			slice.origin = bits.origin + position;
+
+			bits.seek_to(position + count);
+
+			Ok(slice)
+		}, |slice| format!("<{} bits>", slice.bits_len()))
 	}
 
-	pub fn read_var(&mut self) -> Result<u32, InsufficientBits> {
-		match self.read_bits(2)? {
-			0 => self.read_bits(4),
-			1 => self.read_u8().map(u32::from),
-			2 => self.read_bits(12),
-			_ => self.read_u32()
-		}
+	pub fn end(self) -> (&'i [u8], u8) {
+		(self.input, self.available)
 	}
+}
 
-	pub fn read_coord(&mut self) -> Result<f32, InsufficientBits> {
-		// TODO: This is not pure, parse errors are not recoverable.
+impl<'i, O> BitRead for BitReader<'i, O> where O: BitOrder {
+	type Order = O;
 
-		let integral = self.read_bit()?;
-		let fractional = self.read_bit()?;
+	fn read_bits(&mut self, count: u8) -> Result<u32, InsufficientBits> {
+		self.traced("bits", |bits| {
+			if count == 0 {
+				return Ok(0);
+			}
 
-		if integral || fractional {
-			let sign = self.read_bit()?;
+			assert!(count <= 32, "cannot read more than 32 bits from a BitReader at a time, use read_u64 instead.");
 
-			let integer = if integral {
-				self.read_bits(14)? + 1
-			} else {
-				0
-			};
+			bits.ensure(count)?;
 
-			let fraction = if fractional {
-				self.read_bits(5)?
-			} else {
-				0
-			};
+			Ok(bits.consume(count) as u32)
+		})
+	}
 
-			let value = (integer as f32) + (fraction as f32) * 0.03125;
+	fn read_bit(&mut self) -> Result<bool, InsufficientBits> {
+		self.traced("bit", |bits| {
+			bits.ensure(1)?;
 
-			Ok(if sign { -value } else { value })
-		} else {
-			Ok(0.0)
-		}
+			Ok(bits.consume(1) == 1)
+		})
 	}
 
-	pub fn read_vec3(&mut self) -> Result<(f32, f32, f32), InsufficientBits> {
-		// TODO: This is not pure, parse errors are not recoverable.
+	fn skip_bits(&mut self, count: usize) -> Result<(), InsufficientBits> {
+		self.traced_with("skip", |bits| {
+			if !bits.has_remaining(count) {
+				return Err(bits.insufficient(count));
+			}
 
-		let x = self.read_bit()?;
-		let y = self.read_bit()?;
-		let z = self.read_bit()?;
+			let position = bits.data_position();
+			bits.seek_to(position + count);
 
-		Ok((
-			if x { self.read_coord()? } else { 0.0 },
-			if y { self.read_coord()? } else { 0.0 },
-			if z { self.read_coord()? } else { 0.0 }
-		))
+			Ok(())
+		}, |()| String::new())
 	}
 
-	pub fn read_string(&mut self) -> Result<String, BitParseError> {
-		// TODO: This is not pure, parse errors are not recoverable.
+	fn read_u8_array_into(&mut self, data: &mut Vec<u8>, len: usize) -> Result<(), InsufficientBits> {
+		self.traced_with("u8_array", |bits| bits.copy_u8_array_into(data, len), |()| format!("<{} bytes>", len))
+	}
 
-		let mut data = Vec::new();
+	fn position(&self) -> usize {
+		BitReader::position(self)
+	}
 
-		loop {
-			let value = self.read_u8().map_err(BitParseError::InsufficientBits)?;
+	fn checkpoint(&self) -> Checkpoint {
+		Checkpoint(self.data_position())
+	}
 
-			if value == 0 {
-				break;
-			}
+	fn restore(&mut self, checkpoint: Checkpoint) {
+		assert!(checkpoint.0 <= self.data.len() * 8 - (self.unused_tail as usize), "checkpoint is out of bounds for this reader");
 
-			data.push(value);
+		#[cfg(feature = "trace")]
+		if let Some(ref mut trace) = self.trace {
+			trace.rewind(checkpoint.0 - (self.start as usize));
 		}
 
-		String::from_utf8(data).map_err(BitParseError::Utf8)
+		self.seek_to(checkpoint.0);
 	}
 
-	pub fn read_var_u32(&mut self) -> Result<u32, InsufficientBits> {
-		// TODO: This is not pure, parse errors are not recoverable.
+	#[cfg(feature = "trace")]
+	fn traced_with<T, E, F, D>(&mut self, read: &'static str, parse: F, describe: D) -> Result<T, E>
+		where F: FnOnce(&mut Self) -> Result<T, E>, D: FnOnce(&T) -> String {
 
-		let mut result = 0;
+		match self.trace {
+			Some(ref mut trace) => trace.depth += 1,
+			None => return parse(self)
+		}
 
-		for index in 0..5 {
-			let byte = self.read_u8()?;
+		let offset = BitReader::position(self);
+		let result = parse(self);
+		let len = BitReader::position(self).saturating_sub(offset);
 
-			result |= ((byte & 0x7F) as u32) << (7 * index);
+		if let Some(ref mut trace) = self.trace {
+			trace.depth -= 1;
 
-			if byte < 128 {
-				break;
+			if let (0, Ok(value)) = (trace.depth, &result) {
+				trace.record(offset, len, read, describe(value));
 			}
 		}
 
-		Ok(result)
-	}
-
-	pub fn end(self) -> (&'i [u8], u8) {
-		(self.input, self.available)
+		result
 	}
 }
 
 #[derive(Debug, Clone)]
-pub struct Bits {
+pub struct Bits<O = LsbFirst> {
 	data: Vec<u8>,
-	trailing_bits: u8
+	trailing_bits: u8,
+	order: PhantomData<O>
 }
 
 impl Bits {
 	pub fn from_bytes(data: Vec<u8>) -> Self {
-		Bits { data, trailing_bits: 0 }
+		Bits::from_bytes_in(data, LsbFirst)
+	}
+
+	/// Creates a `Bits` value where only the lowest `trailing_bits` bits of the last byte are used.
+	/// A `trailing_bits` value of zero means that the last byte is completely used.
+	pub fn from_parts(data: Vec<u8>, trailing_bits: u8) -> Self {
+		Bits::from_parts_in(data, trailing_bits, LsbFirst)
+	}
+}
+
+impl<O> Bits<O> where O: BitOrder {
+	pub fn from_bytes_in(data: Vec<u8>, _order: O) -> Self {
+		Bits { data, trailing_bits: 0, order: PhantomData }
 	}
 
-	pub fn copy_into(bits: &mut BitReader, count: usize) -> Result<Self, InsufficientBits> {
+	/// Like `from_parts`, but the used bits of the last byte are the ones that come first in the
+	/// given order, so the highest bits for `MsbFirst`.
+	pub fn from_parts_in(data: Vec<u8>, trailing_bits: u8, _order: O) -> Self {
+		assert!(trailing_bits < 8, "trailing bits must fit within a single byte");
+		assert!(trailing_bits == 0 || !data.is_empty(), "trailing bits require at least one byte of data");
+
+		Bits { data, trailing_bits, order: PhantomData }
+	}
+
+	pub fn copy_into(bits: &mut BitReader<O>, count: usize) -> Result<Self, InsufficientBits> {
 		if !bits.has_remaining(count) {
-			return Err(InsufficientBits {
-				requested: count, 
-				available: bits.remaining_bits()
-			});
+			return Err(bits.insufficient(count));
 		}
 
 		let trailing_bits = (count % 8) as u8;
@@ -344,21 +594,300 @@ impl Bits {
 		bits.read_u8_array_into(&mut data, bytes)?;
 
 		if trailing_bits != 0 {
-			data.push(bits.read_bits(trailing_bits)? as u8);
+			data.push(O::partial_byte(bits.read_bits(trailing_bits)? as u8, trailing_bits));
 		}
 
-		Ok(Bits { data, trailing_bits })
+		Ok(Bits { data, trailing_bits, order: PhantomData })
+	}
+
+	pub fn reader(&self) -> BitReader<'_, O> {
+		self.as_slice().reader()
 	}
 
-	pub fn reader(&self) -> BitReader {
-		BitReader::new(&self.data)
+	pub fn as_slice(&self) -> BitSlice<'_, O> {
+		BitSlice::from_parts_in(&self.data, 0, self.bits_len(), O::default())
 	}
 
 	pub fn bits_len(&self) -> usize {
-		self.data.len() * 8 + (self.trailing_bits as usize)
+		if self.trailing_bits != 0 {
+			(self.data.len() - 1) * 8 + (self.trailing_bits as usize)
+		} else {
+			self.data.len() * 8
+		}
+	}
+
+	pub fn trailing_bits(&self) -> u8 {
+		self.trailing_bits
 	}
 
 	pub fn raw_bytes(&self) -> &[u8] {
 		&self.data
 	}
-}
\ No newline at end of file
+}
+/// A borrowed sequence of bits within a parent buffer. Unlike `Bits`, the sequence does not need
+/// to start or end on a byte boundary, so no copying is needed to create one.
+#[derive(Debug, Copy, Clone)]
+pub struct BitSlice<'i, O = LsbFirst> {
+	/// Bytes starting with the byte that contains the first bit.
+	data: &'i [u8],
+	/// Index of the first bit within the first byte, counted in the order `O`.
+	offset: u8,
+	len: usize,
+	/// Position of the first bit within the outermost buffer, for error reporting.
+	origin: usize,
+	order: PhantomData<O>
+}
+
+impl<'i> BitSlice<'i> {
+	pub fn new(data: &'i [u8]) -> Self {
+		BitSlice::new_in(data, LsbFirst)
+	}
+
+	/// Creates a slice of the `len` bits of `data`, starting at the bit `offset` within the
+	/// first byte.
+	pub fn from_parts(data: &'i [u8], offset: u8, len: usize) -> Self {
+		BitSlice::from_parts_in(data, offset, len, LsbFirst)
+	}
+}
+
+impl<'i, O> BitSlice<'i, O> where O: BitOrder {
+	pub fn new_in(data: &'i [u8], _order: O) -> Self {
+		BitSlice { data, offset: 0, len: data.len() * 8, origin: 0, order: PhantomData }
+	}
+
+	/// Like `from_parts`, with `offset` counted in the given order, so from the highest bit for
+	/// `MsbFirst`.
+	pub fn from_parts_in(data: &'i [u8], offset: u8, len: usize, _order: O) -> Self {
+		assert!(offset < 8, "bit offset must be within the first byte");
+
+		let bytes = (offset as usize + len).div_ceil(8);
+		assert!(bytes <= data.len(), "bit slice extends past the end of the data");
+
+		BitSlice { data: &data[..bytes], offset, len, origin: offset as usize, order: PhantomData }
+	}
+
+	pub fn reader(&self) -> BitReader<'i, O> {
+		BitReader::with_bounds(self.data, self.offset, self.len, self.origin)
+	}
+
+	pub fn bits_len(&self) -> usize {
+		self.len
+	}
+
+	/// Index of the first bit within the first byte of `raw_bytes`.
+	pub fn bit_offset(&self) -> u8 {
+		self.offset
+	}
+
+	/// All bytes that contain at least one bit of this slice. The first and last bytes may contain
+	/// bits that are not part of this slice.
+	pub fn raw_bytes(&self) -> &'i [u8] {
+		self.data
+	}
+
+	/// Copies the bits into an owned, byte-aligned `Bits` value.
+	pub fn to_bits(&self) -> Bits<O> {
+		// Infallible: the reader contains exactly `len` bits.
+		Bits::copy_into(&mut self.reader(), self.len).unwrap()
+	}
+}
+
+/// Storage for a sequence of bits, either owned (`Bits`) or borrowed from a parent buffer
+/// (`BitSlice`). Packet structures are generic over this so that parsing does not need to copy.
+pub trait BitStorage {
+	fn reader(&self) -> BitReader<'_>;
+
+	fn bits_len(&self) -> usize;
+
+	fn to_bits(&self) -> Bits;
+}
+
+impl BitStorage for Bits {
+	fn reader(&self) -> BitReader<'_> {
+		Bits::reader(self)
+	}
+
+	fn bits_len(&self) -> usize {
+		Bits::bits_len(self)
+	}
+
+	fn to_bits(&self) -> Bits {
+		self.clone()
+	}
+}
+
+impl BitStorage for BitSlice<'_> {
+	fn reader(&self) -> BitReader<'_> {
+		BitSlice::reader(self)
+	}
+
+	fn bits_len(&self) -> usize {
+		BitSlice::bits_len(self)
+	}
+
+	fn to_bits(&self) -> Bits {
+		BitSlice::to_bits(self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::BitWriter;
+	use super::*;
+
+	/// Bytes 0, 1, 2, ... as a bit stream.
+	fn counting(len: usize) -> Vec<u8> {
+		(0..len).map(|byte| byte as u8).collect()
+	}
+
+	#[test]
+	fn slices_borrow_unaligned_bits() {
+		let mut writer = BitWriter::new();
+		writer.write_bits(0b101, 3);
+		writer.write_bits(11, 4);
+		writer.write_u16(0xCAFE);
+		writer.write_bit(true);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+
+		let slice = reader.read_slice(20).unwrap();
+
+		assert_eq!(slice.bits_len(), 20);
+		assert_eq!(slice.bit_offset(), 3);
+		assert_eq!(reader.position(), 23);
+		assert!(reader.read_bit().unwrap());
+
+		let mut inner = slice.reader();
+		assert_eq!(inner.read_bits(4).unwrap(), 11);
+		assert_eq!(inner.read_u16().unwrap(), 0xCAFE);
+		assert!(inner.read_bit().is_err());
+
+		let owned = slice.to_bits();
+		assert_eq!(owned.bits_len(), 20);
+		assert_eq!(owned.reader().read_bits(20).unwrap(), slice.reader().read_bits(20).unwrap());
+	}
+
+	#[test]
+	fn nested_slices_report_outer_offsets() {
+		let data = counting(8);
+		let mut outer = BitReader::new(&data);
+
+		outer.skip_bits(5).unwrap();
+
+		let mut middle = outer.read_slice(40).unwrap().reader();
+		middle.skip_bits(9).unwrap();
+
+		let mut inner = middle.read_slice(10).unwrap().reader();
+		inner.skip_bits(10).unwrap();
+
+		let err = inner.read_bit().unwrap_err();
+		assert_eq!(err.offset, 5 + 9 + 10);
+		assert_eq!(err.available, 0);
+	}
+	#[test]
+	fn context_is_listed_from_the_outside_in() {
+		let mut reader = BitReader::new(&[]);
+
+		let err = reader.read_u8().context("inner").context("outer").unwrap_err();
+
+		assert_eq!(err.path(), ["outer", "inner"]);
+		assert!(matches!(err.root(), BitParseError::InsufficientBits(_)));
+		assert_eq!(format!("{}", err), "outer.inner: tried to read 8 bits at bit offset 0, but only 0 bits were available");
+	}
+
+	#[test]
+	fn peek_skip_and_rewind() {
+		let data = [0b1010_0110, 0xFF, 0x12, 0x34];
+		let mut reader = BitReader::new(&data);
+
+		assert_eq!(reader.peek_bits(4).unwrap(), 0b0110);
+		assert_eq!(reader.position(), 0);
+
+		let start = reader.checkpoint();
+
+		assert_eq!(reader.read_bits(4).unwrap(), 0b0110);
+		reader.skip_bits(12).unwrap();
+		assert_eq!(reader.position(), 16);
+		assert_eq!(reader.remaining_bits(), 16);
+		assert_eq!(reader.read_u8().unwrap(), 0x12);
+
+		reader.restore(start);
+		assert_eq!(reader.position(), 0);
+		assert_eq!(reader.read_u8().unwrap(), 0b1010_0110);
+
+		assert!(reader.skip_bits(25).is_err());
+		assert_eq!(reader.position(), 8);
+	}
+
+	#[test]
+	fn failed_parse_is_rewound() {
+		let data = [0x01, 0x02];
+		let mut reader = BitReader::new(&data);
+
+		let result = reader.try_parse(|bits| {
+			bits.read_u8()?;
+			bits.read_u16()
+		});
+
+		assert!(result.is_err());
+		assert_eq!(reader.position(), 0);
+
+		assert_eq!(reader.try_parse(|bits| bits.read_u8()).unwrap(), 0x01);
+		assert_eq!(reader.position(), 8);
+	}
+
+	#[test]
+	fn unread_bytes_follow_the_position() {
+		let data = counting(20);
+		let mut reader = BitReader::new(&data);
+
+		assert_eq!(reader.unread_bytes(), 20);
+
+		reader.read_bit().unwrap();
+		assert_eq!(reader.unread_bytes(), 19);
+
+		reader.skip_bits(7).unwrap();
+		assert_eq!(reader.unread_bytes(), 19);
+
+		reader.skip_bits(8 * 18 + 1).unwrap();
+		assert_eq!(reader.unread_bytes(), 0);
+
+		let mut slice = BitSlice::from_parts(&data, 3, 12).reader();
+		slice.skip_bits(5).unwrap();
+		assert_eq!(slice.unread_bytes(), 1);
+		slice.skip_bits(1).unwrap();
+		assert_eq!(slice.unread_bytes(), 0);
+	}
+
+	#[test]
+	fn reads_across_refills_match_a_naive_reader() {
+		let data = counting(64);
+		let naive = |position: usize, count: usize| (0..count).fold(0u64, |value, index| {
+			let bit = position + index;
+			value | ((((data[bit / 8] >> (bit % 8)) & 1) as u64) << index)
+		});
+
+		let mut reader = BitReader::new(&data);
+		let mut position = 0;
+
+		for count in 1..=20 {
+			assert_eq!(reader.read_bits(count).unwrap() as u64, naive(position, count as usize));
+			position += count as usize;
+		}
+
+		// Byte arrays at an unaligned position take the bulk path for the first 8 bytes
+		let bytes = reader.read_u8_array(10).unwrap();
+
+		for (index, &byte) in bytes.iter().enumerate() {
+			assert_eq!(byte as u64, naive(position + index * 8, 8));
+		}
+
+		position += 80;
+
+		assert_eq!(reader.read_u64().unwrap(), naive(position, 64));
+		assert_eq!(reader.position(), position + 64);
+	}
+}
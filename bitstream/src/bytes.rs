@@ -1,34 +1,153 @@
+use core::error::Error;
+use core::fmt::{self, Display, Formatter};
+
+/// Error generated when less bytes are available than needed for the given operation
+#[derive(Debug)]
+pub struct InsufficientBytes {
+	/// Offset from the start of the input where the failed read started.
+	pub offset: usize,
+	pub requested: usize,
+	pub available: usize
+}
+
+impl Display for InsufficientBytes {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "tried to read {} bytes at offset {}, but only {} bytes were available", self.requested, self.offset, self.available)
+	}
+}
+
+impl Error for InsufficientBytes {}
+
 pub struct ByteReader<'a> {
-	bytes: &'a [u8]
+	bytes: &'a [u8],
+	offset: usize
 }
 
 impl<'a> ByteReader<'a> {
 	pub fn new(bytes: &'a [u8]) -> Self {
 		ByteReader {
-			bytes
+			bytes,
+			offset: 0
 		}
 	}
 
-	pub fn bytes(&mut self, len: usize) -> &'a [u8] {
+	/// Amount of bytes that have been consumed so far.
+	pub fn offset(&self) -> usize {
+		self.offset
+	}
+
+	pub fn remaining(&self) -> usize {
+		self.bytes.len()
+	}
+
+	pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], InsufficientBytes> {
+		if len > self.bytes.len() {
+			return Err(InsufficientBytes {
+				offset: self.offset,
+				requested: len,
+				available: self.bytes.len()
+			});
+		}
+
 		let (requested, rest) = self.bytes.split_at(len);
 		self.bytes = rest;
+		self.offset += len;
+
+		Ok(requested)
+	}
+
+	fn array<const N: usize>(&mut self) -> Result<[u8; N], InsufficientBytes> {
+		let mut array = [0; N];
+		array.copy_from_slice(self.bytes(N)?);
+
+		Ok(array)
+	}
+
+	pub fn u8(&mut self) -> Result<u8, InsufficientBytes> {
+		self.array().map(u8::from_le_bytes)
+	}
+
+	pub fn i8(&mut self) -> Result<i8, InsufficientBytes> {
+		self.array().map(i8::from_le_bytes)
+	}
+
+	pub fn u16(&mut self) -> Result<u16, InsufficientBytes> {
+		self.array().map(u16::from_le_bytes)
+	}
+
+	pub fn i16(&mut self) -> Result<i16, InsufficientBytes> {
+		self.array().map(i16::from_le_bytes)
+	}
+
+	pub fn u32(&mut self) -> Result<u32, InsufficientBytes> {
+		self.array().map(u32::from_le_bytes)
+	}
+
+	pub fn i32(&mut self) -> Result<i32, InsufficientBytes> {
+		self.array().map(i32::from_le_bytes)
+	}
+
+	pub fn u64(&mut self) -> Result<u64, InsufficientBytes> {
+		self.array().map(u64::from_le_bytes)
+	}
 
-		requested
+	pub fn i64(&mut self) -> Result<i64, InsufficientBytes> {
+		self.array().map(i64::from_le_bytes)
 	}
 
-	pub fn u32(&mut self) -> u32 {
-		if let &[a, b, c, d] = self.bytes(4) {
-			u32::from_le_bytes([a, b, c, d])
-		} else {
-			unreachable!()
+	pub fn f32(&mut self) -> Result<f32, InsufficientBytes> {
+		self.u32().map(f32::from_bits)
+	}
+
+	/// Reads a NUL terminated string, returning the bytes before the terminator. The terminator
+	/// itself is consumed but not returned. Fails without consuming anything if there is no
+	/// terminator.
+	pub fn cstring(&mut self) -> Result<&'a [u8], InsufficientBytes> {
+		match self.bytes.iter().position(|&byte| byte == 0) {
+			Some(len) => {
+				let string = self.bytes(len)?;
+				self.offset += 1;
+				self.bytes = &self.bytes[1..];
+
+				Ok(string)
+			},
+			None => Err(InsufficientBytes {
+				offset: self.offset,
+				requested: self.bytes.len() + 1,
+				available: self.bytes.len()
+			})
 		}
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ByteReader;
 
-	pub fn i32(&mut self) -> i32 {
-		self.u32() as i32
+	#[test]
+	fn short_input_is_an_error() {
+		let mut reader = ByteReader::new(&[1, 0, 2, 3, 4]);
+
+		assert_eq!(reader.u16().unwrap(), 1);
+
+		let err = reader.u32().unwrap_err();
+		assert_eq!((err.offset, err.requested, err.available), (2, 4, 3));
+
+		// A failed read consumes nothing
+		assert_eq!(reader.offset(), 2);
+		assert_eq!(reader.bytes(3).unwrap(), &[2, 3, 4]);
+		assert!(reader.u8().is_err());
 	}
 
-	pub fn f32(&mut self) -> f32 {
-		f32::from_bits(self.u32())
+	#[test]
+	fn cstrings_need_a_terminator() {
+		let mut reader = ByteReader::new(b"ab\0cd");
+
+		assert_eq!(reader.cstring().unwrap(), b"ab");
+		assert_eq!(reader.offset(), 3);
+
+		let err = reader.cstring().unwrap_err();
+		assert_eq!((err.offset, err.requested, err.available), (3, 3, 2));
+		assert_eq!(reader.remaining(), 2);
 	}
 }
@@ -0,0 +1,231 @@
+//! Traits for types with a single, fixed bit representation, so that reading and writing them can
+//! be derived instead of written out by hand. See the `bitstream-derive` crate for the attributes
+//! accepted by `#[derive(BitDecode, BitEncode)]`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use crate::{BitParseError, BitRead, BitReader, BitWriter, RawString};
+
+/// A value that can be read from a stream of bits. `protocol` is the network protocol version that
+/// the stream was written with, for values whose representation depends on it.
+pub trait BitDecode: Sized {
+	fn decode(bits: &mut BitReader, protocol: u32) -> Result<Self, BitParseError>;
+}
+
+/// A value that can be written to a stream of bits, in the form understood by `BitDecode`.
+pub trait BitEncode {
+	fn encode(&self, bits: &mut BitWriter, protocol: u32);
+}
+
+macro_rules! primitive {
+	($ty:ty, $read:ident, $write:ident) => {
+		impl BitDecode for $ty {
+			fn decode(bits: &mut BitReader, _protocol: u32) -> Result<Self, BitParseError> {
+				Ok(bits.$read()?)
+			}
+		}
+
+		impl BitEncode for $ty {
+			fn encode(&self, bits: &mut BitWriter, _protocol: u32) {
+				bits.$write(*self)
+			}
+		}
+	}
+}
+
+primitive!(bool, read_bit, write_bit);
+primitive!(u8, read_u8, write_u8);
+primitive!(u16, read_u16, write_u16);
+primitive!(u32, read_u32, write_u32);
+primitive!(u64, read_u64, write_u64);
+primitive!(i8, read_i8, write_i8);
+primitive!(i16, read_i16, write_i16);
+primitive!(i32, read_i32, write_i32);
+primitive!(i64, read_i64, write_i64);
+primitive!(f32, read_f32, write_f32);
+
+impl BitDecode for String {
+	fn decode(bits: &mut BitReader, _protocol: u32) -> Result<Self, BitParseError> {
+		bits.read_string()
+	}
+}
+
+impl BitEncode for String {
+	fn encode(&self, bits: &mut BitWriter, _protocol: u32) {
+		bits.write_string(self)
+	}
+}
+
+impl BitDecode for RawString {
+	fn decode(bits: &mut BitReader, _protocol: u32) -> Result<Self, BitParseError> {
+		Ok(RawString(bits.read_string_bytes()?))
+	}
+}
+
+impl BitEncode for RawString {
+	fn encode(&self, bits: &mut BitWriter, _protocol: u32) {
+		bits.write_string_bytes(self.bytes())
+	}
+}
+
+/// Prefixed by a single bit that is set if the value is present.
+impl<T> BitDecode for Option<T> where T: BitDecode {
+	fn decode(bits: &mut BitReader, protocol: u32) -> Result<Self, BitParseError> {
+		bits.try_parse(|bits| {
+			Ok(if bits.read_bit()? { Some(T::decode(bits, protocol)?) } else { None })
+		})
+	}
+}
+
+impl<T> BitEncode for Option<T> where T: BitEncode {
+	fn encode(&self, bits: &mut BitWriter, protocol: u32) {
+		bits.write_bit(self.is_some());
+
+		if let Some(value) = self {
+			value.encode(bits, protocol);
+		}
+	}
+}
+
+impl<T, const N: usize> BitDecode for [T; N] where T: BitDecode {
+	fn decode(bits: &mut BitReader, protocol: u32) -> Result<Self, BitParseError> {
+		bits.try_parse(|bits| {
+			let mut values = Vec::with_capacity(N);
+
+			for _ in 0..N {
+				values.push(T::decode(bits, protocol)?);
+			}
+
+			match values.try_into() {
+				Ok(array) => Ok(array),
+				Err(_) => unreachable!("exactly N values were decoded")
+			}
+		})
+	}
+}
+
+impl<T, const N: usize> BitEncode for [T; N] where T: BitEncode {
+	fn encode(&self, bits: &mut BitWriter, protocol: u32) {
+		for value in self {
+			value.encode(bits, protocol);
+		}
+	}
+}
+
+impl BitDecode for () {
+	fn decode(_bits: &mut BitReader, _protocol: u32) -> Result<Self, BitParseError> {
+		Ok(())
+	}
+}
+
+impl BitEncode for () {
+	fn encode(&self, _bits: &mut BitWriter, _protocol: u32) {}
+}
+
+macro_rules! tuple {
+	($($name:ident)+) => {
+		impl<$($name),+> BitDecode for ($($name,)+) where $($name: BitDecode),+ {
+			fn decode(bits: &mut BitReader, protocol: u32) -> Result<Self, BitParseError> {
+				bits.try_parse(|bits| Ok(($($name::decode(bits, protocol)?,)+)))
+			}
+		}
+
+		impl<$($name),+> BitEncode for ($($name,)+) where $($name: BitEncode),+ {
+			#[allow(non_snake_case)]
+			fn encode(&self, bits: &mut BitWriter, protocol: u32) {
+				let ($($name,)+) = self;
+
+				$($name.encode(bits, protocol);)+
+			}
+		}
+	}
+}
+
+tuple!(A);
+tuple!(A B);
+tuple!(A B C);
+tuple!(A B C D);
+tuple!(A B C D E);
+tuple!(A B C D E F);
+
+#[cfg(test)]
+mod tests {
+	use alloc::string::ToString;
+	use core::fmt::Debug;
+	use super::*;
+
+	fn round_trip<T>(value: &T, protocol: u32) -> T where T: BitDecode + BitEncode + Debug + PartialEq {
+		let mut writer = BitWriter::new();
+		value.encode(&mut writer, protocol);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		let decoded = T::decode(&mut reader, protocol).unwrap();
+		assert_eq!(reader.remaining_bits(), 0);
+
+		decoded
+	}
+
+	#[derive(Debug, PartialEq, crate::BitDecode, crate::BitEncode)]
+	struct Fields {
+		#[bits(3)]
+		small: u8,
+		#[bits(1)]
+		flag: bool,
+		#[bits(2)]
+		flags: Option<bool>,
+		#[coord]
+		origin: (f32, f32),
+		#[varint]
+		delta: i32,
+		#[since(protocol = 10)]
+		name: String
+	}
+
+	#[derive(Debug, PartialEq, crate::BitDecode, crate::BitEncode)]
+	struct Wrapper<T, const N: usize = 2>(Option<T>, [u16; N]) where T: Copy;
+
+	#[test]
+	fn derived_fields_round_trip() {
+		let value = Fields {
+			small: 5,
+			flag: true,
+			flags: Some(true),
+			origin: (-4.5, 1000.0),
+			delta: -300,
+			name: "name".to_string()
+		};
+
+		assert_eq!(round_trip(&value, 10), value);
+
+		// The field is not sent at all before its protocol, and is left at the default
+		let old = round_trip(&value, 9);
+		assert_eq!(old.name, "");
+		assert_eq!(old.small, 5);
+	}
+
+	#[test]
+	fn bool_fields_are_set_by_any_bit() {
+		let mut writer = BitWriter::new();
+		writer.write_bits(7, 3);
+		writer.write_bit(false);
+		writer.write_bit(true);
+		writer.write_bits(2, 2);
+		writer.write_bits(0, 4);
+		writer.write_var_i32(0);
+
+		let decoded = Fields::decode(&mut writer.finish().reader(), 0).unwrap();
+
+		assert!(!decoded.flag);
+		assert_eq!(decoded.flags, Some(true));
+	}
+
+	#[test]
+	fn generic_structs_are_bounded_by_the_trait() {
+		let value = Wrapper::<u8, 3>(Some(7), [1, 2, 3]);
+
+		assert_eq!(round_trip(&value, 0), value);
+	}
+}
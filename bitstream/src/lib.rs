@@ -1,7 +1,43 @@
-/// bitstream: Abstractions for parsing data from streams of bits and bytes
+//! bitstream: Abstractions for parsing data from streams of bits and bytes
+//!
+//! Only `alloc` is needed unless the default `std` feature is enabled, which adds
+//! `StreamBitReader` for reading from `std::io::Read`.
+
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+// The derives refer to this crate by name, which tests within it need to be able to resolve
+#[cfg(test)]
+extern crate self as bitstream;
 
 mod bits;
 mod bytes;
+mod codec;
+mod order;
+mod read;
+mod source;
+#[cfg(feature = "std")]
+mod stream;
+mod string;
+#[cfg(feature = "trace")]
+mod trace;
+mod writer;
 
 pub use self::bits::*;
 pub use self::bytes::*;
+pub use self::codec::*;
+pub use self::order::*;
+pub use self::read::*;
+pub use self::source::*;
+#[cfg(feature = "std")]
+pub use self::stream::*;
+pub use self::string::*;
+#[cfg(feature = "trace")]
+pub use self::trace::*;
+pub use self::writer::*;
+
+pub use bitstream_derive::{BitDecode, BitEncode};
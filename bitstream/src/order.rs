@@ -0,0 +1,168 @@
+use core::fmt::Debug;
+
+/// The order that bits are packed into each byte of the input. `BitReader`, `BitSlice` and `Bits`
+/// are generic over this, defaulting to `LsbFirst`, which is what the Source engine uses.
+///
+/// `LsbFirst` reads each byte from its lowest bit up, and the first bit of a multi-bit value
+/// becomes its least significant bit, so values are little endian. `MsbFirst` reads each byte from
+/// its highest bit down, and the first bit becomes the most significant bit, so values are big
+/// endian.
+pub trait BitOrder: Copy + Debug + Default + 'static {
+	/// Appends the first `count` bytes of `bytes` to a buffer holding `available` bits.
+	/// `available + count * 8` must not exceed 64.
+	fn fill(buffer: u64, available: u8, bytes: [u8; 8], count: usize) -> u64;
+
+	/// Removes the next `count` bits from the buffer and returns them. `count` must be between 1
+	/// and 63.
+	fn take(buffer: &mut u64, count: u8) -> u64;
+
+	/// Extracts the 8 bytes starting `shift` bits into `bytes`, where `next` is the byte following
+	/// `bytes`. `shift` must be between 1 and 7.
+	fn realign(bytes: [u8; 8], next: u8, shift: u32) -> [u8; 8];
+
+	/// Like `realign`, but for a single byte.
+	fn realign_byte(byte: u8, next: u8, shift: u32) -> u8;
+
+	/// Joins two 32 bit values into a 64 bit value, where `first` was read before `second`.
+	fn join(first: u32, second: u32) -> u64;
+
+	/// Places the `count` bits of `value` within a byte, as the first bits of that byte.
+	fn partial_byte(value: u8, count: u8) -> u8;
+}
+
+/// Bits are read starting with the least significant bit of each byte. This is the order used
+/// by `bf_read` and `bf_write`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct LsbFirst;
+
+/// Bits are read starting with the most significant bit of each byte, as in many audio codecs.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct MsbFirst;
+
+impl BitOrder for LsbFirst {
+	#[inline(always)]
+	fn fill(buffer: u64, available: u8, bytes: [u8; 8], count: usize) -> u64 {
+		let word = u64::from_le_bytes(bytes);
+
+		// Only keep the bytes that were actually taken from the input.
+		// When all 8 bytes are taken, the buffer is empty, so there is nothing to shift.
+		let word = if count == 8 { word } else { word & ((1 << (count * 8)) - 1) };
+
+		buffer | (word << available)
+	}
+
+	#[inline(always)]
+	fn take(buffer: &mut u64, count: u8) -> u64 {
+		let bits = *buffer & ((1 << count) - 1);
+		*buffer >>= count;
+
+		bits
+	}
+
+	#[inline(always)]
+	fn realign(bytes: [u8; 8], next: u8, shift: u32) -> [u8; 8] {
+		let word = u64::from_le_bytes(bytes);
+
+		((word >> shift) | ((next as u64) << (64 - shift))).to_le_bytes()
+	}
+
+	#[inline(always)]
+	fn realign_byte(byte: u8, next: u8, shift: u32) -> u8 {
+		(byte >> shift) | (next << (8 - shift))
+	}
+
+	#[inline(always)]
+	fn join(first: u32, second: u32) -> u64 {
+		(first as u64) | ((second as u64) << 32)
+	}
+
+	#[inline(always)]
+	fn partial_byte(value: u8, _count: u8) -> u8 {
+		value
+	}
+}
+
+impl BitOrder for MsbFirst {
+	#[inline(always)]
+	fn fill(buffer: u64, available: u8, bytes: [u8; 8], count: usize) -> u64 {
+		let word = u64::from_be_bytes(bytes);
+
+		// The buffer is filled from the top, so the unused bytes are the lowest ones.
+		let word = if count == 8 { word } else { word & !(u64::MAX >> (count * 8)) };
+
+		buffer | (word >> available)
+	}
+
+	#[inline(always)]
+	fn take(buffer: &mut u64, count: u8) -> u64 {
+		let bits = *buffer >> (64 - count);
+		*buffer <<= count;
+
+		bits
+	}
+
+	#[inline(always)]
+	fn realign(bytes: [u8; 8], next: u8, shift: u32) -> [u8; 8] {
+		let word = u64::from_be_bytes(bytes);
+
+		((word << shift) | ((next as u64) >> (8 - shift))).to_be_bytes()
+	}
+
+	#[inline(always)]
+	fn realign_byte(byte: u8, next: u8, shift: u32) -> u8 {
+		(byte << shift) | (next >> (8 - shift))
+	}
+
+	#[inline(always)]
+	fn join(first: u32, second: u32) -> u64 {
+		((first as u64) << 32) | (second as u64)
+	}
+
+	#[inline(always)]
+	fn partial_byte(value: u8, count: u8) -> u8 {
+		value << (8 - count)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+	use crate::{BitRead, BitReader, BitSlice, Bits};
+	use super::*;
+
+	#[test]
+	fn msb_first_reads_from_the_top_of_each_byte() {
+		let data = [0b1011_0010, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F];
+		let mut reader = BitReader::new_in(&data, MsbFirst);
+
+		assert!(reader.read_bit().unwrap());
+		assert_eq!(reader.read_bits(3).unwrap(), 0b011);
+		assert_eq!(reader.read_bits(8).unwrap(), 0b0010_0001);
+
+		// Unaligned byte arrays are realigned in the same order
+		assert_eq!(reader.read_u8_array(7).unwrap(), [0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+
+		// The first 32 bits are the high half of 64 bit values
+		let mut reader = BitReader::new_in(&data[1..], MsbFirst);
+		assert_eq!(reader.read_u64().unwrap(), 0x1234_5678_9ABC_DEF0);
+		assert!(reader.read_u16().is_err());
+	}
+
+	#[test]
+	fn msb_first_slices_count_from_the_top() {
+		let data = [0b0001_1110, 0b1000_0000];
+		let slice = BitSlice::from_parts_in(&data, 3, 6, MsbFirst);
+
+		assert_eq!(slice.reader().read_bits(6).unwrap(), 0b11_1101);
+
+		let owned = slice.to_bits();
+		assert_eq!(owned.raw_bytes(), [0b1111_0100]);
+		assert_eq!(owned.trailing_bits(), 6);
+
+		let bits = Bits::from_parts_in(vec![0xFF, 0b1100_0000], 2, MsbFirst);
+		let mut reader = bits.reader();
+		reader.skip_bits(8).unwrap();
+		assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+		assert!(reader.read_bit().is_err());
+	}
+}
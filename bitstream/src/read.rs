@@ -0,0 +1,361 @@
+//! The reading methods shared by `BitReader` and `StreamBitReader`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use crate::{source, BitOrder, BitParseError, Checkpoint, CoordType, InsufficientBits};
+
+/// A source of bits. Implementations provide the basic operations, and every larger value is
+/// read on top of them, so that all readers decode the same input in the same way.
+pub trait BitRead {
+	/// The order that bits are packed into each byte of the input.
+	type Order: BitOrder;
+
+	/// Reads up to 32 bits. With `LsbFirst`, the first bit read becomes the least significant bit
+	/// of the result, so values are little endian. With `MsbFirst`, the first bit read becomes the
+	/// most significant of the `count` bits, so values are big endian.
+	fn read_bits(&mut self, count: u8) -> Result<u32, InsufficientBits>;
+
+	/// Skips over the next `count` bits without reading them.
+	fn skip_bits(&mut self, count: usize) -> Result<(), InsufficientBits>;
+
+	/// Appends the next `len` bytes to `data`. Nothing is read if there are not enough bits left.
+	fn read_u8_array_into(&mut self, data: &mut Vec<u8>, len: usize) -> Result<(), InsufficientBits>;
+
+	/// Returns the amount of bits that have been consumed so far.
+	fn position(&self) -> usize;
+
+	/// Saves the current position, so that the reader can later be rewound with `restore`.
+	fn checkpoint(&self) -> Checkpoint;
+
+	/// Rewinds (or fast-forwards) the reader to a position saved by `checkpoint`. The checkpoint
+	/// must have come from this reader.
+	fn restore(&mut self, checkpoint: Checkpoint);
+
+	/// Runs the parser, rewinding the reader to where it was before the parser was run if it
+	/// fails. This allows trying out a parse without losing the current position on failure.
+	fn try_parse<T, E, F>(&mut self, parse: F) -> Result<T, E> where F: FnOnce(&mut Self) -> Result<T, E> {
+		let checkpoint = self.checkpoint();
+		let result = parse(self);
+
+		if result.is_err() {
+			self.restore(checkpoint);
+		}
+
+		result
+	}
+
+	/// Like `traced`, but with a custom description of the value that was read. Readers that can
+	/// record their reads do so here, otherwise this only runs the parser.
+	#[doc(hidden)]
+	#[inline(always)]
+	fn traced_with<T, E, F, D>(&mut self, _read: &'static str, parse: F, _describe: D) -> Result<T, E>
+		where F: FnOnce(&mut Self) -> Result<T, E>, D: FnOnce(&T) -> String {
+
+		parse(self)
+	}
+
+	/// Runs a read, recording its position and value if it is not nested within another read.
+	#[doc(hidden)]
+	#[inline(always)]
+	fn traced<T, E, F>(&mut self, read: &'static str, parse: F) -> Result<T, E> where T: Debug, F: FnOnce(&mut Self) -> Result<T, E> {
+		self.traced_with(read, parse, |value| format!("{:?}", value))
+	}
+
+	/// Combines `traced` and `try_parse`.
+	#[doc(hidden)]
+	#[inline(always)]
+	fn try_parse_traced<T, E, F>(&mut self, read: &'static str, parse: F) -> Result<T, E> where T: Debug, F: FnOnce(&mut Self) -> Result<T, E> {
+		self.traced(read, |bits| bits.try_parse(parse))
+	}
+
+	fn read_bit(&mut self) -> Result<bool, InsufficientBits> {
+		self.traced("bit", |bits| bits.read_bits(1).map(|bit| bit == 1))
+	}
+
+	/// Reads the next `count` bits without consuming them.
+	fn peek_bits(&mut self, count: u8) -> Result<u32, InsufficientBits> {
+		let checkpoint = self.checkpoint();
+		let bits = self.read_bits(count)?;
+
+		self.restore(checkpoint);
+
+		Ok(bits)
+	}
+
+	fn read_u8(&mut self) -> Result<u8, InsufficientBits> {
+		self.traced("u8", |bits| bits.read_bits(8).map(|x| x as u8))
+	}
+
+	fn read_u8_array(&mut self, len: usize) -> Result<Vec<u8>, InsufficientBits> {
+		self.traced("u8_array", |bits| {
+			let mut data = Vec::with_capacity(len);
+
+			bits.read_u8_array_into(&mut data, len)?;
+
+			Ok(data)
+		})
+	}
+
+	fn read_u16(&mut self) -> Result<u16, InsufficientBits> {
+		self.traced("u16", |bits| bits.read_bits(16).map(|x| x as u16))
+	}
+
+	fn read_u32(&mut self) -> Result<u32, InsufficientBits> {
+		self.traced("u32", |bits| bits.read_bits(32))
+	}
+
+	fn read_u64(&mut self) -> Result<u64, InsufficientBits> {
+		self.try_parse_traced("u64", |bits| {
+			let first = bits.read_u32().map_err(|err| err.within(0, 64))?;
+			let second = bits.read_u32().map_err(|err| err.within(32, 64))?;
+
+			Ok(Self::Order::join(first, second))
+		})
+	}
+
+	fn read_f32(&mut self) -> Result<f32, InsufficientBits> {
+		self.traced("f32", |bits| bits.read_u32().map(f32::from_bits))
+	}
+
+	fn read_i8(&mut self) -> Result<i8, InsufficientBits> {
+		self.traced("i8", |bits| bits.read_u8().map(|x| x as i8))
+	}
+
+	fn read_i16(&mut self) -> Result<i16, InsufficientBits> {
+		self.traced("i16", |bits| bits.read_u16().map(|x| x as i16))
+	}
+
+	fn read_i32(&mut self) -> Result<i32, InsufficientBits> {
+		self.traced("i32", |bits| bits.read_u32().map(|x| x as i32))
+	}
+
+	fn read_i64(&mut self) -> Result<i64, InsufficientBits> {
+		self.traced("i64", |bits| bits.read_u64().map(|x| x as i64))
+	}
+
+	fn read_var(&mut self) -> Result<u32, InsufficientBits> {
+		self.try_parse_traced("var", |bits| {
+			match bits.read_bits(2)? {
+				0 => bits.read_bits(4),
+				1 => bits.read_u8().map(u32::from),
+				2 => bits.read_bits(12),
+				_ => bits.read_u32()
+			}
+		})
+	}
+
+	fn read_coord(&mut self) -> Result<f32, InsufficientBits> {
+		self.try_parse_traced("coord", |bits| {
+			let integral = bits.read_bit()?;
+			let fractional = bits.read_bit()?;
+
+			if integral || fractional {
+				let sign = bits.read_bit()?;
+
+				let integer = if integral {
+					bits.read_bits(14)? + 1
+				} else {
+					0
+				};
+
+				let fraction = if fractional {
+					bits.read_bits(5)?
+				} else {
+					0
+				};
+
+				let value = (integer as f32) + (fraction as f32) * 0.03125;
+
+				Ok(if sign { -value } else { value })
+			} else {
+				Ok(0.0)
+			}
+		})
+	}
+
+	fn read_vec3(&mut self) -> Result<(f32, f32, f32), InsufficientBits> {
+		self.try_parse_traced("vec3", |bits| {
+			let x = bits.read_bit()?;
+			let y = bits.read_bit()?;
+			let z = bits.read_bit()?;
+
+			Ok((
+				if x { bits.read_coord()? } else { 0.0 },
+				if y { bits.read_coord()? } else { 0.0 },
+				if z { bits.read_coord()? } else { 0.0 }
+			))
+		})
+	}
+
+	fn read_string(&mut self) -> Result<String, BitParseError> {
+		self.try_parse_traced("string", |bits| Ok(String::from_utf8(read_string_bytes(bits, usize::MAX)?)?))
+	}
+
+	/// Reads a NUL terminated string like `read_string`, but replaces invalid UTF-8 sequences with
+	/// U+FFFD instead of failing.
+	fn read_string_lossy(&mut self) -> Result<String, InsufficientBits> {
+		self.traced("string", |bits| bits.read_string_bytes().map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+	}
+
+	/// Reads the bytes of a NUL terminated string, without the terminator and without checking
+	/// the encoding.
+	fn read_string_bytes(&mut self) -> Result<Vec<u8>, InsufficientBits> {
+		self.try_parse_traced("string", |bits| read_string_bytes(bits, usize::MAX))
+	}
+
+	/// Reads a NUL terminated string into a buffer of `max_len` bytes, matching the semantics of
+	/// `bf_read::ReadString`: the whole string is consumed, but only the first `max_len - 1` bytes
+	/// are kept, as the buffer also has to fit the terminator.
+	fn read_string_bounded(&mut self, max_len: usize) -> Result<String, BitParseError> {
+		self.try_parse_traced("string", |bits| Ok(String::from_utf8(read_string_bytes(bits, max_len.saturating_sub(1))?)?))
+	}
+
+	/// Reads the bytes of a NUL terminated string, truncated in the same way as
+	/// `read_string_bounded`.
+	fn read_string_bytes_bounded(&mut self, max_len: usize) -> Result<Vec<u8>, InsufficientBits> {
+		self.try_parse_traced("string", |bits| read_string_bytes(bits, max_len.saturating_sub(1)))
+	}
+
+	fn read_var_u32(&mut self) -> Result<u32, InsufficientBits> {
+		self.try_parse_traced("var_u32", |bits| {
+			let mut result = 0;
+
+			for index in 0..5 {
+				let byte = bits.read_u8()?;
+
+				result |= ((byte & 0x7F) as u32) << (7 * index);
+
+				if byte < 128 {
+					break;
+				}
+			}
+
+			Ok(result)
+		})
+	}
+
+	/// Reads a single component of a normal vector: a sign bit followed by 11 fractional bits.
+	fn read_bit_normal(&mut self) -> Result<f32, InsufficientBits> {
+		source::read_bit_normal(self)
+	}
+
+	/// Reads a normalized vector. Only the X and Y components are sent, Z is reconstructed from
+	/// them using its sign bit.
+	fn read_bit_vec3_normal(&mut self) -> Result<(f32, f32, f32), InsufficientBits> {
+		source::read_bit_vec3_normal(self)
+	}
+
+	/// Reads an angle in degrees, quantized to `count` bits over the range `[0, 360)`.
+	fn read_bit_angle(&mut self, count: u8) -> Result<f32, InsufficientBits> {
+		source::read_bit_angle(self, count)
+	}
+
+	/// Reads a coordinate using the multiplayer-optimized encoding, which uses fewer integer bits
+	/// when the value is within the in-bounds range of the map.
+	fn read_coord_mp(&mut self, kind: CoordType) -> Result<f32, InsufficientBits> {
+		source::read_coord_mp(self, kind)
+	}
+
+	/// Reads an unsigned coordinate relative to the origin of a cell, where the integer part is
+	/// `count` bits long.
+	fn read_bit_cell_coord(&mut self, count: u8, kind: CoordType) -> Result<f32, InsufficientBits> {
+		source::read_bit_cell_coord(self, count, kind)
+	}
+
+	/// Reads a UBitVar as encoded by newer engine branches: the low 4 bits of the value come
+	/// first, followed by 2 bits selecting how many more bits follow (0, 4, 8, or 28).
+	/// This is not the same as `read_var`, which puts the selector first.
+	fn read_ubit_int(&mut self) -> Result<u32, InsufficientBits> {
+		source::read_ubit_int(self)
+	}
+
+	fn read_var_u64(&mut self) -> Result<u64, InsufficientBits> {
+		source::read_var_u64(self)
+	}
+
+	/// Reads a zigzag encoded signed varint.
+	fn read_var_i32(&mut self) -> Result<i32, InsufficientBits> {
+		self.traced("var_i32", |bits| bits.read_var_u32().map(|value| ((value >> 1) as i32) ^ -((value & 1) as i32)))
+	}
+
+	/// Reads a zigzag encoded signed varint.
+	fn read_var_i64(&mut self) -> Result<i64, InsufficientBits> {
+		self.traced("var_i64", |bits| bits.read_var_u64().map(|value| ((value >> 1) as i64) ^ -((value & 1) as i64)))
+	}
+}
+
+/// Reads a NUL terminated string, keeping at most `keep` bytes of it.
+fn read_string_bytes<R>(bits: &mut R, keep: usize) -> Result<Vec<u8>, InsufficientBits> where R: BitRead + ?Sized {
+	let mut data = Vec::new();
+
+	loop {
+		let value = bits.read_u8()?;
+
+		if value == 0 {
+			break;
+		}
+
+		if data.len() < keep {
+			data.push(value);
+		}
+	}
+
+	Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::format;
+	use crate::{BitReader, BitWriter, RawString};
+	use super::*;
+
+	fn strings(strings: &[&[u8]]) -> Vec<u8> {
+		let mut writer = BitWriter::new();
+
+		for string in strings {
+			writer.write_string_bytes(string);
+		}
+
+		writer.finish().raw_bytes().to_vec()
+	}
+
+	#[test]
+	fn bounded_strings_consume_the_whole_string() {
+		let data = strings(&[b"hostname", b"next"]);
+		let mut reader = BitReader::new(&data);
+
+		assert_eq!(reader.read_string_bounded(5).unwrap(), "host");
+		assert_eq!(reader.read_string().unwrap(), "next");
+
+		let mut reader = BitReader::new(&data);
+
+		assert_eq!(reader.read_string_bytes_bounded(0).unwrap(), b"");
+		assert_eq!(reader.read_string_bytes_bounded(100).unwrap(), b"next");
+	}
+
+	#[test]
+	fn invalid_utf8_is_kept_or_replaced() {
+		let data = strings(&[b"caf\xE9", b"caf\xE9"]);
+		let mut reader = BitReader::new(&data);
+
+		assert!(matches!(reader.read_string(), Err(BitParseError::Utf8(_))));
+		assert_eq!(reader.position(), 0);
+
+		assert_eq!(reader.read_string_lossy().unwrap(), "caf\u{FFFD}");
+
+		let raw = RawString(reader.read_string_bytes().unwrap());
+		assert_eq!(format!("{:?}", raw), "b\"caf\\xe9\"");
+		assert_eq!(format!("{}", raw), "caf\u{FFFD}");
+	}
+
+	#[test]
+	fn unterminated_strings_are_rewound() {
+		let mut reader = BitReader::new(b"abc");
+
+		assert!(reader.read_string_bytes().is_err());
+		assert!(reader.read_string_bounded(2).is_err());
+		assert_eq!(reader.position(), 0);
+	}
+}
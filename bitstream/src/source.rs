@@ -0,0 +1,415 @@
+//! Numeric encodings used by Source engine networking, beyond the basic ones in `BitRead`.
+//! These mirror the equivalently named `bf_read` / `bf_write` functions of the engine.
+
+use crate::{BitRead, BitWriter, InsufficientBits};
+
+const COORD_INTEGER_BITS: u8 = 14;
+const COORD_INTEGER_BITS_MP: u8 = 11;
+const COORD_FRACTIONAL_BITS: u8 = 5;
+const COORD_FRACTIONAL_BITS_LOW_PRECISION: u8 = 3;
+
+const NORMAL_FRACTIONAL_BITS: u8 = 11;
+const NORMAL_DENOMINATOR: f32 = ((1 << NORMAL_FRACTIONAL_BITS) - 1) as f32;
+const NORMAL_RESOLUTION: f32 = 1.0 / NORMAL_DENOMINATOR;
+
+/// Selects between the variants of the CoordMP and cell coordinate encodings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CoordType {
+	/// Integer part and 5 fractional bits.
+	Normal,
+	/// Integer part and 3 fractional bits.
+	LowPrecision,
+	/// Integer part only.
+	Integral
+}
+
+impl CoordType {
+	fn fractional_bits(self) -> u8 {
+		if self == CoordType::LowPrecision {
+			COORD_FRACTIONAL_BITS_LOW_PRECISION
+		} else {
+			COORD_FRACTIONAL_BITS
+		}
+	}
+
+	fn denominator(self) -> f32 {
+		(1 << self.fractional_bits()) as f32
+	}
+}
+
+#[cfg(feature = "std")]
+fn sqrt(value: f32) -> f32 {
+	value.sqrt()
+}
+
+#[cfg(not(feature = "std"))]
+fn sqrt(value: f32) -> f32 {
+	newton_sqrt(value)
+}
+
+/// `f32::sqrt` needs `std`, so without it, use Newton's method instead. Working in `f64` keeps
+/// the result within rounding error of the `std` version.
+#[cfg(any(test, not(feature = "std")))]
+fn newton_sqrt(value: f32) -> f32 {
+	if value <= 0.0 {
+		return 0.0;
+	}
+
+	let value = value as f64;
+	let mut estimate = if value > 1.0 { value } else { 1.0 };
+
+	// Starting above the root, each step decreases the estimate until it converges
+	loop {
+		let next = 0.5 * (estimate + value / estimate);
+
+		if next >= estimate {
+			return estimate as f32;
+		}
+
+		estimate = next;
+	}
+}
+
+pub(crate) fn read_bit_normal<R>(bits: &mut R) -> Result<f32, InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("bit_normal", |bits| {
+		let sign = bits.read_bit()?;
+		let fraction = bits.read_bits(NORMAL_FRACTIONAL_BITS)?;
+
+		let value = (fraction as f32) * NORMAL_RESOLUTION;
+
+		Ok(if sign { -value } else { value })
+	})
+}
+
+pub(crate) fn read_bit_vec3_normal<R>(bits: &mut R) -> Result<(f32, f32, f32), InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("bit_vec3_normal", |bits| {
+		let has_x = bits.read_bit()?;
+		let has_y = bits.read_bit()?;
+
+		let x = if has_x { bits.read_bit_normal()? } else { 0.0 };
+		let y = if has_y { bits.read_bit_normal()? } else { 0.0 };
+
+		let z_negative = bits.read_bit()?;
+		let xy = x * x + y * y;
+
+		let z = if xy < 1.0 { sqrt(1.0 - xy) } else { 0.0 };
+
+		Ok((x, y, if z_negative { -z } else { z }))
+	})
+}
+
+pub(crate) fn read_bit_angle<R>(bits: &mut R, count: u8) -> Result<f32, InsufficientBits> where R: BitRead + ?Sized {
+	bits.traced("bit_angle", |bits| {
+		let value = bits.read_bits(count)?;
+
+		Ok((value as f32) * (360.0 / ((1u64 << count) as f32)))
+	})
+}
+
+pub(crate) fn read_coord_mp<R>(bits: &mut R, kind: CoordType) -> Result<f32, InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("coord_mp", |bits| {
+		let in_bounds = bits.read_bit()?;
+		let integer_bits = if in_bounds { COORD_INTEGER_BITS_MP } else { COORD_INTEGER_BITS };
+
+		if kind == CoordType::Integral {
+			if !bits.read_bit()? {
+				return Ok(0.0);
+			}
+
+			let sign = bits.read_bit()?;
+			let value = (bits.read_bits(integer_bits)? + 1) as f32;
+
+			Ok(if sign { -value } else { value })
+		} else {
+			let has_integer = bits.read_bit()?;
+			let sign = bits.read_bit()?;
+
+			let integer = if has_integer { bits.read_bits(integer_bits)? + 1 } else { 0 };
+			let fraction = bits.read_bits(kind.fractional_bits())?;
+
+			let value = (integer as f32) + (fraction as f32) / kind.denominator();
+
+			Ok(if sign { -value } else { value })
+		}
+	})
+}
+
+pub(crate) fn read_bit_cell_coord<R>(bits: &mut R, count: u8, kind: CoordType) -> Result<f32, InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("bit_cell_coord", |bits| {
+		let integer = bits.read_bits(count)?;
+
+		if kind == CoordType::Integral {
+			return Ok(integer as f32);
+		}
+
+		let fraction = bits.read_bits(kind.fractional_bits())?;
+
+		Ok((integer as f32) + (fraction as f32) / kind.denominator())
+	})
+}
+
+pub(crate) fn read_ubit_int<R>(bits: &mut R) -> Result<u32, InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("ubit_int", |bits| {
+		let value = bits.read_bits(6)?;
+
+		Ok(match value >> 4 {
+			0 => value,
+			1 => (value & 15) | (bits.read_bits(4)? << 4),
+			2 => (value & 15) | (bits.read_bits(8)? << 4),
+			_ => (value & 15) | (bits.read_bits(28)? << 4)
+		})
+	})
+}
+
+pub(crate) fn read_var_u64<R>(bits: &mut R) -> Result<u64, InsufficientBits> where R: BitRead + ?Sized {
+	bits.try_parse_traced("var_u64", |bits| {
+		let mut result = 0;
+
+		for index in 0..10 {
+			let byte = bits.read_u8()?;
+
+			result |= ((byte & 0x7F) as u64) << (7 * index);
+
+			if byte < 128 {
+				break;
+			}
+		}
+
+		Ok(result)
+	})
+}
+
+impl BitWriter {
+	pub fn write_bit_normal(&mut self, value: f32) {
+		let sign = value <= -NORMAL_RESOLUTION;
+		let fraction = ((value * NORMAL_DENOMINATOR).abs() as u32).min(NORMAL_DENOMINATOR as u32);
+
+		self.write_bit(sign);
+		self.write_bits(fraction, NORMAL_FRACTIONAL_BITS);
+	}
+
+	pub fn write_bit_vec3_normal(&mut self, value: (f32, f32, f32)) {
+		let present = |component: f32| component >= NORMAL_RESOLUTION || component <= -NORMAL_RESOLUTION;
+
+		let (has_x, has_y) = (present(value.0), present(value.1));
+
+		self.write_bit(has_x);
+		self.write_bit(has_y);
+
+		if has_x { self.write_bit_normal(value.0) }
+		if has_y { self.write_bit_normal(value.1) }
+
+		self.write_bit(value.2 <= -NORMAL_RESOLUTION);
+	}
+
+	pub fn write_bit_angle(&mut self, angle: f32, count: u8) {
+		let steps = (1u64 << count) as f32;
+		let value = ((angle / 360.0) * steps) as i64 as u32;
+
+		self.write_bits(value, count);
+	}
+
+	/// Writes a coordinate in the given CoordMP encoding. Like `write_coord`, values with an
+	/// integer part above 16384 are clamped to the largest coordinate that the encoding can hold,
+	/// and NaN is written as 0.
+	pub fn write_coord_mp(&mut self, value: f32, kind: CoordType) {
+		let max = (1 << COORD_INTEGER_BITS) as f32 + 1.0 - 1.0 / kind.denominator();
+		let magnitude = if value.is_nan() { 0.0 } else { value.abs().min(max) };
+
+		let sign = value <= -(1.0 / kind.denominator());
+		let integer = magnitude as u32;
+		let fraction = ((magnitude * kind.denominator()) as u32) & ((1 << kind.fractional_bits()) - 1);
+
+		let in_bounds = integer < (1 << COORD_INTEGER_BITS_MP);
+		let integer_bits = if in_bounds { COORD_INTEGER_BITS_MP } else { COORD_INTEGER_BITS };
+
+		self.write_bit(in_bounds);
+		self.write_bit(integer != 0);
+
+		if kind == CoordType::Integral {
+			if integer != 0 {
+				self.write_bit(sign);
+				self.write_bits(integer - 1, integer_bits);
+			}
+		} else {
+			self.write_bit(sign);
+
+			if integer != 0 {
+				self.write_bits(integer - 1, integer_bits);
+			}
+
+			self.write_bits(fraction, kind.fractional_bits());
+		}
+	}
+
+	pub fn write_bit_cell_coord(&mut self, value: f32, count: u8, kind: CoordType) {
+		let integer = value.abs() as u32;
+
+		self.write_bits(integer, count);
+
+		if kind != CoordType::Integral {
+			let fraction = ((value * kind.denominator()) as i32).unsigned_abs() & ((1 << kind.fractional_bits()) - 1);
+
+			self.write_bits(fraction, kind.fractional_bits());
+		}
+	}
+
+	pub fn write_ubit_int(&mut self, value: u32) {
+		let low = value & 15;
+
+		if value < (1 << 4) {
+			self.write_bits(low, 6);
+		} else if value < (1 << 8) {
+			self.write_bits(low | (1 << 4), 6);
+			self.write_bits(value >> 4, 4);
+		} else if value < (1 << 12) {
+			self.write_bits(low | (2 << 4), 6);
+			self.write_bits(value >> 4, 8);
+		} else {
+			self.write_bits(low | (3 << 4), 6);
+			self.write_bits(value >> 4, 28);
+		}
+	}
+
+	pub fn write_var_u64(&mut self, mut value: u64) {
+		while value >= 128 {
+			self.write_u8((value as u8 & 0x7F) | 0x80);
+			value >>= 7;
+		}
+
+		self.write_u8(value as u8);
+	}
+
+	pub fn write_var_i32(&mut self, value: i32) {
+		self.write_var_u32(((value << 1) ^ (value >> 31)) as u32)
+	}
+
+	pub fn write_var_i64(&mut self, value: i64) {
+		self.write_var_u64(((value << 1) ^ (value >> 63)) as u64)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{BitReader, BitWriter};
+	use super::*;
+
+	fn close(a: f32, b: f32, tolerance: f32) -> bool {
+		(a - b).abs() <= tolerance
+	}
+
+	#[test]
+	fn newton_sqrt_matches_the_exact_root() {
+		assert_eq!(newton_sqrt(0.0), 0.0);
+		assert_eq!(newton_sqrt(-1.0), 0.0);
+		assert_eq!(newton_sqrt(0.25), 0.5);
+		assert_eq!(newton_sqrt(16384.0), 128.0);
+
+		for step in 1..=1000 {
+			let value = step as f32 / 1000.0;
+			let root = newton_sqrt(value) as f64;
+
+			assert!((root * root - value as f64).abs() <= 1e-6, "sqrt({}) = {}", value, root);
+		}
+	}
+
+	#[test]
+	fn normals() {
+		let mut writer = BitWriter::new();
+		writer.write_bit_normal(0.5);
+		writer.write_bit_normal(-1.0);
+		writer.write_bit_vec3_normal((0.6, 0.0, -0.8));
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert!(close(reader.read_bit_normal().unwrap(), 0.5, NORMAL_RESOLUTION));
+		assert_eq!(reader.read_bit_normal().unwrap(), -1.0);
+
+		let (x, y, z) = reader.read_bit_vec3_normal().unwrap();
+		assert!(close(x, 0.6, NORMAL_RESOLUTION));
+		assert_eq!(y, 0.0);
+		assert!(close(z, -0.8, 0.01));
+
+		// has_x, has_y, then the sign bit of z, and nothing else for a vector along Z
+		assert_eq!(bits.bits_len(), 12 + 12 + 2 + 12 + 1);
+	}
+
+	#[test]
+	fn angles_and_coordinates() {
+		let mut writer = BitWriter::new();
+		writer.write_bit_angle(90.0, 8);
+		writer.write_coord_mp(-100.25, CoordType::Normal);
+		writer.write_coord_mp(3000.5, CoordType::LowPrecision);
+		writer.write_coord_mp(-7.0, CoordType::Integral);
+		writer.write_coord_mp(0.0, CoordType::Integral);
+		writer.write_bit_cell_coord(12.125, 6, CoordType::LowPrecision);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert_eq!(reader.read_bit_angle(8).unwrap(), 90.0);
+		assert_eq!(reader.read_coord_mp(CoordType::Normal).unwrap(), -100.25);
+		assert_eq!(reader.read_coord_mp(CoordType::LowPrecision).unwrap(), 3000.5);
+		assert_eq!(reader.read_coord_mp(CoordType::Integral).unwrap(), -7.0);
+		assert_eq!(reader.read_coord_mp(CoordType::Integral).unwrap(), 0.0);
+		assert_eq!(reader.read_bit_cell_coord(6, CoordType::LowPrecision).unwrap(), 12.125);
+		assert_eq!(reader.remaining_bits(), 0);
+	}
+
+	#[test]
+	fn coord_mp_out_of_range_is_clamped() {
+		let mut writer = BitWriter::new();
+		writer.write_coord_mp(20000.0, CoordType::Normal);
+		writer.write_coord_mp(-1.0e9, CoordType::LowPrecision);
+		writer.write_coord_mp(f32::INFINITY, CoordType::Integral);
+		writer.write_coord_mp(f32::NAN, CoordType::Normal);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert_eq!(reader.read_coord_mp(CoordType::Normal).unwrap(), 16384.0 + 31.0 / 32.0);
+		assert_eq!(reader.read_coord_mp(CoordType::LowPrecision).unwrap(), -(16384.0 + 7.0 / 8.0));
+		assert_eq!(reader.read_coord_mp(CoordType::Integral).unwrap(), 16384.0);
+		assert_eq!(reader.read_coord_mp(CoordType::Normal).unwrap(), 0.0);
+		assert_eq!(reader.remaining_bits(), 0);
+	}
+
+	#[test]
+	fn variable_length_integers() {
+		let values = [0, 15, 16, 255, 256, 4095, 4096, u32::MAX];
+
+		let mut writer = BitWriter::new();
+
+		for &value in &values {
+			writer.write_ubit_int(value);
+		}
+
+		writer.write_var_u64(u64::MAX);
+		writer.write_var_i32(-1);
+		writer.write_var_i32(i32::MIN);
+		writer.write_var_i64(-300);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		for &value in &values {
+			assert_eq!(reader.read_ubit_int().unwrap(), value);
+		}
+
+		assert_eq!(reader.read_var_u64().unwrap(), u64::MAX);
+		assert_eq!(reader.read_var_i32().unwrap(), -1);
+		assert_eq!(reader.read_var_i32().unwrap(), i32::MIN);
+		assert_eq!(reader.read_var_i64().unwrap(), -300);
+	}
+
+	#[test]
+	fn ubit_int_puts_the_selector_after_the_low_bits() {
+		// Low bits 0b0101, selector 1, then 4 more bits 0b0011: 0x35
+		let data = [0b1101_0101, 0];
+		let mut reader = BitReader::new(&data);
+
+		assert_eq!(reader.read_ubit_int().unwrap(), 0x35);
+		assert_eq!(reader.position(), 10);
+	}
+}
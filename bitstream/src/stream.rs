@@ -0,0 +1,308 @@
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use std::io::{self, Read};
+use crate::{BitOrder, BitRead, BitReader, Bits, Checkpoint, InsufficientBits, LsbFirst};
+
+const CHUNK_LEN: usize = 4096;
+
+/// Reads bits from a `Read` implementation, pulling in more input only as it is needed. The
+/// reads of `BitRead` are made by a `BitReader` over the input read so far, so they decode the
+/// same bits in the same way, but the whole input never has to be in memory at once.
+///
+/// The end of the input, as well as any I/O error, is reported as `InsufficientBits`. Use
+/// `io_error` to tell the two apart.
+///
+/// Input is only discarded once it has been consumed and no checkpoint can rewind to it.
+/// `try_parse` drops its checkpoint once the parser is done, but the ones created by `checkpoint`
+/// keep the input from their position onwards in memory until `release_checkpoints` is called.
+pub struct StreamBitReader<R, O = LsbFirst> {
+	input: R,
+	/// Input taken from `input` that is not known to be unneeded yet.
+	window: Vec<u8>,
+	/// Position of the first byte of `window` within the stream.
+	window_start: usize,
+	/// Bits consumed so far.
+	position: usize,
+	/// First byte that a checkpoint could rewind to, or `usize::MAX` if there are no checkpoints.
+	retained: Cell<usize>,
+	/// Set once `input` reports the end of the stream or an error.
+	ended: bool,
+	error: Option<io::Error>,
+	order: PhantomData<O>
+}
+
+impl<R> StreamBitReader<R> where R: Read {
+	pub fn new(input: R) -> Self {
+		Self::new_in(input, LsbFirst)
+	}
+}
+
+impl<R, O> StreamBitReader<R, O> where R: Read, O: BitOrder {
+	/// Creates a reader that reads the bits of each byte in the given order.
+	pub fn new_in(input: R, _order: O) -> Self {
+		StreamBitReader {
+			input,
+			window: Vec::new(),
+			window_start: 0,
+			position: 0,
+			retained: Cell::new(usize::MAX),
+			ended: false,
+			error: None,
+			order: PhantomData
+		}
+	}
+
+	/// Returns the I/O error that ended the stream, if the stream was ended by an error instead
+	/// of reaching the end of the input.
+	pub fn io_error(&self) -> Option<&io::Error> {
+		self.error.as_ref()
+	}
+
+	/// Returns the underlying reader. Any input that was read ahead and not consumed is lost.
+	pub fn into_inner(self) -> R {
+		self.input
+	}
+
+	/// Returns the amount of bits that have been consumed so far.
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	/// Returns true if all bits have been consumed. This may need to read more input to find out.
+	pub fn is_at_end(&mut self) -> bool {
+		self.fill_to(self.position + 1);
+
+		self.position >= self.window_end()
+	}
+
+	/// Allows the input before the current position to be discarded. Checkpoints created before
+	/// this must not be restored afterwards.
+	pub fn release_checkpoints(&mut self) {
+		self.retained.set(usize::MAX);
+	}
+
+	/// Copies the next `count` bits into an owned `Bits` value.
+	pub fn read_owned(&mut self, count: usize) -> Result<Bits<O>, InsufficientBits> {
+		self.with_reader(count, |bits| bits.read_slice(count).map(|slice| slice.to_bits()))
+	}
+
+	/// Position just past the last bit of `window` within the stream.
+	fn window_end(&self) -> usize {
+		(self.window_start + self.window.len()) * 8
+	}
+
+	/// Reads input until `window` extends to the bit `end`, or the input ends.
+	fn fill_to(&mut self, end: usize) {
+		while self.window_end() < end && !self.ended {
+			self.discard();
+
+			let len = self.window.len();
+			self.window.resize(len + CHUNK_LEN, 0);
+
+			let read = match self.input.read(&mut self.window[len..]) {
+				Ok(read) => {
+					self.ended = read == 0;
+
+					read
+				},
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => 0,
+				Err(err) => {
+					self.error = Some(err);
+					self.ended = true;
+
+					0
+				}
+			};
+
+			self.window.truncate(len + read);
+		}
+	}
+
+	/// Drops the input that has been consumed and that no checkpoint can rewind to. This waits
+	/// until a whole chunk can be dropped, so that the window is not shifted on every read.
+	fn discard(&mut self) {
+		let keep = (self.position / 8).min(self.retained.get());
+		let unneeded = keep - self.window_start;
+
+		if unneeded >= CHUNK_LEN {
+			self.window.drain(..unneeded);
+			self.window_start = keep;
+		}
+	}
+
+	/// Loads the next `count` bits of input if possible, then runs a read over the loaded input.
+	fn with_reader<T, F>(&mut self, count: usize, read: F) -> Result<T, InsufficientBits> where F: FnOnce(&mut BitReader<O>) -> Result<T, InsufficientBits> {
+		self.fill_to(self.position.saturating_add(count));
+
+		let origin = self.window_start * 8;
+		let mut reader = BitReader::resume(&self.window, self.position - origin, origin);
+
+		let result = read(&mut reader);
+		self.position = origin + reader.position();
+
+		result
+	}
+}
+
+impl<R, O> BitRead for StreamBitReader<R, O> where R: Read, O: BitOrder {
+	type Order = O;
+
+	fn read_bits(&mut self, count: u8) -> Result<u32, InsufficientBits> {
+		self.with_reader(count as usize, |bits| bits.read_bits(count))
+	}
+
+	fn skip_bits(&mut self, count: usize) -> Result<(), InsufficientBits> {
+		// Skip a chunk at a time, so that the skipped input is not all loaded at once
+		self.try_parse(|bits| {
+			let mut remaining = count;
+
+			while remaining > 0 {
+				let step = remaining.min(CHUNK_LEN * 8);
+
+				bits.with_reader(step, |bits| bits.skip_bits(step)).map_err(|err| err.within(count - remaining, count))?;
+
+				remaining -= step;
+			}
+
+			Ok(())
+		})
+	}
+
+	fn read_u8_array_into(&mut self, data: &mut Vec<u8>, len: usize) -> Result<(), InsufficientBits> {
+		self.with_reader(len.saturating_mul(8), |bits| bits.read_u8_array_into(data, len))
+	}
+
+	fn position(&self) -> usize {
+		self.position
+	}
+
+	fn checkpoint(&self) -> Checkpoint {
+		self.retained.set(self.retained.get().min(self.position / 8));
+
+		Checkpoint(self.position)
+	}
+
+	fn restore(&mut self, checkpoint: Checkpoint) {
+		assert!(checkpoint.0 / 8 >= self.window_start && checkpoint.0 <= self.window_end(), "checkpoint is no longer available in this reader");
+
+		self.position = checkpoint.0;
+	}
+
+	fn try_parse<T, E, F>(&mut self, parse: F) -> Result<T, E> where F: FnOnce(&mut Self) -> Result<T, E> {
+		let retained = self.retained.get();
+		let checkpoint = self.checkpoint();
+		let result = parse(self);
+
+		if result.is_err() {
+			self.restore(checkpoint);
+		}
+
+		self.retained.set(retained);
+
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+	use crate::{BitParseError, BitWriter, CoordType, MsbFirst};
+	use super::*;
+
+	/// Reads one of everything, so that the result can be compared between readers.
+	fn read_all<B>(bits: &mut B) -> Result<Vec<u64>, BitParseError> where B: BitRead {
+		let mut values = vec![
+			bits.read_bits(3)? as u64,
+			bits.read_u16()? as u64,
+			bits.read_u64()?,
+			bits.read_var()? as u64,
+			bits.read_coord()?.to_bits() as u64,
+			bits.read_coord_mp(CoordType::LowPrecision)?.to_bits() as u64,
+			bits.read_bit_normal()?.to_bits() as u64,
+			bits.read_ubit_int()? as u64,
+			bits.read_var_u64()?,
+			bits.read_var_i32()? as u64,
+			bits.peek_bits(5)? as u64
+		];
+
+		values.extend(bits.read_string()?.bytes().map(u64::from));
+		values.extend(bits.read_u8_array(5)?.into_iter().map(u64::from));
+
+		// A failing parse must leave the reader where it was
+		assert!(bits.try_parse(|bits| bits.read_u8_array(1000)).is_err());
+
+		values.push(bits.position() as u64);
+
+		Ok(values)
+	}
+
+	fn sample() -> Vec<u8> {
+		let mut writer = BitWriter::new();
+		writer.write_bits(5, 3);
+		writer.write_u16(0xBEEF);
+		writer.write_u64(0x0123_4567_89AB_CDEF);
+		writer.write_var(300);
+		writer.write_coord(-12.5);
+		writer.write_coord_mp(1000.25, CoordType::LowPrecision);
+		writer.write_bit_normal(-0.5);
+		writer.write_ubit_int(4000);
+		writer.write_var_u64(1 << 40);
+		writer.write_var_i32(-77);
+		writer.write_string("stream");
+		writer.write_u8_array(b"bytes");
+
+		writer.finish().raw_bytes().to_vec()
+	}
+
+	/// Hands out the input a few bytes at a time, to split reads across fills.
+	struct Trickle<'d>(&'d [u8]);
+
+	impl Read for Trickle<'_> {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let len = buf.len().min(self.0.len()).min(3);
+
+			buf[..len].copy_from_slice(&self.0[..len]);
+			self.0 = &self.0[len..];
+
+			Ok(len)
+		}
+	}
+
+	#[test]
+	fn reads_match_the_slice_reader() {
+		let data = sample();
+
+		let expected = read_all(&mut BitReader::new(&data)).unwrap();
+
+		assert_eq!(read_all(&mut StreamBitReader::new(&data[..])).unwrap(), expected);
+		assert_eq!(read_all(&mut StreamBitReader::new(Trickle(&data))).unwrap(), expected);
+
+		let expected = read_all(&mut BitReader::new_in(&data, MsbFirst));
+
+		assert_eq!(read_all(&mut StreamBitReader::new_in(Trickle(&data), MsbFirst)).ok(), expected.ok());
+	}
+
+	#[test]
+	fn checkpoints_keep_the_input() {
+		let data: Vec<u8> = (0..3 * CHUNK_LEN).map(|byte| byte as u8).collect();
+		let mut reader = StreamBitReader::new(&data[..]);
+
+		reader.skip_bits(4).unwrap();
+		let start = reader.checkpoint();
+
+		reader.skip_bits(8 * 2 * CHUNK_LEN).unwrap();
+		reader.restore(start);
+
+		assert_eq!(reader.position(), 4);
+		assert_eq!(reader.read_bits(8).unwrap(), 0x10);
+
+		reader.release_checkpoints();
+		assert_eq!(reader.read_owned(8 * (3 * CHUNK_LEN - 2)).unwrap().raw_bytes().len(), 3 * CHUNK_LEN - 2);
+
+		let err = reader.read_u16().unwrap_err();
+		assert_eq!((err.offset, err.requested, err.available), (8 * (3 * CHUNK_LEN - 1) + 4, 16, 4));
+		assert!(!reader.is_at_end());
+		assert!(reader.io_error().is_none());
+	}
+}
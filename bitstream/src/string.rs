@@ -0,0 +1,66 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::str::{self, Utf8Error};
+
+/// The bytes of a NUL terminated string, as they appeared in the input. The Source engine does not
+/// enforce any encoding, so strings such as player names and hostnames are often Latin-1 or
+/// contain broken UTF-8. Keeping the raw bytes means that nothing is lost when they are displayed
+/// or written back out.
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct RawString(pub Vec<u8>);
+
+impl RawString {
+	pub fn bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.0
+	}
+
+	pub fn to_str(&self) -> Result<&str, Utf8Error> {
+		str::from_utf8(&self.0)
+	}
+
+	/// Converts the string to UTF-8, replacing any invalid sequences with U+FFFD.
+	pub fn to_string_lossy(&self) -> Cow<'_, str> {
+		String::from_utf8_lossy(&self.0)
+	}
+}
+
+impl From<Vec<u8>> for RawString {
+	fn from(bytes: Vec<u8>) -> Self {
+		RawString(bytes)
+	}
+}
+
+impl From<String> for RawString {
+	fn from(string: String) -> Self {
+		RawString(string.into_bytes())
+	}
+}
+
+impl From<&str> for RawString {
+	fn from(string: &str) -> Self {
+		RawString(string.as_bytes().to_vec())
+	}
+}
+
+impl Display for RawString {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		Display::fmt(&self.to_string_lossy(), f)
+	}
+}
+
+impl Debug for RawString {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		// Anything that is not valid UTF-8 is escaped byte by byte, so that nothing is lost
+		if let Ok(utf8) = self.to_str() {
+			write!(f, "{:?}", utf8)
+		} else {
+			write!(f, "b\"{}\"", self.0.escape_ascii())
+		}
+	}
+}
@@ -0,0 +1,188 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Write};
+use crate::{BitOrder, BitRead, BitSlice};
+
+const MAX_VALUE_LEN: usize = 64;
+const MAX_RAW_BYTES: usize = 8;
+
+/// A log of the reads made by a `BitReader` with tracing enabled. Only the outermost read is
+/// recorded, so a string is one entry rather than an entry for each of its bytes.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+	entries: Vec<TraceEntry>,
+	/// Labels of the `BitReader::with_label` calls that are currently running.
+	pub(crate) labels: Vec<&'static str>,
+	/// Amount of traced reads that are currently running.
+	pub(crate) depth: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+	/// Offset of the first bit that was read, counted from the start of the reader.
+	pub offset: usize,
+	pub len: usize,
+	/// Labels that were active during the read, joined with dots, such as `ServerInfo.hostname`.
+	/// Empty if there were none.
+	pub label: String,
+	/// Name of the read method, such as `u16` for `read_u16`.
+	pub read: &'static str,
+	/// The decoded value, usually formatted with `Debug`.
+	pub value: String
+}
+
+impl Trace {
+	pub fn entries(&self) -> &[TraceEntry] {
+		&self.entries
+	}
+
+	pub(crate) fn record(&mut self, offset: usize, len: usize, read: &'static str, value: String) {
+		self.entries.push(TraceEntry {
+			offset,
+			len,
+			label: self.labels.join("."),
+			read,
+			value
+		});
+	}
+
+	/// Forgets the reads that happened at or after `position`, after the reader was rewound.
+	pub(crate) fn rewind(&mut self, position: usize) {
+		while self.entries.last().map(|entry| entry.offset >= position).unwrap_or(false) {
+			self.entries.pop();
+		}
+	}
+
+	/// Renders the log as a table, with one row for each read listing the raw bits that it
+	/// consumed. `bits` must be the same input that the reader was reading from. Bits that were
+	/// never read are listed as `<unread>` rows, so that gaps and leftover data stand out.
+	pub fn render<O>(&self, bits: &BitSlice<O>) -> String where O: BitOrder {
+		let mut out = String::new();
+		let mut cursor = 0;
+
+		row(&mut out, "offset", "bits", "raw", "read", "label", "value");
+
+		for entry in &self.entries {
+			if entry.offset > cursor {
+				unread(&mut out, bits, cursor, entry.offset - cursor);
+			}
+
+			let value = if entry.value.chars().count() > MAX_VALUE_LEN {
+				format!("{}…", entry.value.chars().take(MAX_VALUE_LEN).collect::<String>())
+			} else {
+				entry.value.clone()
+			};
+
+			row(&mut out, entry.offset, entry.len, raw(bits, entry.offset, entry.len), entry.read, &entry.label, value);
+			cursor = cursor.max(entry.offset + entry.len);
+		}
+
+		if bits.bits_len() > cursor {
+			unread(&mut out, bits, cursor, bits.bits_len() - cursor);
+		}
+
+		out
+	}
+}
+
+fn row<O, L, R, V>(out: &mut String, offset: O, len: L, raw: R, read: &str, label: &str, value: V) where O: Display, L: Display, R: Display, V: Display {
+	let line = format!("{:>8} {:>6}  {:<26} {:<12} {:<32} {}", offset, len, raw, read, label, value);
+
+	out.push_str(line.trim_end());
+	out.push('\n');
+}
+
+fn unread<O>(out: &mut String, bits: &BitSlice<O>, offset: usize, len: usize) where O: BitOrder {
+	row(out, offset, len, raw(bits, offset, len), "<unread>", "", "");
+}
+
+/// Formats the bits in the given range: short ranges that are not a whole amount of bytes in
+/// binary, as returned by `BitReader::read_bits`, and everything else as hex bytes.
+fn raw<O>(bits: &BitSlice<O>, offset: usize, len: usize) -> String where O: BitOrder {
+	let mut reader = bits.reader();
+
+	if len == 0 || reader.skip_bits(offset).is_err() {
+		return String::new();
+	}
+
+	if len < 8 || (len <= 24 && len % 8 != 0) {
+		return match reader.read_bits(len as u8) {
+			Ok(value) => format!("{:0width$b}", value, width = len),
+			Err(_) => String::new()
+		};
+	}
+
+	let mut out = String::new();
+	let mut remaining = len;
+
+	for _ in 0..MAX_RAW_BYTES {
+		if remaining == 0 {
+			break;
+		}
+
+		let count = remaining.min(8);
+
+		match reader.read_bits(count as u8) {
+			Ok(byte) => {
+				// Infallible: writing to a String does not fail
+				write!(out, "{:02X} ", byte).unwrap();
+			},
+			Err(_) => break
+		}
+
+		remaining -= count;
+	}
+
+	if remaining > 0 {
+		out.push('…');
+	}
+
+	out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::BitWriter;
+	use super::*;
+
+	#[test]
+	fn records_outermost_reads_with_labels() {
+		let mut writer = BitWriter::new();
+		writer.write_bits(5, 3);
+		writer.write_u16(0xBEEF);
+		writer.write_string("hi");
+		writer.write_u8(0xFF);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+		reader.enable_trace();
+
+		reader.read_bits(3).unwrap();
+
+		reader.with_label("Header", |bits| {
+			bits.with_label("magic", |bits| bits.read_u16()).unwrap();
+			bits.with_label("name", |bits| bits.read_string()).unwrap();
+		});
+
+		// Failed reads are forgotten when the reader is rewound
+		assert!(reader.try_parse(|bits| { bits.read_u8()?; bits.read_u8() }).is_err());
+
+		let trace = reader.trace().unwrap();
+		let entries: Vec<_> = trace.entries().iter().map(|entry| (entry.offset, entry.len, entry.read, entry.label.as_str(), entry.value.as_str())).collect();
+
+		assert_eq!(entries, [
+			(0, 3, "bits", "", "5"),
+			(3, 16, "u16", "Header.magic", "48879"),
+			(19, 24, "string", "Header.name", "\"hi\"")
+		]);
+
+		let annotated = reader.annotate().unwrap();
+		let lines: Vec<&str> = annotated.lines().collect();
+
+		assert_eq!(lines.len(), 5);
+		assert!(lines[1].starts_with("       0      3  101"));
+		assert!(lines[2].contains("EF BE") && lines[2].ends_with("48879"));
+		assert!(lines[4].contains("<unread>") && lines[4].contains("FF"));
+	}
+}
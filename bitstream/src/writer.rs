@@ -0,0 +1,334 @@
+use alloc::vec::Vec;
+use crate::Bits;
+
+/// Largest coordinate that `write_coord` can encode: 14 bits for the integer part minus 1, and
+/// 31/32 for the fraction.
+const MAX_COORD: f32 = 16384.0 + 31.0 / 32.0;
+
+/// Writes data to a stream of bits, using the same little-endian, least-significant-bit-first
+/// order that `BitReader` expects. Every `read_*` method on `BitReader` has a matching `write_*`
+/// method here, so that data read from a demo can be written back out.
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+	data: Vec<u8>,
+	/// Bits used in the last byte of `data`. Zero means that the last byte is completely full.
+	trailing_bits: u8
+}
+
+impl BitWriter {
+	pub fn new() -> Self {
+		BitWriter {
+			data: Vec::new(),
+			trailing_bits: 0
+		}
+	}
+
+	pub fn with_capacity(bytes: usize) -> Self {
+		BitWriter {
+			data: Vec::with_capacity(bytes),
+			trailing_bits: 0
+		}
+	}
+
+	pub fn bits_len(&self) -> usize {
+		if self.trailing_bits != 0 {
+			(self.data.len() - 1) * 8 + (self.trailing_bits as usize)
+		} else {
+			self.data.len() * 8
+		}
+	}
+
+	pub fn is_aligned(&self) -> bool {
+		self.trailing_bits == 0
+	}
+
+	pub fn write_bit(&mut self, bit: bool) {
+		self.write_bits(bit as u32, 1)
+	}
+
+	/// Writes the lowest `count` bits of `value`. Any bits above `count` are ignored.
+	pub fn write_bits(&mut self, value: u32, count: u8) {
+		assert!(count <= 32, "cannot write more than 32 bits to a BitWriter at a time.");
+
+		if count == 0 {
+			return;
+		}
+
+		let mut value = if count == 32 { value } else { value & ((1 << count) - 1) };
+		let mut remaining = count;
+
+		if self.trailing_bits != 0 {
+			let free = 8 - self.trailing_bits;
+
+			// Infallible: trailing_bits is only nonzero if there is at least one byte
+			let last = self.data.last_mut().unwrap();
+			*last |= (value << self.trailing_bits) as u8;
+
+			if remaining < free {
+				self.trailing_bits += remaining;
+				return;
+			}
+
+			value >>= free;
+			remaining -= free;
+			self.trailing_bits = 0;
+		}
+
+		while remaining >= 8 {
+			self.data.push(value as u8);
+			value >>= 8;
+			remaining -= 8;
+		}
+
+		if remaining > 0 {
+			self.data.push(value as u8);
+			self.trailing_bits = remaining;
+		}
+	}
+
+	pub fn write_u8(&mut self, value: u8) {
+		self.write_bits(value as u32, 8)
+	}
+
+	pub fn write_u8_array(&mut self, data: &[u8]) {
+		if self.is_aligned() {
+			self.data.extend_from_slice(data);
+		} else {
+			for &byte in data {
+				self.write_u8(byte);
+			}
+		}
+	}
+
+	pub fn write_u16(&mut self, value: u16) {
+		self.write_bits(value as u32, 16)
+	}
+
+	pub fn write_u32(&mut self, value: u32) {
+		self.write_bits(value, 32)
+	}
+
+	pub fn write_u64(&mut self, value: u64) {
+		self.write_u32(value as u32);
+		self.write_u32((value >> 32) as u32);
+	}
+
+	pub fn write_f32(&mut self, value: f32) {
+		self.write_u32(value.to_bits())
+	}
+
+	pub fn write_i8(&mut self, value: i8) {
+		self.write_u8(value as u8)
+	}
+
+	pub fn write_i16(&mut self, value: i16) {
+		self.write_u16(value as u16)
+	}
+
+	pub fn write_i32(&mut self, value: i32) {
+		self.write_u32(value as u32)
+	}
+
+	pub fn write_i64(&mut self, value: i64) {
+		self.write_u64(value as u64)
+	}
+
+	/// Writes the value using the smallest of the 4 encodings understood by `BitReader::read_var`.
+	pub fn write_var(&mut self, value: u32) {
+		if value < (1 << 4) {
+			self.write_bits(0, 2);
+			self.write_bits(value, 4);
+		} else if value < (1 << 8) {
+			self.write_bits(1, 2);
+			self.write_bits(value, 8);
+		} else if value < (1 << 12) {
+			self.write_bits(2, 2);
+			self.write_bits(value, 12);
+		} else {
+			self.write_bits(3, 2);
+			self.write_u32(value);
+		}
+	}
+
+	/// Writes a coordinate, rounding the fractional part towards zero to the nearest 1/32 of a unit.
+	/// Values with an integer part above 16384 do not fit in the encoding, and are clamped to
+	/// ±16384.96875. NaN is written as 0.
+	pub fn write_coord(&mut self, value: f32) {
+		let sign = value < 0.0;
+		let value = if value.is_nan() { 0.0 } else { value.abs().min(MAX_COORD) };
+
+		let integer = value as u32;
+		let fraction = ((value * 32.0) as u32) & 31;
+
+		self.write_bit(integer != 0);
+		self.write_bit(fraction != 0);
+
+		if integer != 0 || fraction != 0 {
+			self.write_bit(sign);
+
+			if integer != 0 {
+				self.write_bits(integer - 1, 14);
+			}
+
+			if fraction != 0 {
+				self.write_bits(fraction, 5);
+			}
+		}
+	}
+
+	pub fn write_vec3(&mut self, value: (f32, f32, f32)) {
+		// Components too small to be represented as a coordinate are omitted entirely
+		let present = |component: f32| component.abs() >= 0.03125;
+
+		let (x, y, z) = (present(value.0), present(value.1), present(value.2));
+
+		self.write_bit(x);
+		self.write_bit(y);
+		self.write_bit(z);
+
+		if x { self.write_coord(value.0) }
+		if y { self.write_coord(value.1) }
+		if z { self.write_coord(value.2) }
+	}
+
+	/// Writes the string followed by a NUL terminator. The string must not contain any NUL bytes,
+	/// otherwise it will be truncated when read back.
+	pub fn write_string(&mut self, value: &str) {
+		self.write_string_bytes(value.as_bytes())
+	}
+
+	/// Writes raw string bytes followed by a NUL terminator, with the same caveat as `write_string`.
+	pub fn write_string_bytes(&mut self, value: &[u8]) {
+		self.write_u8_array(value);
+		self.write_u8(0);
+	}
+
+	pub fn write_var_u32(&mut self, mut value: u32) {
+		while value >= 128 {
+			self.write_u8((value as u8 & 0x7F) | 0x80);
+			value >>= 7;
+		}
+
+		self.write_u8(value as u8);
+	}
+
+	/// Appends all of the bits contained within `bits`, including any trailing bits.
+	pub fn write_raw_bits(&mut self, bits: &Bits) {
+		let bytes = bits.raw_bytes();
+		let trailing_bits = bits.trailing_bits();
+
+		if trailing_bits == 0 {
+			self.write_u8_array(bytes);
+		} else {
+			let (last, full) = bytes.split_last().unwrap();
+
+			self.write_u8_array(full);
+			self.write_bits(*last as u32, trailing_bits);
+		}
+	}
+
+	pub fn raw_bytes(&self) -> &[u8] {
+		&self.data
+	}
+
+	pub fn finish(self) -> Bits {
+		Bits::from_parts(self.data, self.trailing_bits)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+	use crate::{BitRead, BitReader, Bits};
+	use super::{BitWriter, MAX_COORD};
+
+	#[test]
+	fn round_trip() {
+		let mut writer = BitWriter::new();
+
+		writer.write_bit(true);
+		writer.write_bits(0b101, 3);
+		writer.write_u8(0xAB);
+		writer.write_u16(0xBEEF);
+		writer.write_u32(0xDEAD_BEEF);
+		writer.write_u64(0x0123_4567_89AB_CDEF);
+		writer.write_f32(-1.5);
+		writer.write_i8(-3);
+		writer.write_i16(-300);
+		writer.write_i32(-70000);
+		writer.write_i64(-5_000_000_000);
+		writer.write_var(3);
+		writer.write_var(200);
+		writer.write_var(4000);
+		writer.write_var(100_000);
+		writer.write_coord(-123.5);
+		writer.write_coord(16384.0);
+		writer.write_coord(0.0);
+		writer.write_vec3((1.0, 0.0, -0.25));
+		writer.write_string("hello");
+		writer.write_var_u32(300);
+		writer.write_u8_array(&[1, 2, 3]);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert!(reader.read_bit().unwrap());
+		assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+		assert_eq!(reader.read_u8().unwrap(), 0xAB);
+		assert_eq!(reader.read_u16().unwrap(), 0xBEEF);
+		assert_eq!(reader.read_u32().unwrap(), 0xDEAD_BEEF);
+		assert_eq!(reader.read_u64().unwrap(), 0x0123_4567_89AB_CDEF);
+		assert_eq!(reader.read_f32().unwrap(), -1.5);
+		assert_eq!(reader.read_i8().unwrap(), -3);
+		assert_eq!(reader.read_i16().unwrap(), -300);
+		assert_eq!(reader.read_i32().unwrap(), -70000);
+		assert_eq!(reader.read_i64().unwrap(), -5_000_000_000);
+		assert_eq!(reader.read_var().unwrap(), 3);
+		assert_eq!(reader.read_var().unwrap(), 200);
+		assert_eq!(reader.read_var().unwrap(), 4000);
+		assert_eq!(reader.read_var().unwrap(), 100_000);
+		assert_eq!(reader.read_coord().unwrap(), -123.5);
+		assert_eq!(reader.read_coord().unwrap(), 16384.0);
+		assert_eq!(reader.read_coord().unwrap(), 0.0);
+		assert_eq!(reader.read_vec3().unwrap(), (1.0, 0.0, -0.25));
+		assert_eq!(reader.read_string().unwrap(), "hello");
+		assert_eq!(reader.read_var_u32().unwrap(), 300);
+		assert_eq!(reader.read_u8_array(3).unwrap(), vec![1, 2, 3]);
+		assert_eq!(reader.remaining_bits(), 0);
+	}
+
+	#[test]
+	fn raw_bits_keep_trailing_bits() {
+		let mut writer = BitWriter::new();
+
+		writer.write_bits(1, 3);
+		writer.write_raw_bits(&Bits::from_parts(vec![0xFF, 0b101], 3));
+
+		assert_eq!(writer.bits_len(), 3 + 11);
+
+		let bits = writer.finish();
+		let mut reader = BitReader::new(bits.raw_bytes());
+
+		assert_eq!(reader.read_bits(3).unwrap(), 1);
+		assert_eq!(reader.read_u8().unwrap(), 0xFF);
+		assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+	}
+
+	#[test]
+	fn coords_out_of_range_are_clamped() {
+		let mut writer = BitWriter::new();
+		writer.write_coord(16385.0);
+		writer.write_coord(-1.0e9);
+		writer.write_coord(f32::INFINITY);
+		writer.write_coord(f32::NAN);
+
+		let bits = writer.finish();
+		let mut reader = bits.reader();
+
+		assert_eq!(reader.read_coord().unwrap(), MAX_COORD);
+		assert_eq!(reader.read_coord().unwrap(), -MAX_COORD);
+		assert_eq!(reader.read_coord().unwrap(), MAX_COORD);
+		assert_eq!(reader.read_coord().unwrap(), 0.0);
+		assert_eq!(reader.remaining_bits(), 0);
+	}
+}
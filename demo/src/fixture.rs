@@ -0,0 +1,128 @@
+//! A small demo assembled frame by frame, shared by the tests of the modules that read, index and
+//! rewrite whole demos.
+
+use bitstream::{BitWriter, BitEncode};
+use crate::frame::{DemoProtocol, Frame, FramePayload, Update, UserCmdDelta, PositionUpdate, ConsoleCommandFrame, DataTablesFrame, UserCmdFrame};
+use crate::header::{DemoHeader, DemoHeaderBuf, HEADER_LENGTH, MAGIC, PATH_LENGTH};
+
+pub const TICK_INTERVAL: f32 = 0.015;
+
+/// The header of a TF2 demo that is still being recorded, with the playback fields left at 0.
+pub fn header() -> DemoHeaderBuf {
+	let mut bytes = [0; HEADER_LENGTH];
+
+	bytes[..8].copy_from_slice(MAGIC);
+	bytes[8..12].copy_from_slice(&3i32.to_le_bytes());
+	bytes[12..16].copy_from_slice(&24i32.to_le_bytes());
+
+	let strings: [&[u8]; 4] = [b"Server", b"Player", b"ctf_2fort", b"tf"];
+
+	for (index, string) in strings.iter().enumerate() {
+		let start = 16 + index * PATH_LENGTH;
+
+		bytes[start..start + string.len()].copy_from_slice(string);
+	}
+
+	// Infallible: the magic was written above
+	DemoHeader::parse(&bytes).unwrap().to_owned()
+}
+
+pub fn protocol() -> DemoProtocol {
+	DemoProtocol::from_header(&header().as_header())
+}
+
+/// A `ServerInfo` packet, preceded by its kind ID, as found in the signon data.
+fn server_info() -> Vec<u8> {
+	let mut bits = BitWriter::new();
+
+	bits.write_bits(8, 6);
+	bits.write_u16(24);
+	bits.write_u32(1);
+	bits.write_bit(false);
+	bits.write_bit(true);
+	bits.write_u32(0xFFFF_FFFF);
+	bits.write_u16(300);
+	bits.write_u8_array(&[0; 16]);
+	bits.write_u8(1);
+	bits.write_u8(24);
+	bits.write_f32(TICK_INTERVAL);
+	bits.write_u8(b'L');
+	bits.write_string("tf");
+	bits.write_string("ctf_2fort");
+	bits.write_string("sky_tf2_04");
+	bits.write_string("Server");
+	bits.write_bit(false);
+
+	bits.finish().raw_bytes().to_vec()
+}
+
+fn update(packets: Vec<u8>) -> Update {
+	Update {
+		positions: vec![PositionUpdate::from_bytes([0; PositionUpdate::LEN])],
+		sequence_in: 0,
+		sequence_out: 0,
+		packets
+	}
+}
+
+pub fn user_cmd(delta: &UserCmdDelta) -> UserCmdFrame {
+	let mut bits = BitWriter::new();
+	delta.encode(&mut bits, 0);
+
+	UserCmdFrame::from_raw(bits.finish().raw_bytes().to_vec())
+}
+
+/// A delta that only sets `buttons`.
+pub fn buttons_delta(buttons: u32) -> UserCmdDelta {
+	UserCmdDelta {
+		command_number: None,
+		tick_count: None,
+		view_angles: (None, None, None),
+		forward: None,
+		side: None,
+		up: None,
+		buttons: Some(buttons),
+		impulse: None,
+		weapon_select: None,
+		mouse_delta: (None, None)
+	}
+}
+
+/// Signon data up to tick 0, then updates on ticks 1 and 10, followed by a `Stop` frame.
+pub fn frames() -> Vec<Frame> {
+	let frame = |tick, payload| Frame { tick, player_slot: 0, payload };
+
+	vec![
+		frame(0, FramePayload::SignonUpdate(update(server_info()))),
+		frame(0, FramePayload::DataTables(DataTablesFrame::from_raw(vec![1, 2, 3]))),
+		frame(0, FramePayload::TickSync),
+		frame(1, FramePayload::Update(update(vec![0]))),
+		frame(1, FramePayload::ConsoleCommand(ConsoleCommandFrame::new("+attack"))),
+		frame(1, FramePayload::UserCmdDelta { sequence: 1, frame: user_cmd(&buttons_delta(1)) }),
+		frame(10, FramePayload::Update(update(vec![0]))),
+		frame(10, FramePayload::Stop)
+	]
+}
+
+/// Length of each frame of `frames` once written.
+pub fn frame_lengths() -> Vec<u64> {
+	frames().iter().map(|frame| {
+		let mut bytes = Vec::new();
+		// Infallible: writing to a Vec does not fail
+		frame.write(&mut bytes, protocol()).unwrap();
+
+		bytes.len() as u64
+	}).collect()
+}
+
+/// The header followed by `frames`.
+pub fn demo() -> Vec<u8> {
+	let mut bytes = header().to_bytes().to_vec();
+
+	for frame in frames() {
+		// Infallible: writing to a Vec does not fail
+		frame.write(&mut bytes, protocol()).unwrap();
+	}
+
+	bytes
+}
@@ -0,0 +1,339 @@
+//! The button bits of `UserCmd`, matching the `IN_*` constants of the engine.
+
+use std::fmt::{self, Debug, Formatter};
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Sub};
+use super::UserCmd;
+
+/// A set of buttons held down by a player. The constants cover the buttons shared by all Source
+/// games, and game specific buttons live in modules such as `tf2`. Bits without a name are kept
+/// as they are.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Buttons(pub u32);
+
+impl Buttons {
+	pub const ATTACK:     Buttons = Buttons(1 << 0);
+	pub const JUMP:       Buttons = Buttons(1 << 1);
+	pub const DUCK:       Buttons = Buttons(1 << 2);
+	pub const FORWARD:    Buttons = Buttons(1 << 3);
+	pub const BACK:       Buttons = Buttons(1 << 4);
+	pub const USE:        Buttons = Buttons(1 << 5);
+	pub const CANCEL:     Buttons = Buttons(1 << 6);
+	/// Turning left with the keyboard.
+	pub const LEFT:       Buttons = Buttons(1 << 7);
+	/// Turning right with the keyboard.
+	pub const RIGHT:      Buttons = Buttons(1 << 8);
+	pub const MOVE_LEFT:  Buttons = Buttons(1 << 9);
+	pub const MOVE_RIGHT: Buttons = Buttons(1 << 10);
+	pub const ATTACK2:    Buttons = Buttons(1 << 11);
+	pub const RUN:        Buttons = Buttons(1 << 12);
+	pub const RELOAD:     Buttons = Buttons(1 << 13);
+	pub const ALT1:       Buttons = Buttons(1 << 14);
+	pub const ALT2:       Buttons = Buttons(1 << 15);
+	/// Showing the scoreboard.
+	pub const SCORE:      Buttons = Buttons(1 << 16);
+	pub const SPEED:      Buttons = Buttons(1 << 17);
+	pub const WALK:       Buttons = Buttons(1 << 18);
+	pub const ZOOM:       Buttons = Buttons(1 << 19);
+	pub const WEAPON1:    Buttons = Buttons(1 << 20);
+	pub const WEAPON2:    Buttons = Buttons(1 << 21);
+	pub const BULLRUSH:   Buttons = Buttons(1 << 22);
+	pub const GRENADE1:   Buttons = Buttons(1 << 23);
+	pub const GRENADE2:   Buttons = Buttons(1 << 24);
+
+	/// Names of the buttons shared by all games, used by `names` and the `Debug` output.
+	pub const NAMES: &'static [(&'static str, Buttons)] = &[
+		("attack", Buttons::ATTACK),
+		("jump", Buttons::JUMP),
+		("duck", Buttons::DUCK),
+		("forward", Buttons::FORWARD),
+		("back", Buttons::BACK),
+		("use", Buttons::USE),
+		("cancel", Buttons::CANCEL),
+		("left", Buttons::LEFT),
+		("right", Buttons::RIGHT),
+		("moveleft", Buttons::MOVE_LEFT),
+		("moveright", Buttons::MOVE_RIGHT),
+		("attack2", Buttons::ATTACK2),
+		("run", Buttons::RUN),
+		("reload", Buttons::RELOAD),
+		("alt1", Buttons::ALT1),
+		("alt2", Buttons::ALT2),
+		("score", Buttons::SCORE),
+		("speed", Buttons::SPEED),
+		("walk", Buttons::WALK),
+		("zoom", Buttons::ZOOM),
+		("weapon1", Buttons::WEAPON1),
+		("weapon2", Buttons::WEAPON2),
+		("bullrush", Buttons::BULLRUSH),
+		("grenade1", Buttons::GRENADE1),
+		("grenade2", Buttons::GRENADE2)
+	];
+
+	pub fn empty() -> Self {
+		Buttons(0)
+	}
+
+	pub fn bits(self) -> u32 {
+		self.0
+	}
+
+	pub fn is_empty(self) -> bool {
+		self.0 == 0
+	}
+
+	/// Whether every button in `other` is held.
+	pub fn contains(self, other: Buttons) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	/// Whether any button in `other` is held.
+	pub fn intersects(self, other: Buttons) -> bool {
+		self.0 & other.0 != 0
+	}
+
+	pub fn insert(&mut self, other: Buttons) {
+		self.0 |= other.0;
+	}
+
+	pub fn remove(&mut self, other: Buttons) {
+		self.0 &= !other.0;
+	}
+
+	/// Names of the held buttons, looking in `extensions` after the shared names, for example
+	/// `buttons.names(tf2::NAMES)`. Bits without a name are skipped.
+	pub fn names<'a>(self, extensions: &'a [(&'static str, Buttons)]) -> impl Iterator<Item = &'static str> + 'a {
+		Buttons::NAMES.iter()
+			.chain(extensions)
+			.filter(move |&&(_, button)| self.contains(button))
+			.map(|&(name, _)| name)
+	}
+}
+
+impl Debug for Buttons {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let mut unnamed = self.0;
+
+		write!(f, "Buttons(")?;
+
+		for (index, name) in self.names(&[]).enumerate() {
+			if index != 0 {
+				write!(f, " | ")?;
+			}
+
+			write!(f, "{}", name)?;
+		}
+
+		for &(_, button) in Buttons::NAMES {
+			unnamed &= !button.0;
+		}
+
+		if unnamed != 0 {
+			let separator = if unnamed != self.0 { " | " } else { "" };
+
+			write!(f, "{}{:#x}", separator, unnamed)?;
+		}
+
+		write!(f, ")")
+	}
+}
+
+impl BitOr for Buttons {
+	type Output = Buttons;
+
+	fn bitor(self, rhs: Buttons) -> Buttons {
+		Buttons(self.0 | rhs.0)
+	}
+}
+
+impl BitOrAssign for Buttons {
+	fn bitor_assign(&mut self, rhs: Buttons) {
+		self.0 |= rhs.0;
+	}
+}
+
+impl BitAnd for Buttons {
+	type Output = Buttons;
+
+	fn bitand(self, rhs: Buttons) -> Buttons {
+		Buttons(self.0 & rhs.0)
+	}
+}
+
+impl BitAndAssign for Buttons {
+	fn bitand_assign(&mut self, rhs: Buttons) {
+		self.0 &= rhs.0;
+	}
+}
+
+impl Sub for Buttons {
+	type Output = Buttons;
+
+	fn sub(self, rhs: Buttons) -> Buttons {
+		Buttons(self.0 & !rhs.0)
+	}
+}
+
+impl Not for Buttons {
+	type Output = Buttons;
+
+	fn not(self) -> Buttons {
+		Buttons(!self.0)
+	}
+}
+
+/// Buttons added by Team Fortress 2.
+pub mod tf2 {
+	use super::Buttons;
+
+	/// Special attack, such as the Engineer's rescue ranger pickup.
+	pub const ATTACK3: Buttons = Buttons(1 << 25);
+
+	pub const NAMES: &[(&str, Buttons)] = &[("attack3", ATTACK3)];
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ButtonEdgeKind {
+	Press,
+	Release
+}
+
+/// A single button being pressed or released.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ButtonEdge {
+	/// The `tick_count` of the command where the change happened.
+	pub tick: u32,
+	/// Exactly one button.
+	pub button: Buttons,
+	pub kind: ButtonEdgeKind
+}
+
+/// Turns a sequence of commands into the presses and releases of each button, see `edges`.
+pub struct ButtonEdges<I> {
+	commands: I,
+	held: u32,
+	tick: u32,
+	pressed: u32,
+	released: u32
+}
+
+/// Compares the buttons of each command to the previous one, yielding an edge for every button
+/// that changed. Buttons held in the first command count as pressed. Within a command, releases
+/// come before presses, and both are ordered by bit.
+///
+/// The commands must be full commands, as returned by `UserCmdTracker`, so that a delta without
+/// buttons keeps holding the buttons of the previous command.
+pub fn edges<I>(commands: I) -> ButtonEdges<I::IntoIter> where I: IntoIterator<Item = UserCmd> {
+	ButtonEdges {
+		commands: commands.into_iter(),
+		held: 0,
+		tick: 0,
+		pressed: 0,
+		released: 0
+	}
+}
+
+impl<I> Iterator for ButtonEdges<I> where I: Iterator<Item = UserCmd> {
+	type Item = ButtonEdge;
+
+	fn next(&mut self) -> Option<ButtonEdge> {
+		loop {
+			for (bits, kind) in [(&mut self.released, ButtonEdgeKind::Release), (&mut self.pressed, ButtonEdgeKind::Press)] {
+				if *bits != 0 {
+					let lowest = *bits & bits.wrapping_neg();
+					*bits &= !lowest;
+
+					return Some(ButtonEdge { tick: self.tick, button: Buttons(lowest), kind });
+				}
+			}
+
+			let command = self.commands.next()?;
+			let buttons = command.buttons.0;
+
+			self.pressed = buttons & !self.held;
+			self.released = self.held & !buttons;
+			self.held = buttons;
+			self.tick = command.tick_count;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::frame::{UserCmdDelta, UserCmdTracker};
+	use super::*;
+
+	fn delta(tick_count: u32, buttons: Option<Buttons>) -> UserCmdDelta {
+		UserCmdDelta {
+			command_number: None,
+			tick_count: Some(tick_count),
+			view_angles: (None, None, None),
+			forward: None,
+			side: None,
+			up: None,
+			buttons: buttons.map(Buttons::bits),
+			impulse: None,
+			weapon_select: None,
+			mouse_delta: (None, None)
+		}
+	}
+
+	fn edge(tick: u32, button: Buttons, kind: ButtonEdgeKind) -> ButtonEdge {
+		ButtonEdge { tick, button, kind }
+	}
+
+	#[test]
+	fn omitted_buttons_stay_held() {
+		let deltas = [
+			delta(10, Some(Buttons::JUMP | Buttons::DUCK)),
+			delta(11, None),
+			delta(12, Some(Buttons::DUCK)),
+			delta(13, Some(Buttons::ATTACK))
+		];
+
+		let mut tracker = UserCmdTracker::new();
+		let commands: Vec<_> = deltas.iter().enumerate().filter_map(|(sequence, delta)| tracker.apply(sequence as u32, delta)).collect();
+
+		let edges: Vec<_> = edges(commands).collect();
+
+		assert_eq!(edges, [
+			edge(10, Buttons::JUMP, ButtonEdgeKind::Press),
+			edge(10, Buttons::DUCK, ButtonEdgeKind::Press),
+			edge(12, Buttons::JUMP, ButtonEdgeKind::Release),
+			edge(13, Buttons::DUCK, ButtonEdgeKind::Release),
+			edge(13, Buttons::ATTACK, ButtonEdgeKind::Press)
+		]);
+	}
+
+	#[test]
+	fn game_specific_buttons_have_edges_and_names() {
+		let held = Buttons::ATTACK | tf2::ATTACK3;
+		let commands = vec![held, Buttons::empty()].into_iter().enumerate().map(|(tick, buttons)| {
+			UserCmd { tick_count: tick as u32, buttons, ..UserCmd::default() }
+		});
+
+		let edges: Vec<_> = edges(commands).filter(|edge| edge.button == tf2::ATTACK3).collect();
+
+		assert_eq!(edges, [edge(0, tf2::ATTACK3, ButtonEdgeKind::Press), edge(1, tf2::ATTACK3, ButtonEdgeKind::Release)]);
+		assert_eq!(tf2::ATTACK3.bits(), 1 << 25);
+		assert_eq!(held.names(tf2::NAMES).collect::<Vec<_>>(), ["attack", "attack3"]);
+		assert_eq!(format!("{:?}", held), "Buttons(attack | 0x2000000)");
+		assert_eq!(format!("{:?}", tf2::ATTACK3), "Buttons(0x2000000)");
+	}
+
+	#[test]
+	fn set_operations() {
+		let mut buttons = Buttons::FORWARD | Buttons::SPEED;
+
+		assert!(buttons.contains(Buttons::FORWARD));
+		assert!(!buttons.contains(Buttons::FORWARD | Buttons::BACK));
+		assert!(buttons.intersects(Buttons::FORWARD | Buttons::BACK));
+
+		buttons.remove(Buttons::SPEED);
+		buttons.insert(Buttons::WALK);
+
+		assert_eq!(buttons, Buttons::FORWARD | Buttons::WALK);
+		assert_eq!(buttons - Buttons::FORWARD, Buttons::WALK);
+		assert_eq!(buttons & !Buttons::WALK, Buttons::FORWARD);
+		assert_eq!(format!("{:?}", Buttons::empty()), "Buttons()");
+	}
+}
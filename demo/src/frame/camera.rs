@@ -0,0 +1,178 @@
+use super::{Frame, FramePayload, PositionFlags};
+
+/// The effective camera of a player at a tick, see `PositionUpdate::effective`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraSample {
+	pub tick: u32,
+	pub origin: (f32, f32, f32),
+	pub angles: (f32, f32, f32),
+	pub local_angles: (f32, f32, f32),
+	/// False if the camera jumped to this position, so it should not be interpolated from the
+	/// previous sample.
+	pub interpolate: bool
+}
+
+/// The path of the camera of a single split screen player, with one sample per tick, built from
+/// the positions recorded in update frames.
+#[derive(Debug, Clone)]
+pub struct CameraTrajectory {
+	slot: usize,
+	samples: Vec<CameraSample>
+}
+
+impl CameraTrajectory {
+	/// Creates an empty trajectory for the player in the given split screen slot. Slot 0 is the
+	/// player that recorded the demo.
+	pub fn new(slot: usize) -> Self {
+		CameraTrajectory { slot, samples: Vec::new() }
+	}
+
+	pub fn from_frames<'f, I>(frames: I, slot: usize) -> Self where I: IntoIterator<Item = &'f Frame> {
+		let mut trajectory = CameraTrajectory::new(slot);
+
+		for frame in frames {
+			trajectory.record(frame);
+		}
+
+		trajectory
+	}
+
+	/// Adds the camera position of an `Update` or `SignonUpdate` frame, ignoring all other
+	/// frames. If there already is a sample for the tick of the frame, it is replaced.
+	///
+	/// The samples are kept sorted by tick. When the tick goes back, as it does when the server
+	/// changes the level, the samples at or after the new tick are dropped, so the trajectory
+	/// starts over from there.
+	pub fn record(&mut self, frame: &Frame) {
+		let update = match frame.payload {
+			FramePayload::SignonUpdate(ref update) | FramePayload::Update(ref update) => update,
+			_ => return
+		};
+
+		let position = match update.positions.get(self.slot) {
+			Some(position) => position,
+			None => return
+		};
+
+		let view = position.effective();
+
+		let sample = CameraSample {
+			tick: frame.tick,
+			origin: view.view_origin,
+			angles: view.view_angles,
+			local_angles: view.view_angles_local,
+			interpolate: !position.flags.contains(PositionFlags::NOINTERP)
+		};
+
+		let kept = self.samples.partition_point(|kept| kept.tick < sample.tick);
+
+		self.samples.truncate(kept);
+		self.samples.push(sample);
+	}
+
+	pub fn slot(&self) -> usize {
+		self.slot
+	}
+
+	pub fn samples(&self) -> &[CameraSample] {
+		&self.samples
+	}
+
+	/// The last sample at or before `tick`, if any. Samples from before the tick last went back
+	/// are not kept, see `record`.
+	pub fn at(&self, tick: u32) -> Option<&CameraSample> {
+		let after = self.samples.partition_point(|sample| sample.tick <= tick);
+
+		after.checked_sub(1).map(|index| &self.samples[index])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::frame::{Position, PositionUpdate, Update};
+	use super::*;
+
+	fn position(value: f32) -> Position {
+		Position {
+			view_origin: (value, 0.0, 0.0),
+			view_angles: (0.0, value, 0.0),
+			view_angles_local: (0.0, 0.0, value)
+		}
+	}
+
+	fn update(tick: u32, flags: PositionFlags, values: &[f32]) -> Frame {
+		let positions = values.iter().map(|&value| PositionUpdate { flags, original: position(value), resampled: position(-value) }).collect();
+
+		Frame {
+			tick,
+			player_slot: 0,
+			payload: FramePayload::Update(Update { positions, sequence_in: 0, sequence_out: 0, packets: Vec::new() })
+		}
+	}
+
+	#[test]
+	fn effective_view_follows_the_flags() {
+		let update = PositionUpdate { flags: PositionFlags::USE_ORIGIN2, original: position(1.0), resampled: position(2.0) };
+		let view = update.effective();
+
+		assert_eq!((view.view_origin, view.view_angles, view.view_angles_local), ((2.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)));
+
+		let update = PositionUpdate { flags: PositionFlags::USE_ANGLES2, ..update };
+		let view = update.effective();
+
+		assert_eq!((view.view_origin, view.view_angles, view.view_angles_local), ((1.0, 0.0, 0.0), (0.0, 2.0, 0.0), (0.0, 0.0, 2.0)));
+
+		let mut bytes = Vec::new();
+		update.write(&mut bytes).unwrap();
+
+		let mut array = [0; PositionUpdate::LEN];
+		array.copy_from_slice(&bytes);
+
+		let parsed = PositionUpdate::from_bytes(array);
+		assert_eq!(parsed.flags, PositionFlags::USE_ANGLES2);
+		assert_eq!(parsed.resampled.view_angles_local, (0.0, 0.0, 2.0));
+	}
+
+	#[test]
+	fn trajectories_keep_one_sample_per_tick() {
+		let frames = [
+			update(1, PositionFlags::default(), &[1.0, 10.0]),
+			Frame { tick: 2, player_slot: 0, payload: FramePayload::TickSync },
+			update(3, PositionFlags::default(), &[2.0, 20.0]),
+			update(3, PositionFlags(PositionFlags::NOINTERP.0 | PositionFlags::USE_ORIGIN2.0), &[3.0, 30.0]),
+			update(5, PositionFlags::default(), &[4.0])
+		];
+
+		let trajectory = CameraTrajectory::from_frames(&frames, 1);
+		let samples = trajectory.samples();
+
+		assert_eq!(trajectory.slot(), 1);
+		assert_eq!(samples.len(), 2);
+		assert_eq!((samples[0].tick, samples[0].origin, samples[0].interpolate), (1, (10.0, 0.0, 0.0), true));
+		assert_eq!((samples[1].tick, samples[1].origin, samples[1].interpolate), (3, (-30.0, 0.0, 0.0), false));
+		assert_eq!(samples[1].angles, (0.0, 30.0, 0.0));
+
+		assert!(trajectory.at(0).is_none());
+		assert_eq!(trajectory.at(2).unwrap().tick, 1);
+		assert_eq!(trajectory.at(100).unwrap().tick, 3);
+		assert_eq!(CameraTrajectory::from_frames(&frames, 0).samples().len(), 3);
+	}
+
+	#[test]
+	fn trajectories_start_over_when_the_tick_goes_back() {
+		let frames = [
+			update(10, PositionFlags::default(), &[1.0]),
+			update(20, PositionFlags::default(), &[2.0]),
+			update(30, PositionFlags::default(), &[3.0]),
+			update(15, PositionFlags::default(), &[4.0]),
+			update(25, PositionFlags::default(), &[5.0])
+		];
+
+		let trajectory = CameraTrajectory::from_frames(&frames, 0);
+		let ticks: Vec<_> = trajectory.samples().iter().map(|sample| sample.tick).collect();
+
+		assert_eq!(ticks, [10, 15, 25]);
+		assert_eq!(trajectory.at(20).unwrap().origin, (4.0, 0.0, 0.0));
+		assert_eq!(trajectory.at(100).unwrap().origin, (5.0, 0.0, 0.0));
+	}
+}
@@ -0,0 +1,397 @@
+//! Splitting the text of `ConsoleCommand` frames into commands and arguments, following the rules
+//! of the engine's command buffer.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Characters that form a token on their own when not quoted, like the default break set of
+/// `CCommand::Tokenize`.
+const BREAK_SET: &[u8] = b"{}()':";
+
+/// A single console command with its arguments. Quoted arguments are stored without the quotes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConsoleCommand {
+	pub name: String,
+	pub args: Vec<String>,
+	/// The text after the name, as it was typed, like `CCommand::ArgS`. Commands such as `say`
+	/// use this instead of the split arguments.
+	pub arg_string: String
+}
+
+/// Splits `text` into commands at `;` and newlines that are not within quotes. Everything after
+/// `//` up to the end of the line is a comment, and empty commands are skipped.
+pub fn parse(text: &str) -> Vec<ConsoleCommand> {
+	let bytes = text.as_bytes();
+	let mut commands = Vec::new();
+
+	let mut start = 0;
+	let mut index = 0;
+	let mut quoted = false;
+
+	while index < bytes.len() {
+		match bytes[index] {
+			b'"' => quoted = !quoted,
+			b'\n' | b';' if !quoted => {
+				commands.extend(ConsoleCommand::parse(&text[start..index]));
+				start = index + 1;
+			},
+			b'/' if !quoted && bytes.get(index + 1) == Some(&b'/') => {
+				commands.extend(ConsoleCommand::parse(&text[start..index]));
+
+				while index < bytes.len() && bytes[index] != b'\n' {
+					index += 1;
+				}
+
+				start = index + 1;
+			},
+			_ => ()
+		}
+
+		index += 1;
+	}
+
+	if start < bytes.len() {
+		commands.extend(ConsoleCommand::parse(&text[start..]));
+	}
+
+	commands
+}
+
+/// Pairs each `+action` with the `-action` that releases it, given the commands along with the
+/// ticks of their frames. Pressing an action that is already held and releasing one that is not
+/// held are ignored, like the repeats of a key. Actions are returned in the order they were
+/// pressed.
+pub fn held_actions<'c, I>(commands: I) -> Vec<HeldAction> where I: IntoIterator<Item = (u32, &'c ConsoleCommand)> {
+	let mut actions: Vec<HeldAction> = Vec::new();
+	// Indices of the actions that have not been released yet
+	let mut held: Vec<usize> = Vec::new();
+
+	for (tick, command) in commands {
+		let action = match command.action() {
+			Some(action) => action,
+			None => continue
+		};
+
+		let position = held.iter().position(|&index| actions[index].name.eq_ignore_ascii_case(action.name));
+
+		match (action.pressed, position) {
+			(true, None) => {
+				held.push(actions.len());
+				actions.push(HeldAction { name: action.name.to_string(), pressed: tick, released: None });
+			},
+			(false, Some(position)) => {
+				let index = held.swap_remove(position);
+				actions[index].released = Some(tick);
+			},
+			_ => ()
+		}
+	}
+
+	actions
+}
+
+impl ConsoleCommand {
+	/// Tokenizes a single command, which should already be split from the others. Returns None if
+	/// there are no tokens.
+	pub fn parse(command: &str) -> Option<Self> {
+		let mut tokens = Tokens { text: command, position: 0 };
+
+		let name = tokens.next()?;
+		let arg_string = command[tokens.position..].trim().to_string();
+
+		Some(ConsoleCommand {
+			name,
+			args: tokens.collect(),
+			arg_string
+		})
+	}
+
+	/// Whether the command has this name. Names are not case sensitive, just like in the game.
+	pub fn is(&self, name: &str) -> bool {
+		self.name.eq_ignore_ascii_case(name)
+	}
+
+	pub fn arg(&self, index: usize) -> Option<&str> {
+		self.args.get(index).map(String::as_str)
+	}
+
+	/// A `+name` or `-name` command, as sent when a key bound to an action is pressed or released.
+	pub fn action(&self) -> Option<Action<'_>> {
+		let (pressed, name) = if let Some(name) = self.name.strip_prefix('+') {
+			(true, name)
+		} else if let Some(name) = self.name.strip_prefix('-') {
+			(false, name)
+		} else {
+			return None;
+		};
+
+		if name.is_empty() {
+			return None;
+		}
+
+		Some(Action { name, pressed })
+	}
+
+	/// A `bind key command` command. If there are several arguments after the key, they are joined
+	/// with spaces, like the game does.
+	pub fn bind(&self) -> Option<Bind<'_>> {
+		if !self.is("bind") {
+			return None;
+		}
+
+		let key = self.arg(0)?;
+		let command = if self.args.len() > 1 { Some(self.args[1..].join(" ")) } else { None };
+
+		Some(Bind { key, command })
+	}
+
+	/// The config file run by an `exec` command.
+	pub fn exec(&self) -> Option<&str> {
+		if !self.is("exec") {
+			return None;
+		}
+
+		self.arg(0)
+	}
+
+	/// A `say` or `say_team` chat message.
+	pub fn chat(&self) -> Option<Chat<'_>> {
+		let team = if self.is("say") {
+			false
+		} else if self.is("say_team") {
+			true
+		} else {
+			return None;
+		};
+
+		// The game strips the quotes around the whole message, but leaves any others alone
+		let message = self.arg_string.as_str();
+		let message = match message.strip_prefix('"').and_then(|inner| inner.strip_suffix('"')) {
+			Some(inner) => inner,
+			None => message
+		};
+
+		Some(Chat { message, team })
+	}
+
+	/// A command that controls demo playback or marks a point of interest: `demo_*` commands and
+	/// the `ds_mark` bookmarks of CS:GO.
+	pub fn marker(&self) -> Option<Marker<'_>> {
+		if self.is("ds_mark") {
+			return Some(Marker::Bookmark(self.arg(0)));
+		}
+
+		let prefix = self.name.get(..5)?;
+
+		if !prefix.eq_ignore_ascii_case("demo_") {
+			return None;
+		}
+
+		Some(Marker::Demo {
+			command: &self.name[5..],
+			args: &self.args
+		})
+	}
+}
+
+/// Writes the command so that parsing it again gives the same name and arguments, quoting the
+/// tokens that need it.
+impl Display for ConsoleCommand {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write_token(f, &self.name)?;
+
+		for arg in &self.args {
+			write!(f, " ")?;
+			write_token(f, arg)?;
+		}
+
+		Ok(())
+	}
+}
+
+fn write_token(f: &mut Formatter, token: &str) -> fmt::Result {
+	let plain = !token.is_empty() && token.bytes().all(|byte| byte > b' ' && byte != b'"' && byte != b';' && !BREAK_SET.contains(&byte)) && !token.contains("//");
+
+	if plain {
+		write!(f, "{}", token)
+	} else {
+		write!(f, "\"{}\"", token)
+	}
+}
+
+/// A button style action, such as `+attack` or `-duck`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Action<'c> {
+	/// Name without the `+` or `-`.
+	pub name: &'c str,
+	pub pressed: bool
+}
+
+/// An action from the tick of its `+` command to the tick of its `-` command, see `held_actions`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HeldAction {
+	/// Name without the `+` or `-`, as it was typed when pressed.
+	pub name: String,
+	pub pressed: u32,
+	/// None if the action was still held at the end.
+	pub released: Option<u32>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Bind<'c> {
+	pub key: &'c str,
+	/// None if the command only asks for the current binding of the key.
+	pub command: Option<String>
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Chat<'c> {
+	pub message: &'c str,
+	/// Whether the message was only sent to the team of the player.
+	pub team: bool
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Marker<'c> {
+	/// A `demo_*` command, with the name after the prefix, such as `pause` or `gototick`.
+	Demo {
+		command: &'c str,
+		args: &'c [String]
+	},
+	/// A `ds_mark` bookmark with its optional description.
+	Bookmark(Option<&'c str>)
+}
+
+/// Reads tokens the same way as `CCommand::Tokenize`: whitespace separates tokens, quotes group
+/// them, break characters stand alone, and `//` ends the command.
+struct Tokens<'t> {
+	text: &'t str,
+	position: usize
+}
+
+impl Iterator for Tokens<'_> {
+	type Item = String;
+
+	fn next(&mut self) -> Option<String> {
+		let bytes = self.text.as_bytes();
+
+		while self.position < bytes.len() && bytes[self.position] <= b' ' {
+			self.position += 1;
+		}
+
+		let rest = &bytes[self.position..];
+		let start = self.position;
+
+		match rest.first()? {
+			b'/' if rest.get(1) == Some(&b'/') => {
+				self.position = bytes.len();
+
+				None
+			},
+			b'"' => {
+				// An unterminated quote continues to the end of the command
+				let len = rest[1..].iter().position(|&byte| byte == b'"').unwrap_or(rest.len() - 1);
+				self.position += (len + 2).min(rest.len());
+
+				Some(self.text[start + 1..start + 1 + len].to_string())
+			},
+			byte if BREAK_SET.contains(byte) => {
+				self.position += 1;
+
+				Some(self.text[start..start + 1].to_string())
+			},
+			_ => {
+				let len = rest.iter().position(|&byte| byte <= b' ' || BREAK_SET.contains(&byte)).unwrap_or(rest.len());
+				self.position += len;
+
+				Some(self.text[start..start + len].to_string())
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tokens(text: &str) -> Vec<String> {
+		Tokens { text, position: 0 }.collect()
+	}
+
+	#[test]
+	fn break_characters_are_tokens_of_their_own() {
+		assert_eq!(tokens("alias{a}(b)'c':d"), ["alias", "{", "a", "}", "(", "b", ")", "'", "c", "'", ":", "d"]);
+		assert_eq!(tokens("\"{}()':\" x"), ["{}()':", "x"]);
+	}
+
+	#[test]
+	fn unterminated_quotes_run_to_the_end() {
+		assert_eq!(tokens("say \"hello there"), ["say", "hello there"]);
+		assert_eq!(tokens("echo \""), ["echo", ""]);
+		assert_eq!(parse("say \"a;b"), [ConsoleCommand::parse("say \"a;b").unwrap()]);
+	}
+
+	#[test]
+	fn comments_end_the_command() {
+		assert_eq!(tokens("echo a//b c"), ["echo", "a//b", "c"]);
+		assert_eq!(tokens("echo a //b c"), ["echo", "a"]);
+		assert_eq!(tokens("echo \"//b\""), ["echo", "//b"]);
+
+		let commands = parse("echo a // not; a command\nexec autoexec.cfg");
+
+		assert_eq!(commands.len(), 2);
+		assert_eq!(commands[0].args, ["a"]);
+		assert_eq!(commands[1].exec(), Some("autoexec.cfg"));
+	}
+
+	#[test]
+	fn separators_within_quotes_are_kept() {
+		let commands = parse("say \"gg; wp\";say_team \"rush b\"\n\n;");
+
+		assert_eq!(commands.len(), 2);
+		assert_eq!(commands[0].chat(), Some(Chat { message: "gg; wp", team: false }));
+		assert_eq!(commands[1].chat(), Some(Chat { message: "rush b", team: true }));
+	}
+
+	#[test]
+	fn commands_are_classified() {
+		let command = ConsoleCommand::parse("BIND mouse1 +attack extra").unwrap();
+		assert_eq!(command.bind(), Some(Bind { key: "mouse1", command: Some("+attack extra".to_string()) }));
+
+		let command = ConsoleCommand::parse("-duck").unwrap();
+		assert_eq!(command.action(), Some(Action { name: "duck", pressed: false }));
+		assert!(ConsoleCommand::parse("+").unwrap().action().is_none());
+
+		let command = ConsoleCommand::parse("demo_gototick 500 0").unwrap();
+		assert_eq!(command.marker(), Some(Marker::Demo { command: "gototick", args: &command.args }));
+		assert_eq!(ConsoleCommand::parse("ds_mark").unwrap().marker(), Some(Marker::Bookmark(None)));
+	}
+
+	#[test]
+	fn displayed_commands_parse_the_same() {
+		let command = ConsoleCommand::parse("bind \"k\" \"say hi; kill\" {x}").unwrap();
+		let again = ConsoleCommand::parse(&command.to_string()).unwrap();
+
+		assert_eq!((again.name, again.args), (command.name, command.args));
+	}
+
+	#[test]
+	fn actions_are_paired_with_their_releases() {
+		let commands = [
+			(1, "+attack"),
+			(2, "+jump"),
+			(3, "+attack"),
+			(4, "-ATTACK"),
+			(5, "-duck"),
+			(6, "+attack"),
+			(7, "-jump"),
+			(8, "say +duck")
+		];
+
+		let commands: Vec<_> = commands.iter().map(|&(tick, text)| (tick, ConsoleCommand::parse(text).unwrap())).collect();
+		let held = held_actions(commands.iter().map(|(tick, command)| (*tick, command)));
+
+		let action = |name: &str, pressed, released| HeldAction { name: name.to_string(), pressed, released };
+
+		assert_eq!(held, [action("attack", 1, Some(4)), action("jump", 2, Some(7)), action("attack", 6, None)]);
+	}
+}
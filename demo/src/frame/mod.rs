@@ -1,14 +1,57 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use bitstream::{InsufficientBits, BitReader, BitParseError};
+use std::str::{self, Utf8Error};
 use std::string::FromUtf8Error;
 use crate::string_table::{StringTables, InvalidHistoryIndex, DecompressionError, StringTableParseError};
 use crate::data_table::{DataTableParseError, DataTables};
+use crate::header::DemoHeader;
 
+pub mod buttons;
+mod camera;
+pub mod console;
 mod usercmd;
 
-use usercmd::{UserCmdDelta, PositionUpdate};
+pub use buttons::Buttons;
+pub use camera::{CameraSample, CameraTrajectory};
+pub use console::ConsoleCommand;
+pub use usercmd::{UserCmd, UserCmdDelta, UserCmdTracker, WeaponSelect, PositionFlags, PositionUpdate, Position};
+
+/// Version of the demo file format, which determines how frames are laid out. This is separate
+/// from the network protocol of the packets within the frames.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DemoProtocol {
+	pub version: u32,
+	/// Amount of `PositionUpdate`s in each update frame, one for each split screen player.
+	pub split_screen_slots: usize
+}
 
-// TODO: NetProto 36+: CustomData frame
+impl DemoProtocol {
+	/// Assumes the split screen layout of the game most commonly seen with the version: 2 slots
+	/// for demo protocol 4 (Portal 2, CS:GO), and 1 slot for older versions.
+	pub fn new(version: u32) -> Self {
+		DemoProtocol {
+			version,
+			split_screen_slots: if version >= 4 { 2 } else { 1 }
+		}
+	}
+
+	/// Picks the protocol for the demo with this header. Left 4 Dead and Left 4 Dead 2 record 4
+	/// split screen slots, unlike the other demo protocol 4 games.
+	pub fn from_header(header: &DemoHeader) -> Self {
+		let mut protocol = DemoProtocol::new(header.demo_protocol as u32);
+
+		if protocol.version >= 4 && header.game_directory.str_bytes().starts_with(b"left4dead") {
+			protocol.split_screen_slots = 4;
+		}
+
+		protocol
+	}
+
+	/// Starting with demo protocol 4, each frame records the split screen player it belongs to.
+	pub fn has_player_slot(self) -> bool {
+		self.version >= 4
+	}
+}
 
 #[derive(Debug)]
 pub enum FrameParseError {
@@ -72,23 +115,44 @@ pub enum FrameKind {
 	UserCmdDelta,
 	DataTables,
 	Stop,
+	/// Only in demo protocol 4 and later.
+	CustomData,
 	StringTables
 }
 
 impl FrameKind {
-	fn from_id(id: u8) -> Option<Self> {
-		Some(match id {
-			1 => FrameKind::SignonUpdate,
-			2 => FrameKind::Update,
-			3 => FrameKind::TickSync,
-			4 => FrameKind::ConsoleCommand,
-			5 => FrameKind::UserCmdDelta,
-			6 => FrameKind::DataTables,
-			7 => FrameKind::Stop,
-			8 => FrameKind::StringTables,
+	pub fn from_id(id: u8, protocol: DemoProtocol) -> Option<Self> {
+		Some(match (id, protocol.version >= 4) {
+			(1, _)     => FrameKind::SignonUpdate,
+			(2, _)     => FrameKind::Update,
+			(3, _)     => FrameKind::TickSync,
+			(4, _)     => FrameKind::ConsoleCommand,
+			(5, _)     => FrameKind::UserCmdDelta,
+			(6, _)     => FrameKind::DataTables,
+			(7, _)     => FrameKind::Stop,
+			(8, false) => FrameKind::StringTables,
+			(8, true)  => FrameKind::CustomData,
+			(9, true)  => FrameKind::StringTables,
 			_ => return None
 		})
 	}
+
+	/// Returns None for kinds that do not exist in the given protocol.
+	pub fn id(self, protocol: DemoProtocol) -> Option<u8> {
+		let modern = protocol.version >= 4;
+
+		Some(match self {
+			FrameKind::SignonUpdate   => 1,
+			FrameKind::Update         => 2,
+			FrameKind::TickSync       => 3,
+			FrameKind::ConsoleCommand => 4,
+			FrameKind::UserCmdDelta   => 5,
+			FrameKind::DataTables     => 6,
+			FrameKind::Stop           => 7,
+			FrameKind::CustomData     => if modern { 8 } else { return None },
+			FrameKind::StringTables   => if modern { 9 } else { 8 }
+		})
+	}
 }
 
 fn read_u8<R>(input: &mut R) -> Result<u8, io::Error> where R: Read {
@@ -129,60 +193,166 @@ fn read_u8_array<R>(input: &mut R) -> Result<Vec<u8>, io::Error> where R: Read {
 	Ok(buf)
 }
 
+/// Reads the tick and player slot that follow the kind ID.
+fn read_frame_header<R>(input: &mut R, kind: FrameKind, protocol: DemoProtocol) -> Result<(u32, u8), io::Error> where R: Read {
+	let tick = if kind == FrameKind::Stop && !protocol.has_player_slot() {
+		read_u24(input)?
+	} else {
+		read_u32(input)?
+	};
+
+	let player_slot = if protocol.has_player_slot() {
+		read_u8(input)?
+	} else {
+		0
+	};
+
+	Ok((tick, player_slot))
+}
+
+/// Seeks past a length prefixed array, returning the amount of bytes skipped.
+fn skip_u8_array<R>(input: &mut R) -> Result<u64, io::Error> where R: Read + Seek {
+	let len = read_u32(input)?;
+
+	input.seek(SeekFrom::Current(len as i64))?;
+
+	Ok(4 + len as u64)
+}
+
+fn write_u24<W>(output: &mut W, value: u32) -> Result<(), io::Error> where W: Write {
+	// Only the lowest 3 bytes are written, see read_u24
+	output.write_all(&value.to_le_bytes()[0..3])
+}
+
+fn write_u32<W>(output: &mut W, value: u32) -> Result<(), io::Error> where W: Write {
+	output.write_all(&value.to_le_bytes())
+}
+
+fn write_u8_array<W>(output: &mut W, data: &[u8]) -> Result<(), io::Error> where W: Write {
+	write_u32(output, data.len() as u32)?;
+
+	output.write_all(data)
+}
+
+/// Checks that a payload was parsed up to its last byte. Padding bits within the last byte are
+/// allowed, since payloads are stored as whole bytes.
+fn check_consumed(bits: &BitReader) -> Result<(), BitParseError> {
+	if bits.unread_bytes() != 0 {
+		return Err(BitParseError::Invalid("bytes left over after the end of the payload"));
+	}
+
+	Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct Frame {
 	pub tick: u32,
+	/// Split screen player that the frame belongs to. Always 0 before demo protocol 4.
+	pub player_slot: u8,
 	pub payload: FramePayload
 }
 
 impl Frame {
-	pub fn parse<R>(input: &mut R) -> Result<Self, FrameParseError> where R: Read {
+	pub fn parse<R>(input: &mut R, protocol: DemoProtocol) -> Result<Self, FrameParseError> where R: Read {
 		let kind_id = read_u8(input)?;
-		let kind = FrameKind::from_id(kind_id).ok_or(FrameParseError::BadFrameKind { kind_id })?;
+		let kind = FrameKind::from_id(kind_id, protocol).ok_or(FrameParseError::BadFrameKind { kind_id })?;
 
-		Frame::parse_with_kind(input, kind)
+		Frame::parse_with_kind(input, kind, protocol)
 	}
 
-	pub fn parse_with_kind<R>(input: &mut R, kind: FrameKind) -> Result<Self, FrameParseError> where R: Read {
-		let tick = if kind == FrameKind::Stop {
-			read_u24(input)?
-		} else {
-			read_u32(input)?
-		};
+	pub fn parse_with_kind<R>(input: &mut R, kind: FrameKind, protocol: DemoProtocol) -> Result<Self, FrameParseError> where R: Read {
+		let (tick, player_slot) = read_frame_header(input, kind, protocol)?;
 
 		let payload = match kind {
-			FrameKind::SignonUpdate   => FramePayload::SignonUpdate(Update::parse(input)?),
-			FrameKind::Update         => FramePayload::Update(Update::parse(input)?),
+			FrameKind::SignonUpdate   => FramePayload::SignonUpdate(Update::parse(input, protocol)?),
+			FrameKind::Update         => FramePayload::Update(Update::parse(input, protocol)?),
 			FrameKind::TickSync       => FramePayload::TickSync,
-			FrameKind::ConsoleCommand => {
-				let mut buf = read_u8_array(input)?;
-
-				let mut terminator = None;
-				for (index, &byte) in buf.iter().enumerate() {
-					if byte == 0 {
-						terminator = Some(index);
-						break;
-					}
-				}
-
-				if let Some(terminator) = terminator {
-					for _ in 0..(buf.len() - terminator) {
-						buf.pop();
-					}
-				}
-
-				FramePayload::ConsoleCommand(String::from_utf8(buf)?)
-			},
+			FrameKind::ConsoleCommand => FramePayload::ConsoleCommand(ConsoleCommandFrame::from_raw(read_u8_array(input)?)),
 			FrameKind::UserCmdDelta => FramePayload::UserCmdDelta {
 				sequence: read_u32(input)?,
 				frame: UserCmdFrame::from_raw(read_u8_array(input)?)
 			},
 			FrameKind::DataTables => FramePayload::DataTables(DataTablesFrame::from_raw(read_u8_array(input)?)),
 			FrameKind::Stop => FramePayload::Stop,
+			FrameKind::CustomData => FramePayload::CustomData {
+				callback: read_u32(input)?,
+				data: read_u8_array(input)?
+			},
 			FrameKind::StringTables => FramePayload::StringTables(StringTablesFrame::from_raw(read_u8_array(input)?))
 		};
 
-		Ok(Frame { tick, payload })
+		Ok(Frame { tick, player_slot, payload })
+	}
+
+	/// Skips over the rest of a frame after its kind ID without decoding the payload, using the
+	/// length prefixes to seek past it. Returns the tick of the frame and the amount of bytes that
+	/// were skipped. The input is not checked to be long enough to contain the skipped bytes.
+	pub fn skip_with_kind<R>(input: &mut R, kind: FrameKind, protocol: DemoProtocol) -> Result<(u32, u64), io::Error> where R: Read + Seek {
+		let (tick, _) = read_frame_header(input, kind, protocol)?;
+
+		let header_len = if kind == FrameKind::Stop && !protocol.has_player_slot() { 3 } else { 4 };
+		let header_len = header_len + protocol.has_player_slot() as u64;
+
+		let payload_len = match kind {
+			FrameKind::SignonUpdate | FrameKind::Update => {
+				// Positions, then the incoming and outgoing sequence numbers
+				let fixed = (PositionUpdate::LEN * protocol.split_screen_slots + 8) as u64;
+				input.seek(SeekFrom::Current(fixed as i64))?;
+
+				fixed + skip_u8_array(input)?
+			},
+			FrameKind::TickSync | FrameKind::Stop => 0,
+			FrameKind::ConsoleCommand | FrameKind::DataTables | FrameKind::StringTables => skip_u8_array(input)?,
+			FrameKind::UserCmdDelta | FrameKind::CustomData => {
+				// The sequence number or callback index comes before the data
+				read_u32(input)?;
+
+				4 + skip_u8_array(input)?
+			}
+		};
+
+		Ok((tick, header_len + payload_len))
+	}
+
+	/// Writes the frame in the form read by `parse`. Payloads that are kept as raw bytes are
+	/// written back unchanged, so a frame that was parsed is written out byte for byte.
+	pub fn write<W>(&self, output: &mut W, protocol: DemoProtocol) -> Result<(), io::Error> where W: Write {
+		let kind = self.payload.kind();
+
+		let kind_id = kind.id(protocol).ok_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, "frame kind does not exist in this demo protocol")
+		})?;
+
+		output.write_all(&[kind_id])?;
+
+		if kind == FrameKind::Stop && !protocol.has_player_slot() {
+			write_u24(output, self.tick)?;
+		} else {
+			write_u32(output, self.tick)?;
+		}
+
+		if protocol.has_player_slot() {
+			output.write_all(&[self.player_slot])?;
+		}
+
+		match self.payload {
+			FramePayload::SignonUpdate(ref update) | FramePayload::Update(ref update) => update.write(output, protocol),
+			FramePayload::TickSync => Ok(()),
+			FramePayload::ConsoleCommand(ref command) => write_u8_array(output, command.raw()),
+			FramePayload::UserCmdDelta { sequence, ref frame } => {
+				write_u32(output, sequence)?;
+
+				write_u8_array(output, frame.raw())
+			},
+			FramePayload::DataTables(ref tables) => write_u8_array(output, tables.raw()),
+			FramePayload::Stop => Ok(()),
+			FramePayload::CustomData { callback, ref data } => {
+				write_u32(output, callback)?;
+
+				write_u8_array(output, data)
+			},
+			FramePayload::StringTables(ref tables) => write_u8_array(output, tables.raw())
+		}
 	}
 }
 
@@ -191,45 +361,116 @@ pub enum FramePayload {
 	SignonUpdate(Update),
 	Update(Update),
 	TickSync,
-	ConsoleCommand(String),
+	ConsoleCommand(ConsoleCommandFrame),
 	UserCmdDelta { sequence: u32, frame: UserCmdFrame },
 	DataTables(DataTablesFrame),
 	Stop,
+	/// Data for a callback registered by the game, identified by its index in the list of
+	/// callbacks that precedes it in the demo.
+	CustomData { callback: u32, data: Vec<u8> },
 	StringTables(StringTablesFrame)
 }
 
 impl FramePayload {
 	pub fn kind(&self) -> FrameKind {
-		match self {
-			&FramePayload::SignonUpdate(_)     => FrameKind::SignonUpdate,
-			&FramePayload::Update(_)           => FrameKind::Update,
-			&FramePayload::TickSync            => FrameKind::TickSync,
-			&FramePayload::ConsoleCommand(_)   => FrameKind::ConsoleCommand,
-			&FramePayload::UserCmdDelta { .. } => FrameKind::UserCmdDelta,
-			&FramePayload::DataTables(_)       => FrameKind::DataTables,
-			&FramePayload::Stop                => FrameKind::Stop,
-			&FramePayload::StringTables(_)     => FrameKind::StringTables,
+		match *self {
+			FramePayload::SignonUpdate(_)      => FrameKind::SignonUpdate,
+			FramePayload::Update(_)            => FrameKind::Update,
+			FramePayload::TickSync             => FrameKind::TickSync,
+			FramePayload::ConsoleCommand(_)    => FrameKind::ConsoleCommand,
+			FramePayload::UserCmdDelta { .. }  => FrameKind::UserCmdDelta,
+			FramePayload::DataTables(_)        => FrameKind::DataTables,
+			FramePayload::Stop                 => FrameKind::Stop,
+			FramePayload::CustomData { .. }    => FrameKind::CustomData,
+			FramePayload::StringTables(_)      => FrameKind::StringTables,
 		}
 	}
 }
 
 #[derive(Debug, Clone)]
 pub struct Update {
-	pub position: PositionUpdate,
+	/// One position for each split screen slot, see `DemoProtocol::split_screen_slots`. The
+	/// first one belongs to the main player.
+	pub positions: Vec<PositionUpdate>,
 	pub sequence_in: u32,
 	pub sequence_out: u32,
 	pub packets: Vec<u8>
 }
 
 impl Update {
-	pub fn parse<R>(input: &mut R) -> Result<Self, io::Error> where R: Read {
+	pub fn parse<R>(input: &mut R, protocol: DemoProtocol) -> Result<Self, io::Error> where R: Read {
+		let mut positions = Vec::with_capacity(protocol.split_screen_slots);
+
+		for _ in 0..protocol.split_screen_slots {
+			positions.push(PositionUpdate::read(input)?);
+		}
+
 		Ok(Update {
-			position: PositionUpdate::read(input)?,
+			positions,
 			sequence_in: read_u32(input)?,
 			sequence_out: read_u32(input)?,
 			packets: read_u8_array(input)?
 		})
 	}
+
+	/// Writes exactly `protocol.split_screen_slots` positions, padding with empty ones if there
+	/// are too few.
+	pub fn write<W>(&self, output: &mut W, protocol: DemoProtocol) -> Result<(), io::Error> where W: Write {
+		for index in 0..protocol.split_screen_slots {
+			match self.positions.get(index) {
+				Some(position) => position.write(output)?,
+				None => output.write_all(&[0; PositionUpdate::LEN])?
+			}
+		}
+
+		write_u32(output, self.sequence_in)?;
+		write_u32(output, self.sequence_out)?;
+
+		write_u8_array(output, &self.packets)
+	}
+}
+
+/// The text typed into the console, including the NUL terminator and anything after it. The
+/// engine writes a single terminator, but the bytes are kept as they are so that they can be
+/// written back unchanged.
+#[derive(Debug, Clone)]
+pub struct ConsoleCommandFrame(Vec<u8>);
+impl ConsoleCommandFrame {
+	/// Creates the payload that the engine would write for the command text.
+	pub fn new(command: &str) -> Self {
+		let mut data = command.as_bytes().to_vec();
+		data.push(0);
+
+		ConsoleCommandFrame(data)
+	}
+
+	pub fn from_raw(data: Vec<u8>) -> Self {
+		ConsoleCommandFrame(data)
+	}
+
+	/// The bytes of the command up to the first NUL byte.
+	pub fn command_bytes(&self) -> &[u8] {
+		let len = self.0.iter().position(|&byte| byte == 0).unwrap_or(self.0.len());
+
+		&self.0[..len]
+	}
+
+	pub fn to_str(&self) -> Result<&str, Utf8Error> {
+		str::from_utf8(self.command_bytes())
+	}
+
+	/// Splits the command text into the commands that it contains, see `console::parse`.
+	pub fn parse(&self) -> Result<Vec<ConsoleCommand>, Utf8Error> {
+		self.to_str().map(console::parse)
+	}
+
+	pub fn raw(&self) -> &[u8] {
+		&self.0
+	}
+
+	pub fn into_raw(self) -> Vec<u8> {
+		self.0
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -243,7 +484,7 @@ impl DataTablesFrame {
 		let mut bits = BitReader::new(&self.0);
 
 		let tables = DataTables::parse(&mut bits)?;
-		assert_eq!(bits.unread_bytes(), 0);
+		check_consumed(&bits)?;
 
 		Ok(tables)
 	}
@@ -268,7 +509,7 @@ impl StringTablesFrame {
 		let mut bits = BitReader::new(&self.0);
 
 		let tables = StringTables::parse(&mut bits)?;
-		assert_eq!(bits.unread_bytes(), 0);
+		check_consumed(&bits)?;
 
 		Ok(tables)
 	}
@@ -289,11 +530,11 @@ impl UserCmdFrame {
 		UserCmdFrame(data)
 	}
 
-	pub fn parse(&self) -> Result<UserCmdDelta, InsufficientBits> {
+	pub fn parse(&self) -> Result<UserCmdDelta, BitParseError> {
 		let mut bits = BitReader::new(&self.0);
 
 		let tables = UserCmdDelta::parse(&mut bits)?;
-		assert_eq!(bits.unread_bytes(), 0);
+		check_consumed(&bits)?;
 
 		Ok(tables)
 	}
@@ -306,3 +547,149 @@ impl UserCmdFrame {
 		self.0
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use bitstream::{BitEncode, BitWriter};
+	use crate::header::{HEADER_LENGTH, MAGIC, PATH_LENGTH};
+	use super::*;
+
+	const KINDS: [FrameKind; 9] = [
+		FrameKind::SignonUpdate, FrameKind::Update, FrameKind::TickSync, FrameKind::ConsoleCommand, FrameKind::UserCmdDelta,
+		FrameKind::DataTables, FrameKind::Stop, FrameKind::CustomData, FrameKind::StringTables
+	];
+
+	fn frames() -> Vec<Frame> {
+		let update = Update {
+			positions: vec![PositionUpdate::from_bytes([7; PositionUpdate::LEN])],
+			sequence_in: 5,
+			sequence_out: 6,
+			packets: vec![1, 2, 3]
+		};
+
+		let frame = |tick, player_slot, payload| Frame { tick, player_slot, payload };
+
+		vec![
+			frame(0, 0, FramePayload::SignonUpdate(update.clone())),
+			frame(1, 1, FramePayload::Update(update)),
+			frame(1, 0, FramePayload::TickSync),
+			frame(2, 1, FramePayload::ConsoleCommand(ConsoleCommandFrame::new("+use"))),
+			frame(2, 0, FramePayload::UserCmdDelta { sequence: 9, frame: UserCmdFrame::from_raw(vec![0]) }),
+			frame(2, 0, FramePayload::DataTables(DataTablesFrame::from_raw(vec![4]))),
+			frame(2, 0, FramePayload::CustomData { callback: 3, data: vec![5, 6] }),
+			frame(2, 0, FramePayload::StringTables(StringTablesFrame::from_raw(vec![]))),
+			frame(0x12_3456, 0, FramePayload::Stop)
+		]
+	}
+
+	fn write(frame: &Frame, protocol: DemoProtocol) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		frame.write(&mut bytes, protocol).unwrap();
+
+		bytes
+	}
+
+	#[test]
+	fn frame_kind_ids_depend_on_the_protocol() {
+		for &version in &[3, 4] {
+			let protocol = DemoProtocol::new(version);
+
+			for &kind in &KINDS {
+				match kind.id(protocol) {
+					Some(id) => assert_eq!(FrameKind::from_id(id, protocol), Some(kind)),
+					None => assert_eq!((kind, version), (FrameKind::CustomData, 3))
+				}
+			}
+		}
+
+		assert_eq!(FrameKind::from_id(8, DemoProtocol::new(3)), Some(FrameKind::StringTables));
+		assert_eq!(FrameKind::from_id(8, DemoProtocol::new(4)), Some(FrameKind::CustomData));
+		assert_eq!(FrameKind::from_id(9, DemoProtocol::new(3)), None);
+	}
+
+	#[test]
+	fn split_screen_slots_follow_the_game() {
+		let mut bytes = [0; HEADER_LENGTH];
+		bytes[..8].copy_from_slice(MAGIC);
+		bytes[8] = 4;
+		bytes[16 + 3 * PATH_LENGTH..][..9].copy_from_slice(b"left4dead");
+
+		let header = DemoHeader::parse(&bytes).unwrap();
+		assert_eq!(DemoProtocol::from_header(&header).split_screen_slots, 4);
+
+		bytes[16 + 3 * PATH_LENGTH..][..9].copy_from_slice(b"portal2\0\0");
+
+		let header = DemoHeader::parse(&bytes).unwrap();
+		assert_eq!(DemoProtocol::from_header(&header), DemoProtocol { version: 4, split_screen_slots: 2 });
+		assert_eq!(DemoProtocol::new(3).split_screen_slots, 1);
+	}
+
+	#[test]
+	fn frames_round_trip_in_both_layouts() {
+		for &protocol in &[DemoProtocol::new(3), DemoProtocol::new(4), DemoProtocol { version: 4, split_screen_slots: 4 }] {
+			for frame in frames() {
+				if frame.payload.kind().id(protocol).is_none() {
+					let err = frame.write(&mut Vec::new(), protocol).unwrap_err();
+					assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+					continue;
+				}
+
+				let bytes = write(&frame, protocol);
+				let parsed = Frame::parse(&mut &bytes[..], protocol).unwrap();
+
+				assert_eq!(parsed.tick, frame.tick);
+				assert_eq!(parsed.player_slot, if protocol.has_player_slot() { frame.player_slot } else { 0 });
+				assert_eq!(write(&parsed, protocol), bytes);
+
+				if let FramePayload::Update(ref update) = parsed.payload {
+					assert_eq!(update.positions.len(), protocol.split_screen_slots);
+				}
+
+				// Skipping a frame covers the same bytes as parsing it
+				let mut input = Cursor::new(&bytes[1..]);
+				let (tick, len) = Frame::skip_with_kind(&mut input, frame.payload.kind(), protocol).unwrap();
+
+				assert_eq!((tick, len), (frame.tick, bytes.len() as u64 - 1));
+				assert_eq!(input.position(), len);
+			}
+		}
+	}
+
+	#[test]
+	fn stop_ticks_are_24_bits_before_protocol_4() {
+		let stop = Frame { tick: 0x12_3456, player_slot: 0, payload: FramePayload::Stop };
+
+		assert_eq!(write(&stop, DemoProtocol::new(3)), [7, 0x56, 0x34, 0x12]);
+		assert_eq!(write(&stop, DemoProtocol::new(4)), [7, 0x56, 0x34, 0x12, 0, 0]);
+	}
+
+	#[test]
+	fn payloads_must_be_parsed_to_the_last_byte() {
+		let mut bits = BitWriter::new();
+		UserCmdDelta::parse(&mut BitReader::new(&[0, 0])).unwrap().encode(&mut bits, 0);
+
+		// The delta is 14 bits, so 2 bits of padding are left in the last byte
+		let data = bits.finish().raw_bytes().to_vec();
+		assert_eq!(data.len(), 2);
+		assert!(UserCmdFrame::from_raw(data.clone()).parse().is_ok());
+
+		let mut padded = data;
+		padded.push(0);
+
+		let err = UserCmdFrame::from_raw(padded).parse().unwrap_err();
+		assert!(matches!(err.root(), BitParseError::Invalid(_)));
+	}
+
+	#[test]
+	fn custom_data_is_read_in_protocol_4() {
+		let bytes = [8, 1, 0, 0, 0, 2, 3, 0, 0, 0, 2, 0, 0, 0, 0xAB, 0xCD];
+
+		let frame = Frame::parse(&mut &bytes[..], DemoProtocol::new(4)).unwrap();
+
+		assert_eq!((frame.tick, frame.player_slot), (1, 2));
+		assert!(matches!(frame.payload, FramePayload::CustomData { callback: 3, ref data } if data == &[0xAB, 0xCD]));
+		assert!(matches!(Frame::parse(&mut &[10u8][..], DemoProtocol::new(4)), Err(FrameParseError::BadFrameKind { kind_id: 10 })));
+	}
+}
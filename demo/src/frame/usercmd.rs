@@ -1,60 +1,163 @@
-use std::io::{self, Read};
-use bitstream::{BitReader, ByteReader, InsufficientBits};
-use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use bitstream::{BitReader, ByteReader, InsufficientBytes, BitParseError, BitDecode, BitEncode};
+use super::Buttons;
 
-/// Delta encoded UserCmd.
-/// None values represent that the value did not change.
-#[derive(Debug, Copy, Clone)]
+/// Amount of commands kept by `UserCmdTracker`, matching `MULTIPLAYER_BACKUP` in the engine.
+const COMMAND_BACKUP: usize = 90;
+
+/// The input of a player for a single tick, as sent from the client to the server.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct UserCmd {
+	pub command_number: u32,
+	/// Tick that the client was on when the command was created.
+	pub tick_count: u32,
+	/// Pitch, yaw and roll, in degrees.
+	pub view_angles: (f32, f32, f32),
+	pub forward: f32,
+	pub side: f32,
+	pub up: f32,
+	pub buttons: Buttons,
+	pub impulse: u8,
+	/// Entity index of the weapon that the player switched to.
+	pub weapon: u16,
+	pub weapon_subtype: u8,
+	pub mouse_delta: (i16, i16)
+}
+
+/// Delta encoded UserCmd. None values represent that the value is the same as in the previous
+/// command, see `UserCmdTracker`.
+#[derive(Debug, Copy, Clone, BitDecode, BitEncode)]
 pub struct UserCmdDelta {
-	/// If None, then command_number is the last number + 1.
-	command_number: Option<u32>,
-	/// If None, then tick_count is the last count + 1.
-	tick_count: Option<u32>,
-	view_angles: (Option<f32>, Option<f32>, Option<f32>),
-	forward: Option<f32>,
-	side: Option<f32>,
-	up: Option<f32>,
-	buttons: Option<u32>,
-	impulse: Option<u8>,
-	weapon_select: Option<(u16, Option<u8>)>, // 11 bits, 6 bits
-	mouse_delta: (Option<i16>, Option<i16>)
+	/// If None, then command_number is the number of the previous command + 1.
+	pub command_number: Option<u32>,
+	/// If None, then tick_count is the count of the previous command + 1.
+	pub tick_count: Option<u32>,
+	pub view_angles: (Option<f32>, Option<f32>, Option<f32>),
+	pub forward: Option<f32>,
+	pub side: Option<f32>,
+	pub up: Option<f32>,
+	/// Raw bits of `Buttons`.
+	pub buttons: Option<u32>,
+	pub impulse: Option<u8>,
+	pub weapon_select: Option<WeaponSelect>,
+	pub mouse_delta: (Option<i16>, Option<i16>)
 }
 
 impl UserCmdDelta {
-	pub fn parse(reader: &mut BitReader) -> Result<Self, InsufficientBits> {
-		Ok(UserCmdDelta {
-			command_number: if reader.read_bit()? { Some(reader.read_u32()?) } else { None },
-			tick_count:     if reader.read_bit()? { Some(reader.read_u32()?) } else { None },
+	pub fn parse(reader: &mut BitReader) -> Result<Self, BitParseError> {
+		// The encoding of UserCmds does not depend on the protocol version
+		Self::decode(reader, 0)
+	}
+
+	/// Decodes a delta that has no previous command, by applying it to the null command.
+	pub fn to_command(&self) -> UserCmd {
+		self.apply(&UserCmd::default())
+	}
+
+	/// Applies the changes to the previous command.
+	pub fn apply(&self, previous: &UserCmd) -> UserCmd {
+		let weapon = self.weapon_select.map(|select| select.weapon).unwrap_or(previous.weapon);
+		let weapon_subtype = self.weapon_select.and_then(|select| select.subtype).unwrap_or(previous.weapon_subtype);
+
+		UserCmd {
+			command_number: self.command_number.unwrap_or(previous.command_number.wrapping_add(1)),
+			tick_count: self.tick_count.unwrap_or(previous.tick_count.wrapping_add(1)),
 			view_angles: (
-				if reader.read_bit()? { Some(reader.read_f32()?) } else { None },
-				if reader.read_bit()? { Some(reader.read_f32()?) } else { None },
-				if reader.read_bit()? { Some(reader.read_f32()?) } else { None }
+				self.view_angles.0.unwrap_or(previous.view_angles.0),
+				self.view_angles.1.unwrap_or(previous.view_angles.1),
+				self.view_angles.2.unwrap_or(previous.view_angles.2)
 			),
-			forward: if reader.read_bit()? { Some(reader.read_f32()?) } else { None },
-			side: if reader.read_bit()? { Some(reader.read_f32()?) } else { None },
-			up: if reader.read_bit()? { Some(reader.read_f32()?) } else { None },
-			buttons: if reader.read_bit()? { Some(reader.read_u32()?) } else { None },
-			impulse: if reader.read_bit()? { Some(reader.read_u8()?) } else { None },
-			weapon_select: if reader.read_bit()? {
-				Some((
-					reader.read_bits(11)? as u16,
-					if reader.read_bit()? { Some(reader.read_bits(6)? as u8) } else { None }
-				))
-			} else {
-				None
-			},
+			forward: self.forward.unwrap_or(previous.forward),
+			side: self.side.unwrap_or(previous.side),
+			up: self.up.unwrap_or(previous.up),
+			buttons: self.buttons.map(Buttons).unwrap_or(previous.buttons),
+			impulse: self.impulse.unwrap_or(previous.impulse),
+			weapon,
+			weapon_subtype,
 			mouse_delta: (
-				if reader.read_bit()? { Some(reader.read_i16()?) } else { None },
-				if reader.read_bit()? { Some(reader.read_i16()?) } else { None }
+				self.mouse_delta.0.unwrap_or(previous.mouse_delta.0),
+				self.mouse_delta.1.unwrap_or(previous.mouse_delta.1)
 			)
-		})
+		}
 	}
 }
 
+#[derive(Debug, Copy, Clone, BitDecode, BitEncode)]
+pub struct WeaponSelect {
+	#[bits(11)]
+	pub weapon: u16,
+	#[bits(6)]
+	pub subtype: Option<u8>
+}
+
+/// Turns the `UserCmdDelta` frames of a demo into full commands. Each delta is applied to the
+/// last command, so that omitted fields carry over and the numbers count up, and the first one
+/// is applied to the null command. The command is kept under the `sequence` of its frame.
+/// Commands are kept for the last 90 sequence numbers, like in the engine.
+#[derive(Debug, Clone)]
+pub struct UserCmdTracker {
+	commands: [Option<(u32, UserCmd)>; COMMAND_BACKUP],
+	last: Option<UserCmd>
+}
+
+impl UserCmdTracker {
+	pub fn new() -> Self {
+		UserCmdTracker { commands: [None; COMMAND_BACKUP], last: None }
+	}
+
+	/// Applies the delta of the frame with the given `sequence` to the last command, returning
+	/// the full command. Returns None if a command with the same sequence number was already
+	/// applied, since demos can contain the same command more than once.
+	pub fn apply(&mut self, sequence: u32, delta: &UserCmdDelta) -> Option<UserCmd> {
+		if self.get(sequence).is_some() {
+			return None;
+		}
+
+		let command = delta.apply(&self.last.unwrap_or_default());
+		self.commands[sequence as usize % COMMAND_BACKUP] = Some((sequence, command));
+		self.last = Some(command);
+
+		Some(command)
+	}
+
+	/// Returns the command with the given sequence number, if it is still kept.
+	pub fn get(&self, sequence: u32) -> Option<&UserCmd> {
+		match self.commands[sequence as usize % COMMAND_BACKUP] {
+			Some((stored, ref command)) if stored == sequence => Some(command),
+			_ => None
+		}
+	}
+}
+
+impl Default for UserCmdTracker {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// The `FDEMO_*` flags of a `PositionUpdate`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct PositionFlags(pub u32);
+
+impl PositionFlags {
+	/// The effective view origin is the resampled one.
+	pub const USE_ORIGIN2: PositionFlags = PositionFlags(1 << 0);
+	/// The effective view angles are the resampled ones.
+	pub const USE_ANGLES2: PositionFlags = PositionFlags(1 << 1);
+	/// The camera jumped, so it should not be interpolated from the previous position.
+	pub const NOINTERP:    PositionFlags = PositionFlags(1 << 2);
+
+	pub fn contains(self, other: PositionFlags) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+/// The camera of the recording player at the time of a frame.
 #[derive(Debug, Copy, Clone)]
 pub struct PositionUpdate {
-	pub flags:     u32,
+	pub flags:     PositionFlags,
 	pub original:  Position,
+	/// Used instead of `original` depending on the flags, see `effective`.
 	pub resampled: Position
 }
 
@@ -71,19 +174,41 @@ impl PositionUpdate {
 	}
 
 	pub fn from_bytes(bytes: [u8; Self::LEN]) -> Self {
-		let mut reader = ByteReader::new(&bytes);
+		// Infallible: the array is exactly large enough for every field
+		Self::parse(&mut ByteReader::new(&bytes)).unwrap()
+	}
 
-		PositionUpdate {
-			flags:     reader.u32(),
-			original:  Position::read(&mut reader),
-			resampled: Position::read(&mut reader)
+	pub fn parse(reader: &mut ByteReader) -> Result<Self, InsufficientBytes> {
+		Ok(PositionUpdate {
+			flags:     PositionFlags(reader.u32()?),
+			original:  Position::parse(reader)?,
+			resampled: Position::parse(reader)?
+		})
+	}
+
+	pub fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+		output.write_all(&self.flags.0.to_le_bytes())?;
+		self.original.write(output)?;
+		self.resampled.write(output)
+	}
+
+	/// The view that the game shows, taking the origin and angles from either `original` or
+	/// `resampled` as the flags say.
+	pub fn effective(&self) -> Position {
+		let origin = if self.flags.contains(PositionFlags::USE_ORIGIN2) { &self.resampled } else { &self.original };
+		let angles = if self.flags.contains(PositionFlags::USE_ANGLES2) { &self.resampled } else { &self.original };
+
+		Position {
+			view_origin: origin.view_origin,
+			view_angles: angles.view_angles,
+			view_angles_local: angles.view_angles_local
 		}
 	}
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Position {
-	pub view_orgin:        (f32, f32, f32),
+	pub view_origin:       (f32, f32, f32),
 	pub view_angles:       (f32, f32, f32),
 	pub view_angles_local: (f32, f32, f32),
 }
@@ -92,18 +217,117 @@ impl Position {
 	// 4 bytes per float * 3 floats per vector * 3 vectors
 	pub const LEN: usize = 4 * 3 * 3;
 
-	fn read(reader: &mut ByteReader) -> Self {
-		// Infallible as long as the reader has sufficient bytes
-		Self::from_bytes(reader.bytes(Self::LEN).try_into().unwrap())
+	pub fn parse(reader: &mut ByteReader) -> Result<Self, InsufficientBytes> {
+		Ok(Position {
+			view_origin:       (reader.f32()?, reader.f32()?, reader.f32()?),
+			view_angles:       (reader.f32()?, reader.f32()?, reader.f32()?),
+			view_angles_local: (reader.f32()?, reader.f32()?, reader.f32()?)
+		})
 	}
 
 	pub fn from_bytes(bytes: &[u8; Self::LEN]) -> Self {
-		let mut reader = ByteReader::new(bytes);
+		// Infallible: the array is exactly large enough for every field
+		Self::parse(&mut ByteReader::new(bytes)).unwrap()
+	}
 
-		Position {
-			view_orgin:        (reader.f32(), reader.f32(), reader.f32()),
-			view_angles:       (reader.f32(), reader.f32(), reader.f32()),
-			view_angles_local: (reader.f32(), reader.f32(), reader.f32())
+	pub fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+		for &(x, y, z) in &[self.view_origin, self.view_angles, self.view_angles_local] {
+			output.write_all(&x.to_le_bytes())?;
+			output.write_all(&y.to_le_bytes())?;
+			output.write_all(&z.to_le_bytes())?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitstream::BitWriter;
+	use crate::frame::UserCmdFrame;
+	use super::*;
+
+	/// Writes a delta field by field, in the order used by the engine.
+	fn delta(command_number: u32, tick_count: u32, pitch: Option<f32>, buttons: Option<u32>) -> UserCmdFrame {
+		let mut bits = BitWriter::new();
+
+		bits.write_bit(true);
+		bits.write_u32(command_number);
+		bits.write_bit(true);
+		bits.write_u32(tick_count);
+
+		bits.write_bit(pitch.is_some());
+		if let Some(pitch) = pitch {
+			bits.write_f32(pitch);
+		}
+
+		// Yaw, roll, forward, side and up
+		for _ in 0..5 {
+			bits.write_bit(false);
 		}
+
+		bits.write_bit(buttons.is_some());
+		if let Some(buttons) = buttons {
+			bits.write_u32(buttons);
+		}
+
+		// Impulse, weapon select and mouse deltas
+		bits.write_bit(false);
+		bits.write_bit(true);
+		bits.write_bits(42, 11);
+		bits.write_bit(false);
+		bits.write_bit(false);
+		bits.write_bit(false);
+
+		UserCmdFrame::from_raw(bits.finish().raw_bytes().to_vec())
+	}
+
+	#[test]
+	fn omitted_fields_carry_over_to_the_next_command() {
+		let mut tracker = UserCmdTracker::new();
+
+		let first = delta(1000, 5000, Some(10.0), Some(Buttons::ATTACK.0 | Buttons::JUMP.0)).parse().unwrap();
+		let mut second = delta(0, 0, None, None).parse().unwrap();
+		second.command_number = None;
+		second.tick_count = None;
+		second.weapon_select = None;
+
+		let first = tracker.apply(1000, &first).unwrap();
+		let second = tracker.apply(1001, &second).unwrap();
+
+		assert_eq!(first.buttons, Buttons::ATTACK | Buttons::JUMP);
+		assert_eq!(first.view_angles, (10.0, 0.0, 0.0));
+		assert_eq!(first.weapon, 42);
+
+		assert_eq!((second.command_number, second.tick_count), (1001, 5001));
+		assert_eq!(second.buttons, Buttons::ATTACK | Buttons::JUMP);
+		assert_eq!(second.view_angles, (10.0, 0.0, 0.0));
+		assert_eq!(second.weapon, 42);
+	}
+
+	#[test]
+	fn missing_numbers_follow_the_null_command() {
+		let mut delta = delta(7, 8, None, None).parse().unwrap();
+		delta.command_number = None;
+		delta.tick_count = None;
+
+		let command = delta.to_command();
+
+		assert_eq!((command.command_number, command.tick_count), (1, 1));
+		assert_eq!(delta.apply(&UserCmd { command_number: 9, tick_count: 19, ..UserCmd::default() }).tick_count, 20);
+	}
+
+	#[test]
+	fn repeated_sequences_are_skipped() {
+		let mut tracker = UserCmdTracker::new();
+		let delta = delta(1, 1, None, Some(1)).parse().unwrap();
+
+		assert!(tracker.apply(5, &delta).is_some());
+		assert!(tracker.apply(5, &delta).is_none());
+		assert_eq!(tracker.get(5).unwrap().buttons, Buttons::ATTACK);
+
+		// Sequence numbers that map to the same slot replace each other
+		assert!(tracker.apply(5 + COMMAND_BACKUP as u32, &delta).is_some());
+		assert!(tracker.get(5).is_none());
 	}
 }
@@ -1,12 +1,62 @@
-use std::fmt::{Debug, Formatter, Error};
+use std::fmt::{Debug, Display, Formatter, Error};
+use std::io::{self, Read, Write};
 use std::str::{self, Utf8Error};
 use std::convert::TryInto;
-use bitstream::ByteReader;
+use bitstream::{ByteReader, InsufficientBytes};
 
 pub const PATH_LENGTH: usize = 260;
 pub const HEADER_LENGTH: usize = 8 + 4 + 4 + PATH_LENGTH + PATH_LENGTH + PATH_LENGTH + PATH_LENGTH + 4 + 4 + 4 + 4; // 1072
 
-pub struct HeaderString([u8; 260]);
+pub const MAGIC: &[u8; 8] = b"HL2DEMO\0";
+
+/// An owned, NUL padded header string. Any bytes after the terminator are kept as they are, so
+/// that headers that are read and written back out stay byte for byte identical.
+#[derive(Clone)]
+pub struct HeaderString(pub [u8; 260]);
+
+impl HeaderString {
+	/// Creates a NUL padded header string. The string must leave room for the terminator, and
+	/// must not contain any NUL bytes itself.
+	pub fn new(string: &[u8]) -> Result<Self, InvalidHeaderString> {
+		if string.len() >= PATH_LENGTH {
+			return Err(InvalidHeaderString::TooLong { len: string.len() });
+		}
+
+		if let Some(index) = string.iter().position(|&byte| byte == 0) {
+			return Err(InvalidHeaderString::ContainsNul { index });
+		}
+
+		let mut bytes = [0; PATH_LENGTH];
+		bytes[..string.len()].copy_from_slice(string);
+
+		Ok(HeaderString(bytes))
+	}
+
+	pub fn as_str(&self) -> HeaderStr<'_> {
+		HeaderStr(&self.0)
+	}
+}
+
+impl Default for HeaderString {
+	fn default() -> Self {
+		HeaderString([0; PATH_LENGTH])
+	}
+}
+
+impl Debug for HeaderString {
+	fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+		self.as_str().fmt(f)
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum InvalidHeaderString {
+	/// The string is `len` bytes long, but at most `PATH_LENGTH - 1` bytes fit before the
+	/// terminator.
+	TooLong { len: usize },
+	/// The string contains a NUL byte at `index`, which would cut it short.
+	ContainsNul { index: usize }
+}
 
 pub struct HeaderStr<'s>(& 's [u8; 260]);
 impl<'s> HeaderStr<'s> {
@@ -15,7 +65,7 @@ impl<'s> HeaderStr<'s> {
 	}
 
 	pub fn bytes(&self) -> &[u8; 260] {
-		&self.0
+		self.0
 	}
 
 	pub fn str_bytes(&self) -> &[u8] {
@@ -34,6 +84,10 @@ impl<'s> HeaderStr<'s> {
 	pub fn to_str(&self) -> Result<&str, Utf8Error> {
 		str::from_utf8(self.str_bytes())
 	}
+
+	pub fn to_owned(&self) -> HeaderString {
+		HeaderString(*self.0)
+	}
 }
 
 impl<'s> Debug for HeaderStr<'s> {
@@ -49,6 +103,36 @@ impl<'s> Debug for HeaderStr<'s> {
 #[derive(Debug)]
 pub struct IncorrectMagic<'a>(&'a [u8]);
 
+#[derive(Debug)]
+pub enum HeaderReadError {
+	Io(io::Error),
+	IncorrectMagic([u8; 8])
+}
+
+impl From<io::Error> for HeaderReadError {
+	fn from(err: io::Error) -> Self {
+		Self::Io(err)
+	}
+}
+
+impl Display for HeaderReadError {
+	fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+		match self {
+			HeaderReadError::Io(err) => write!(f, "failed to read the header: {}", err),
+			HeaderReadError::IncorrectMagic(magic) => write!(f, "incorrect magic value \"{}\", expected \"{}\"", magic.escape_ascii(), MAGIC.escape_ascii())
+		}
+	}
+}
+
+impl std::error::Error for HeaderReadError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			HeaderReadError::Io(err) => Some(err),
+			HeaderReadError::IncorrectMagic(_) => None
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct DemoHeader<'data> {
 	pub demo_protocol:    i32,
@@ -64,30 +148,237 @@ pub struct DemoHeader<'data> {
 }
 
 impl<'data> DemoHeader<'data> {
-	pub fn parse(data: &'data [u8; HEADER_LENGTH]) -> Result<DemoHeader, IncorrectMagic> {
+	pub fn parse(data: &'data [u8; HEADER_LENGTH]) -> Result<DemoHeader<'data>, IncorrectMagic<'data>> {
 		let mut reader  = ByteReader::new(data);
 
-		let magic = reader.bytes(8);
+		// Infallible: the array is exactly HEADER_LENGTH bytes long, which is enough for every field
+		let magic = reader.bytes(8).unwrap();
 
-		if magic !=  b"HL2DEMO\0" {
+		if magic != MAGIC {
 			return Err(IncorrectMagic(magic));
 		}
 
+		// Infallible: the array is exactly HEADER_LENGTH bytes long, which is enough for every field
+		Ok(Self::parse_fields(&mut reader).unwrap())
+	}
+
+	/// Reads and parses the header from the start of a demo file, leaving `reader` positioned at
+	/// the first frame.
+	pub fn read<R>(reader: &mut R) -> Result<DemoHeaderBuf, HeaderReadError> where R: Read {
+		let mut buf = [0; HEADER_LENGTH];
+		reader.read_exact(&mut buf)?;
+
+		match DemoHeader::parse(&buf) {
+			Ok(header) => Ok(header.to_owned()),
+			Err(IncorrectMagic(magic)) => {
+				// Infallible: the magic is always 8 bytes long
+				Err(HeaderReadError::IncorrectMagic(magic.try_into().unwrap()))
+			}
+		}
+	}
+
+	pub fn to_owned(&self) -> DemoHeaderBuf {
+		DemoHeaderBuf {
+			demo_protocol: self.demo_protocol,
+			network_protocol: self.network_protocol,
+			server_name: self.server_name.to_owned(),
+			client_name: self.client_name.to_owned(),
+			map_name: self.map_name.to_owned(),
+			game_directory: self.game_directory.to_owned(),
+			playback_seconds: self.playback_seconds,
+			ticks: self.ticks,
+			frames: self.frames,
+			signon_length: self.signon_length
+		}
+	}
+
+	fn parse_fields(reader: &mut ByteReader<'data>) -> Result<Self, InsufficientBytes> {
 		Ok(DemoHeader {
-			demo_protocol: reader.i32(),
-			network_protocol: reader.i32(),
-			server_name: str(&mut reader),
-			client_name: str(&mut reader),
-			map_name: str(&mut reader),
-			game_directory: str(&mut reader),
-			playback_seconds: reader.f32(),
-			ticks: reader.i32(),
-			frames: reader.i32(),
-			signon_length: reader.i32()
+			demo_protocol: reader.i32()?,
+			network_protocol: reader.i32()?,
+			server_name: str(reader)?,
+			client_name: str(reader)?,
+			map_name: str(reader)?,
+			game_directory: str(reader)?,
+			playback_seconds: reader.f32()?,
+			ticks: reader.i32()?,
+			frames: reader.i32()?,
+			signon_length: reader.i32()?
 		})
 	}
 }
 
-fn str<'a>(reader: &mut ByteReader<'a>) -> HeaderStr<'a> {
-	HeaderStr::from_slice(reader.bytes(260)).unwrap()
+fn str<'a>(reader: &mut ByteReader<'a>) -> Result<HeaderStr<'a>, InsufficientBytes> {
+	// Infallible: the slice is always PATH_LENGTH bytes long
+	reader.bytes(PATH_LENGTH).map(|bytes| HeaderStr::from_slice(bytes).unwrap())
+}
+
+/// An owned `DemoHeader`, which can be modified and written back out. The strings can only be
+/// changed through the setters, which make sure that they still fit in the header.
+#[derive(Debug, Clone)]
+pub struct DemoHeaderBuf {
+	pub demo_protocol:    i32,
+	pub network_protocol: i32,
+	server_name:          HeaderString,
+	client_name:          HeaderString,
+	map_name:             HeaderString,
+	game_directory:       HeaderString,
+	pub playback_seconds: f32,
+	pub ticks:            i32,
+	pub frames:           i32,
+	pub signon_length:    i32
+}
+
+impl DemoHeaderBuf {
+	pub fn as_header(&self) -> DemoHeader<'_> {
+		DemoHeader {
+			demo_protocol: self.demo_protocol,
+			network_protocol: self.network_protocol,
+			server_name: self.server_name.as_str(),
+			client_name: self.client_name.as_str(),
+			map_name: self.map_name.as_str(),
+			game_directory: self.game_directory.as_str(),
+			playback_seconds: self.playback_seconds,
+			ticks: self.ticks,
+			frames: self.frames,
+			signon_length: self.signon_length
+		}
+	}
+
+	pub fn server_name(&self) -> HeaderStr<'_> {
+		self.server_name.as_str()
+	}
+
+	pub fn client_name(&self) -> HeaderStr<'_> {
+		self.client_name.as_str()
+	}
+
+	pub fn map_name(&self) -> HeaderStr<'_> {
+		self.map_name.as_str()
+	}
+
+	pub fn game_directory(&self) -> HeaderStr<'_> {
+		self.game_directory.as_str()
+	}
+
+	pub fn set_server_name<S>(&mut self, name: S) -> Result<(), InvalidHeaderString> where S: AsRef<[u8]> {
+		self.server_name = HeaderString::new(name.as_ref())?;
+
+		Ok(())
+	}
+
+	pub fn set_client_name<S>(&mut self, name: S) -> Result<(), InvalidHeaderString> where S: AsRef<[u8]> {
+		self.client_name = HeaderString::new(name.as_ref())?;
+
+		Ok(())
+	}
+
+	pub fn set_map_name<S>(&mut self, name: S) -> Result<(), InvalidHeaderString> where S: AsRef<[u8]> {
+		self.map_name = HeaderString::new(name.as_ref())?;
+
+		Ok(())
+	}
+
+	pub fn set_game_directory<S>(&mut self, directory: S) -> Result<(), InvalidHeaderString> where S: AsRef<[u8]> {
+		self.game_directory = HeaderString::new(directory.as_ref())?;
+
+		Ok(())
+	}
+
+	/// Serializes the header into the layout read by `DemoHeader::parse`.
+	pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+		let mut buf = [0; HEADER_LENGTH];
+		let mut offset = 0;
+
+		let mut put = |bytes: &[u8]| {
+			buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+			offset += bytes.len();
+		};
+
+		put(MAGIC);
+		put(&self.demo_protocol.to_le_bytes());
+		put(&self.network_protocol.to_le_bytes());
+		put(&self.server_name.0);
+		put(&self.client_name.0);
+		put(&self.map_name.0);
+		put(&self.game_directory.0);
+		put(&self.playback_seconds.to_le_bytes());
+		put(&self.ticks.to_le_bytes());
+		put(&self.frames.to_le_bytes());
+		put(&self.signon_length.to_le_bytes());
+
+		buf
+	}
+
+	/// Writes exactly `HEADER_LENGTH` bytes.
+	pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+		writer.write_all(&self.to_bytes())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A header with leftover bytes after the terminators of its strings, as left behind by the
+	/// engine when a shorter string overwrites a longer one.
+	fn sample() -> [u8; HEADER_LENGTH] {
+		let mut header = DemoHeaderBuf {
+			demo_protocol: 3,
+			network_protocol: 24,
+			server_name: HeaderString::new(b"Server").unwrap(),
+			client_name: HeaderString::new(b"Player").unwrap(),
+			map_name: HeaderString::new(b"ctf_2fort").unwrap(),
+			game_directory: HeaderString::new(b"tf").unwrap(),
+			playback_seconds: 12.5,
+			ticks: 825,
+			frames: 1700,
+			signon_length: 123456
+		};
+
+		header.map_name.0[20] = b'x';
+
+		header.to_bytes()
+	}
+
+	#[test]
+	fn headers_round_trip_byte_for_byte() {
+		let bytes = sample();
+
+		let header = DemoHeader::read(&mut &bytes[..]).unwrap();
+
+		assert_eq!(header.map_name().to_str().unwrap(), "ctf_2fort");
+		assert_eq!(header.network_protocol, 24);
+		assert_eq!(header.signon_length, 123456);
+		assert_eq!(&header.to_bytes()[..], &bytes[..]);
+
+		let mut written = Vec::new();
+		header.write_to(&mut written).unwrap();
+		assert_eq!(written.len(), HEADER_LENGTH);
+	}
+
+	#[test]
+	fn setters_check_that_strings_fit() {
+		let mut header = DemoHeader::read(&mut &sample()[..]).unwrap();
+
+		header.set_server_name("Other").unwrap();
+		assert_eq!(header.server_name().str_bytes(), b"Other");
+
+		assert_eq!(header.set_map_name([b'a'; PATH_LENGTH]), Err(InvalidHeaderString::TooLong { len: PATH_LENGTH }));
+		assert_eq!(header.set_game_directory("t\0f"), Err(InvalidHeaderString::ContainsNul { index: 1 }));
+		assert_eq!(header.game_directory().str_bytes(), b"tf");
+	}
+
+	#[test]
+	fn incorrect_magic_is_reported() {
+		let mut bytes = sample();
+		bytes[..8].copy_from_slice(b"HL2DEMX\0");
+
+		assert!(matches!(DemoHeader::read(&mut &bytes[..]), Err(HeaderReadError::IncorrectMagic(magic)) if &magic == b"HL2DEMX\0"));
+		assert!(matches!(DemoHeader::read(&mut &bytes[..100]), Err(HeaderReadError::Io(_))));
+
+		// Infallible: the magic was changed above
+		let err = DemoHeader::read(&mut &bytes[..]).err().unwrap();
+		assert_eq!(err.to_string(), r#"incorrect magic value "HL2DEMX\x00", expected "HL2DEMO\x00""#);
+	}
 }
@@ -0,0 +1,393 @@
+//! An index of where each frame of a demo starts, so that playback can jump to a tick without
+//! reading everything before it.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::frame::{DemoProtocol, Frame, FrameKind, FrameParseError};
+
+const MAGIC: &[u8; 4] = b"DMIX";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct IndexEntry {
+	/// Offset of the start of the frame from the start of the file.
+	pub offset: u64,
+	pub tick: u32,
+	pub kind: FrameKind
+}
+
+#[derive(Debug, Clone)]
+pub struct TickIndex {
+	protocol: DemoProtocol,
+	entries: Vec<IndexEntry>
+}
+
+#[derive(Debug)]
+pub enum IndexLoadError {
+	Io(io::Error),
+	IncorrectMagic([u8; 4]),
+	UnsupportedVersion(u32),
+	/// Only 1, 2 or 4 split screen slots are used by the games.
+	BadSplitScreenSlots {
+		slots: u32
+	},
+	BadFrameKind {
+		kind_id: u8
+	},
+	/// The offset or tick of the entry does not fit in its type.
+	EntryOutOfRange {
+		index: u64
+	}
+}
+
+impl From<io::Error> for IndexLoadError {
+	fn from(err: io::Error) -> Self {
+		Self::Io(err)
+	}
+}
+
+impl TickIndex {
+	/// Indexes the frames of `input`, which must be positioned at the start of the first frame.
+	/// Payloads are skipped over instead of being parsed. Indexing stops after the `Stop` frame or
+	/// at the end of the input. A frame that was cut short by the end of the input is left out,
+	/// so only complete frames are indexed.
+	pub fn build<R>(input: &mut R, protocol: DemoProtocol) -> Result<Self, FrameParseError> where R: Read + Seek {
+		let mut offset = input.stream_position()?;
+		let mut entries = Vec::new();
+
+		// Seeking past the end succeeds, so skipped frames are checked against the length instead
+		let len = input.seek(SeekFrom::End(0))?;
+		input.seek(SeekFrom::Start(offset))?;
+
+		loop {
+			let mut kind_id = [0; 1];
+
+			match input.read(&mut kind_id) {
+				Ok(0) => break,
+				Ok(_) => (),
+				Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err.into())
+			}
+
+			let kind_id = kind_id[0];
+			let kind = FrameKind::from_id(kind_id, protocol).ok_or(FrameParseError::BadFrameKind { kind_id })?;
+
+			let (tick, frame_len) = match Frame::skip_with_kind(input, kind, protocol) {
+				Ok(skipped) => skipped,
+				Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+				Err(err) => return Err(err.into())
+			};
+
+			if offset + 1 + frame_len > len {
+				break;
+			}
+
+			entries.push(IndexEntry { offset, tick, kind });
+			offset += 1 + frame_len;
+
+			if kind == FrameKind::Stop {
+				break;
+			}
+		}
+
+		Ok(TickIndex { protocol, entries })
+	}
+
+	pub fn protocol(&self) -> DemoProtocol {
+		self.protocol
+	}
+
+	pub fn entries(&self) -> &[IndexEntry] {
+		&self.entries
+	}
+
+	/// Finds the last frame with a tick before or at `tick`. Returns None if every frame comes
+	/// after it. The ticks are expected to never decrease, which is how the game records them.
+	pub fn find(&self, tick: u32) -> Option<&IndexEntry> {
+		let after = self.entries.partition_point(|entry| entry.tick <= tick);
+
+		after.checked_sub(1).map(|index| &self.entries[index])
+	}
+
+	/// Saves the index in a compact form, storing the differences between neighbouring entries
+	/// as variable length integers.
+	pub fn save<W>(&self, output: &mut W) -> Result<(), io::Error> where W: Write {
+		output.write_all(MAGIC)?;
+		output.write_all(&FORMAT_VERSION.to_le_bytes())?;
+		output.write_all(&self.protocol.version.to_le_bytes())?;
+		output.write_all(&(self.protocol.split_screen_slots as u32).to_le_bytes())?;
+		write_var(output, self.entries.len() as u64)?;
+
+		let mut offset = 0;
+		let mut tick = 0;
+
+		for entry in &self.entries {
+			// Infallible: every kind that was indexed exists in the protocol
+			output.write_all(&[entry.kind.id(self.protocol).unwrap()])?;
+			write_var(output, entry.offset - offset)?;
+			write_var(output, zigzag(entry.tick as i64 - tick as i64))?;
+
+			offset = entry.offset;
+			tick = entry.tick;
+		}
+
+		Ok(())
+	}
+
+	pub fn load<R>(input: &mut R) -> Result<Self, IndexLoadError> where R: Read {
+		let mut magic = [0; 4];
+		input.read_exact(&mut magic)?;
+
+		if &magic != MAGIC {
+			return Err(IndexLoadError::IncorrectMagic(magic));
+		}
+
+		let version = read_u32(input)?;
+
+		if version != FORMAT_VERSION {
+			return Err(IndexLoadError::UnsupportedVersion(version));
+		}
+
+		let protocol_version = read_u32(input)?;
+		let slots = read_u32(input)?;
+
+		if !matches!(slots, 1 | 2 | 4) {
+			return Err(IndexLoadError::BadSplitScreenSlots { slots });
+		}
+
+		let protocol = DemoProtocol { version: protocol_version, split_screen_slots: slots as usize };
+
+		let count = read_var(input)?;
+
+		// The count is not trusted for the allocation, since the file could be corrupted
+		let mut entries = Vec::with_capacity(count.min(1 << 16) as usize);
+		let mut offset = 0u64;
+		let mut tick = 0i64;
+
+		for index in 0..count {
+			let mut kind_id = [0; 1];
+			input.read_exact(&mut kind_id)?;
+
+			let kind_id = kind_id[0];
+			let kind = FrameKind::from_id(kind_id, protocol).ok_or(IndexLoadError::BadFrameKind { kind_id })?;
+
+			let out_of_range = || IndexLoadError::EntryOutOfRange { index };
+
+			offset = offset.checked_add(read_var(input)?).ok_or_else(out_of_range)?;
+			tick = tick.checked_add(unzigzag(read_var(input)?)).ok_or_else(out_of_range)?;
+
+			let entry_tick = u32::try_from(tick).map_err(|_| out_of_range())?;
+
+			entries.push(IndexEntry { offset, tick: entry_tick, kind });
+		}
+
+		Ok(TickIndex { protocol, entries })
+	}
+}
+
+fn read_u32<R>(input: &mut R) -> Result<u32, io::Error> where R: Read {
+	let mut bytes = [0; 4];
+	input.read_exact(&mut bytes)?;
+
+	Ok(u32::from_le_bytes(bytes))
+}
+
+/// Writes 7 bits at a time, with the high bit set on every byte except for the last.
+fn write_var<W>(output: &mut W, mut value: u64) -> Result<(), io::Error> where W: Write {
+	while value >= 0x80 {
+		output.write_all(&[(value as u8) | 0x80])?;
+		value >>= 7;
+	}
+
+	output.write_all(&[value as u8])
+}
+
+fn read_var<R>(input: &mut R) -> Result<u64, io::Error> where R: Read {
+	let mut value = 0;
+
+	for shift in (0..64).step_by(7) {
+		let mut byte = [0; 1];
+		input.read_exact(&mut byte)?;
+
+		// Only the lowest bit of the 10th byte is left in a u64
+		if shift == 63 && byte[0] > 1 {
+			break;
+		}
+
+		value |= ((byte[0] & 0x7F) as u64) << shift;
+
+		if byte[0] < 0x80 {
+			return Ok(value);
+		}
+	}
+
+	Err(io::Error::new(io::ErrorKind::InvalidData, "variable length integer is too long"))
+}
+
+fn zigzag(value: i64) -> u64 {
+	((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+	((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use crate::DemoReader;
+	use crate::fixture;
+	use crate::header::HEADER_LENGTH;
+	use super::*;
+
+	fn index() -> TickIndex {
+		let mut input = Cursor::new(fixture::demo());
+		input.set_position(HEADER_LENGTH as u64);
+
+		TickIndex::build(&mut input, fixture::protocol()).unwrap()
+	}
+
+	/// The start of a saved index with the given entry count.
+	fn saved_header(slots: u32, count: u8) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+		bytes.extend_from_slice(&3u32.to_le_bytes());
+		bytes.extend_from_slice(&slots.to_le_bytes());
+		bytes.push(count);
+
+		bytes
+	}
+
+	#[test]
+	fn every_frame_is_indexed() {
+		let index = index();
+		let frames = fixture::frames();
+
+		let mut offset = HEADER_LENGTH as u64;
+
+		for ((entry, frame), len) in index.entries().iter().zip(&frames).zip(fixture::frame_lengths()) {
+			assert_eq!(*entry, IndexEntry { offset, tick: frame.tick, kind: frame.payload.kind() });
+
+			offset += len;
+		}
+
+		assert_eq!(index.entries().len(), frames.len());
+		assert_eq!(index.find(0).unwrap().kind, FrameKind::TickSync);
+		assert_eq!(index.find(9).unwrap().kind, FrameKind::UserCmdDelta);
+		assert_eq!(index.find(u32::MAX).unwrap().kind, FrameKind::Stop);
+	}
+
+	/// Interrupts every other read, which must be retried.
+	struct Interrupting<R> {
+		inner: R,
+		interrupt: bool
+	}
+
+	impl<R> Read for Interrupting<R> where R: Read {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.interrupt = !self.interrupt;
+
+			if self.interrupt {
+				return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+			}
+
+			self.inner.read(buf)
+		}
+	}
+
+	impl<R> Seek for Interrupting<R> where R: Seek {
+		fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+			self.inner.seek(pos)
+		}
+	}
+
+	#[test]
+	fn interrupted_reads_are_retried() {
+		let mut input = Interrupting { inner: Cursor::new(fixture::demo()), interrupt: false };
+		input.seek(io::SeekFrom::Start(HEADER_LENGTH as u64)).unwrap();
+
+		let built = TickIndex::build(&mut input, fixture::protocol()).unwrap();
+
+		assert_eq!(built.entries(), index().entries());
+	}
+
+	#[test]
+	fn frames_cut_short_are_left_out() {
+		let demo = fixture::demo();
+		let full = index();
+
+		for cut in HEADER_LENGTH..demo.len() {
+			let mut input = Cursor::new(&demo[..cut]);
+			input.set_position(HEADER_LENGTH as u64);
+
+			let built = TickIndex::build(&mut input, fixture::protocol()).unwrap();
+
+			let complete = full.entries().iter()
+				.zip(fixture::frame_lengths())
+				.take_while(|(entry, len)| entry.offset + len <= cut as u64)
+				.count();
+
+			assert_eq!(built.entries(), &full.entries()[..complete], "cut at {}", cut);
+		}
+	}
+
+	#[test]
+	fn indexes_round_trip() {
+		let index = index();
+
+		let mut saved = Vec::new();
+		index.save(&mut saved).unwrap();
+
+		let loaded = TickIndex::load(&mut &saved[..]).unwrap();
+
+		assert_eq!(loaded.protocol(), index.protocol());
+		assert_eq!(loaded.entries(), index.entries());
+	}
+
+	#[test]
+	fn corrupted_indexes_are_rejected() {
+		let bytes = saved_header(3, 0);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::BadSplitScreenSlots { slots: 3 })));
+
+		// An offset that overflows
+		let mut bytes = saved_header(1, 2);
+		bytes.extend_from_slice(&[1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0]);
+		bytes.extend_from_slice(&[1, 1, 0]);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::EntryOutOfRange { index: 1 })));
+
+		// A tick below 0, then one above u32::MAX
+		let mut bytes = saved_header(1, 1);
+		bytes.extend_from_slice(&[1, 0, 1]);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::EntryOutOfRange { index: 0 })));
+
+		let mut bytes = saved_header(1, 1);
+		bytes.extend_from_slice(&[1, 0, 0x80, 0x80, 0x80, 0x80, 0x20]);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::EntryOutOfRange { index: 0 })));
+
+		// An offset with more than 64 bits
+		let mut bytes = saved_header(1, 1);
+		bytes.extend_from_slice(&[1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02, 0]);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::Io(err)) if err.kind() == io::ErrorKind::InvalidData));
+
+		let mut bytes = saved_header(1, 1);
+		bytes.extend_from_slice(&[10, 0, 0]);
+		assert!(matches!(TickIndex::load(&mut &bytes[..]), Err(IndexLoadError::BadFrameKind { kind_id: 10 })));
+	}
+
+	#[test]
+	fn readers_seek_to_the_frame_at_or_before_a_tick() {
+		let index = index();
+		let mut reader = DemoReader::new(Cursor::new(fixture::demo())).unwrap();
+
+		let entry = reader.seek_to_tick(&index, 5).unwrap().unwrap();
+		let frame = reader.next().unwrap().unwrap();
+
+		assert_eq!(frame.payload.kind(), FrameKind::UserCmdDelta);
+		assert_eq!(reader.frame_offset(), entry.offset);
+
+		let mut other = DemoReader::new(Cursor::new(fixture::demo())).unwrap();
+		other.set_protocol(DemoProtocol::new(4));
+
+		assert_eq!(other.seek_to_tick(&index, 5).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+}
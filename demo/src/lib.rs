@@ -1,7 +1,15 @@
 pub mod header;
+pub mod index;
 pub mod frame;
 pub mod packets;
+pub mod repair;
+mod reader;
 mod table;
+mod writer;
+#[cfg(test)]
+mod fixture;
 
+pub use reader::DemoReader;
+pub use writer::DemoWriter;
 pub use table::string_table;
 pub use table::data_table;
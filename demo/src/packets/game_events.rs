@@ -1,4 +1,7 @@
-use bitstream::{BitReader, Bits, BitParseError};
+use bitstream::{BitRead, BitReader, BitParseError, RawString};
+
+/// Size of the buffers that the engine reads event and property names into, including the terminator.
+const MAX_EVENT_NAME_LENGTH: usize = 32;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct GameEventList(pub Vec<GameEventInfo>);
@@ -9,7 +12,7 @@ impl GameEventList {
 		let bits_len = bits.read_bits(20)?;
 
 		// Bits are nested inside the bits, yo
-		let payload = Bits::copy_into(bits, bits_len as usize)?;
+		let payload = bits.read_slice(bits_len as usize)?;
 		let mut bits = payload.reader();
 
 		let mut infos = Vec::with_capacity(count as usize);
@@ -56,14 +59,14 @@ impl Kind {
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct GameEventInfo {
 	pub index: u16,
-	pub name: String,
-	pub properties: Vec<(Kind, String)>
+	pub name: RawString,
+	pub properties: Vec<(Kind, RawString)>
 }
 
 impl GameEventInfo {
 	pub fn parse(bits: &mut BitReader) -> Result<Self, BitParseError> {
 		let index = bits.read_bits(9)? as u16;
-		let name = bits.read_string()?;
+		let name = RawString(bits.read_string_bytes_bounded(MAX_EVENT_NAME_LENGTH)?);
 		let mut properties = Vec::new();
 
 		loop {
@@ -73,7 +76,7 @@ impl GameEventInfo {
 				break;
 			}
 
-			properties.push((kind, bits.read_string()?));
+			properties.push((kind, RawString(bits.read_string_bytes_bounded(MAX_EVENT_NAME_LENGTH)?)));
 		}
 
 		Ok(GameEventInfo { index, name, properties })
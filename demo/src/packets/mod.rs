@@ -1,7 +1,24 @@
 pub mod game_events;
-// TODO: mod sound;
-
-use bitstream::{BitReader, Bits, BitParseError};
+// Not used by PlaySound yet
+#[allow(dead_code)]
+mod sound;
+
+use bitstream::{BitRead, BitReader, Bits, BitSlice, BitStorage, BitParseError, ParseContext, RawString, BitDecode, BitEncode};
+use crate::data_table::{DataTable, DataTableParseError};
+
+/// Size of the buffers that the engine reads paths, names, and cvars into, including the terminator.
+const MAX_PATH_LENGTH: usize = 260;
+/// Size of the buffer that the engine reads `Print` messages into, including the terminator.
+const MAX_PRINT_LENGTH: usize = 2048;
+/// Size of the buffer that the engine reads `Disconnect` reasons into, including the terminator.
+const MAX_DISCONNECT_LENGTH: usize = 1024;
+/// Size of the buffer that the engine reads `GetCvar` names into, including the terminator.
+const MAX_CVAR_NAME_LENGTH: usize = 256;
+/// Network protocol of TF2 and the other Source SDK 2013 games, whose `netmessages.h` leaves the
+/// IDs of the `HltvControl` (16) and `TerrainMod` (22) messages unused. The messages were
+/// removed at some point before that, but the exact protocol is not known, so older protocols
+/// are read as if they still have them.
+const NO_HLTV_CONTROL_OR_TERRAIN_MOD_PROTOCOL: u32 = 24;
 
 type EntityId = u16;
 
@@ -89,48 +106,90 @@ impl PacketKind {
 			_ => return None
 		})
 	}
+
+	/// Name of the packet kind, used to describe where a parse error occurred.
+	pub fn name(self) -> &'static str {
+		match self {
+			PacketKind::Nop               => "Nop",
+			PacketKind::Disconnect        => "Disconnect",
+			PacketKind::TransferFile      => "TransferFile",
+			PacketKind::Tick              => "Tick",
+			PacketKind::StringCommand     => "StringCommand",
+			PacketKind::SetCvars          => "SetCvars",
+			PacketKind::SignonState       => "SignonState",
+			PacketKind::Print             => "Print",
+			PacketKind::ServerInfo        => "ServerInfo",
+			PacketKind::DataTable         => "DataTable",
+			PacketKind::ClassInfo         => "ClassInfo",
+			PacketKind::Pause             => "Pause",
+			PacketKind::CreateStringTable => "CreateStringTable",
+			PacketKind::UpdateStringTable => "UpdateStringTable",
+			PacketKind::VoiceInit         => "VoiceInit",
+			PacketKind::VoiceData         => "VoiceData",
+			PacketKind::HltvControl       => "HltvControl",
+			PacketKind::PlaySound         => "PlaySound",
+			PacketKind::SetEntityView     => "SetEntityView",
+			PacketKind::FixAngle          => "FixAngle",
+			PacketKind::CrosshairAngle    => "CrosshairAngle",
+			PacketKind::Decal             => "Decal",
+			PacketKind::TerrainMod        => "TerrainMod",
+			PacketKind::UserMessage       => "UserMessage",
+			PacketKind::EntityMessage     => "EntityMessage",
+			PacketKind::GameEvent         => "GameEvent",
+			PacketKind::Entities          => "Entities",
+			PacketKind::TempEntities      => "TempEntities",
+			PacketKind::Prefetch          => "Prefetch",
+			PacketKind::PluginMenu        => "PluginMenu",
+			PacketKind::GameEventList     => "GameEventList",
+			PacketKind::GetCvar           => "GetCvar"
+		}
+	}
 }
 
-pub enum Packet {
+/// A single packet from a network update. Nested payloads are stored in `D`, which is a
+/// `BitSlice` borrowing from the update when freshly parsed, or `Bits` once made owned.
+pub enum Packet<D = Bits> {
 	Nop,
-	Disconnect,          // TODO
+	Disconnect           (RawString),
 	TransferFile         (TransferFile),
 	Tick                 (Tick),
 	StringCommand        (String),
 	SetCvars             (SetCvars),
 	SignonState          (SignonState),
-	Print                (String),
+	Print                (RawString),
 	ServerInfo           (ServerInfo),
-	DataTable,           // TODO
+	DataTable            (DataTable),
 	ClassInfo            (ClassInfo),
 	Pause                (bool),
-	CreateStringTable    (CreateStringTable),
-	UpdateStringTable    (UpdateStringTable),
+	CreateStringTable    (CreateStringTable<D>),
+	UpdateStringTable    (UpdateStringTable<D>),
 	VoiceInit            (VoiceInit),
-	VoiceData            (VoiceData),
-	HltvControl,         // TODO: Not implemented in current protocol version
-	PlaySound            (PlaySound),
+	VoiceData            (VoiceData<D>),
+	/// An `HltvControl` message. Its layout is not known and it has no length prefix, so it holds
+	/// the rest of the update, and no packets are read after it.
+	UnparsedHltvControl  (D),
+	PlaySound            (PlaySound<D>),
 	SetEntityView        (EntityId),
 	FixAngle             (FixAngle),
 	CrosshairAngle       (CrosshairAngle),
 	Decal                (Decal),
-	TerrainMod,          // TODO: Not implemented in current protocol version
-	UserMessage          (UserMessage),
-	EntityMessage        (EntityMessage),
-	GameEvent            (GameEvent),
-	Entities             (Entities),
-	TempEntities         (TempEntities),
+	TerrainMod           (TerrainMod),
+	UserMessage          (UserMessage<D>),
+	EntityMessage        (EntityMessage<D>),
+	GameEvent            (GameEvent<D>),
+	Entities             (Entities<D>),
+	TempEntities         (TempEntities<D>),
 	Prefetch             (Prefetch),
 	PluginMenu           (PluginMenu),
 	GameEventList        (game_events::GameEventList),
-	GetCvar              // TODO: { cookie: u32, key: String }
+	GetCvar              (GetCvar)
 }
 
-impl Packet {
+impl<D> Packet<D> {
 	pub fn kind(&self) -> PacketKind {
 		match *self {
 			Packet::Nop => PacketKind::Nop,
-			Packet::Disconnect => PacketKind::Disconnect,
+			Packet::Disconnect(_) => PacketKind::Disconnect,
 			Packet::TransferFile(_) => PacketKind::TransferFile,
 			Packet::Tick(_) => PacketKind::Tick,
 			Packet::StringCommand(_) => PacketKind::StringCommand,
@@ -138,20 +197,20 @@ impl Packet {
 			Packet::SignonState(_) => PacketKind::SignonState,
 			Packet::Print(_) => PacketKind::Print,
 			Packet::ServerInfo(_) => PacketKind::ServerInfo,
-			Packet::DataTable => PacketKind::DataTable,
+			Packet::DataTable(_) => PacketKind::DataTable,
 			Packet::ClassInfo(_) => PacketKind::ClassInfo,
 			Packet::Pause(_) => PacketKind::Pause,
 			Packet::CreateStringTable(_) => PacketKind::CreateStringTable,
 			Packet::UpdateStringTable(_) => PacketKind::UpdateStringTable,
 			Packet::VoiceInit(_) => PacketKind::VoiceInit,
 			Packet::VoiceData(_) => PacketKind::VoiceData,
-			Packet::HltvControl => PacketKind::HltvControl,
+			Packet::UnparsedHltvControl(_) => PacketKind::HltvControl,
 			Packet::PlaySound(_) => PacketKind::PlaySound,
 			Packet::SetEntityView(_) => PacketKind::SetEntityView,
 			Packet::FixAngle(_) => PacketKind::FixAngle,
 			Packet::CrosshairAngle(_) => PacketKind::CrosshairAngle,
 			Packet::Decal(_) => PacketKind::Decal,
-			Packet::TerrainMod => PacketKind::TerrainMod,
+			Packet::TerrainMod(_) => PacketKind::TerrainMod,
 			Packet::UserMessage(_) => PacketKind::UserMessage,
 			Packet::EntityMessage(_) => PacketKind::EntityMessage,
 			Packet::GameEvent(_) => PacketKind::GameEvent,
@@ -160,35 +219,42 @@ impl Packet {
 			Packet::Prefetch(_) => PacketKind::Prefetch,
 			Packet::PluginMenu(_) => PacketKind::PluginMenu,
 			Packet::GameEventList(_) => PacketKind::GameEventList,
-			Packet::GetCvar => PacketKind::GetCvar
+			Packet::GetCvar(_) => PacketKind::GetCvar
 		}
 	}
 
-	pub fn parse_with_kind(bits: &mut BitReader, kind: PacketKind, version: ProtocolVersion) -> Result<Self, BitParseError> {
+}
+
+impl<'i> Packet<BitSlice<'i>> {
+	pub fn parse_with_kind(bits: &mut BitReader<'i>, kind: PacketKind, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		bits.with_label(kind.name(), |bits| Self::parse_payload(bits, kind, version)).context(kind.name())
+	}
+
+	fn parse_payload(bits: &mut BitReader<'i>, kind: PacketKind, version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(match kind {
 			PacketKind::Nop               => Packet::Nop,
-			PacketKind::Disconnect        => unimplemented!(),
+			PacketKind::Disconnect        => Packet::Disconnect       (bits.read_string_bytes_bounded(MAX_DISCONNECT_LENGTH).map(RawString)?),
 			PacketKind::TransferFile      => Packet::TransferFile     (TransferFile::parse(bits, version)?),
 			PacketKind::Tick              => Packet::Tick             (Tick::parse(bits, version)?),
 			PacketKind::StringCommand     => Packet::StringCommand    (bits.read_string()?),
 			PacketKind::SetCvars          => Packet::SetCvars         (SetCvars::parse(bits, version)?),
 			PacketKind::SignonState       => Packet::SignonState      (SignonState::parse(bits, version)?),
-			PacketKind::Print             => Packet::Print            (bits.read_string()?),
+			PacketKind::Print             => Packet::Print            (bits.read_string_bytes_bounded(MAX_PRINT_LENGTH).map(RawString)?),
 			PacketKind::ServerInfo        => Packet::ServerInfo       (ServerInfo::parse(bits, version)?),
-			PacketKind::DataTable         => unimplemented!(),
+			PacketKind::DataTable         => Packet::DataTable        (parse_send_table(bits, version)?),
 			PacketKind::ClassInfo         => Packet::ClassInfo        (ClassInfo::parse(bits, version)?),
 			PacketKind::Pause             => Packet::Pause            (bits.read_bit()?),
 			PacketKind::CreateStringTable => Packet::CreateStringTable(CreateStringTable::parse(bits, version)?),
 			PacketKind::UpdateStringTable => Packet::UpdateStringTable(UpdateStringTable::parse(bits, version)?),
 			PacketKind::VoiceInit         => Packet::VoiceInit        (VoiceInit::parse(bits, version)?),
 			PacketKind::VoiceData         => Packet::VoiceData        (VoiceData::parse(bits, version)?),
-			PacketKind::HltvControl       => unimplemented!(),
+			PacketKind::HltvControl       => Packet::UnparsedHltvControl(parse_hltv_control(bits, version)?),
 			PacketKind::PlaySound         => Packet::PlaySound        (PlaySound::parse(bits, version)?),
 			PacketKind::SetEntityView     => Packet::SetEntityView    (bits.read_bits(11)? as u16),
 			PacketKind::FixAngle          => Packet::FixAngle         (FixAngle::parse(bits, version)?),
 			PacketKind::CrosshairAngle    => Packet::CrosshairAngle   (CrosshairAngle::parse(bits, version)?),
 			PacketKind::Decal             => Packet::Decal            (Decal::parse(bits, version)?),
-			PacketKind::TerrainMod        => unimplemented!(),
+			PacketKind::TerrainMod        => Packet::TerrainMod       (TerrainMod::parse(bits, version)?),
 			PacketKind::UserMessage       => Packet::UserMessage      (UserMessage::parse(bits, version)?),
 			PacketKind::EntityMessage     => Packet::EntityMessage    (EntityMessage::parse(bits, version)?),
 			PacketKind::GameEvent         => Packet::GameEvent        (GameEvent::parse(bits, version)?),
@@ -197,12 +263,53 @@ impl Packet {
 			PacketKind::Prefetch          => Packet::Prefetch         (Prefetch::parse(bits, version)?),
 			PacketKind::PluginMenu        => Packet::PluginMenu       (PluginMenu::parse(bits, version)?),
 			PacketKind::GameEventList     => Packet::GameEventList    (game_events::GameEventList::parse(bits)?),
-			PacketKind::GetCvar           => unimplemented!()
+			PacketKind::GetCvar           => Packet::GetCvar          (GetCvar::parse(bits, version)?)
 		})
 	}
 }
 
-#[derive(Debug, Clone)]
+impl<D: BitStorage> Packet<D> {
+	/// Copies any nested payloads into owned storage, so that the packet no longer borrows from
+	/// the update it was parsed from.
+	pub fn into_owned(self) -> Packet<Bits> {
+		match self {
+			Packet::Nop                       => Packet::Nop,
+			Packet::Disconnect(packet)        => Packet::Disconnect(packet),
+			Packet::TransferFile(packet)      => Packet::TransferFile(packet),
+			Packet::Tick(packet)              => Packet::Tick(packet),
+			Packet::StringCommand(packet)     => Packet::StringCommand(packet),
+			Packet::SetCvars(packet)          => Packet::SetCvars(packet),
+			Packet::SignonState(packet)       => Packet::SignonState(packet),
+			Packet::Print(packet)             => Packet::Print(packet),
+			Packet::ServerInfo(packet)        => Packet::ServerInfo(packet),
+			Packet::DataTable(packet)         => Packet::DataTable(packet),
+			Packet::ClassInfo(packet)         => Packet::ClassInfo(packet),
+			Packet::Pause(packet)             => Packet::Pause(packet),
+			Packet::CreateStringTable(packet) => Packet::CreateStringTable(packet.into_owned()),
+			Packet::UpdateStringTable(packet) => Packet::UpdateStringTable(packet.into_owned()),
+			Packet::VoiceInit(packet)         => Packet::VoiceInit(packet),
+			Packet::VoiceData(packet)         => Packet::VoiceData(packet.into_owned()),
+			Packet::UnparsedHltvControl(packet) => Packet::UnparsedHltvControl(packet.to_bits()),
+			Packet::PlaySound(packet)         => Packet::PlaySound(packet.into_owned()),
+			Packet::SetEntityView(packet)     => Packet::SetEntityView(packet),
+			Packet::FixAngle(packet)          => Packet::FixAngle(packet),
+			Packet::CrosshairAngle(packet)    => Packet::CrosshairAngle(packet),
+			Packet::Decal(packet)             => Packet::Decal(packet),
+			Packet::TerrainMod(packet)        => Packet::TerrainMod(packet),
+			Packet::UserMessage(packet)       => Packet::UserMessage(packet.into_owned()),
+			Packet::EntityMessage(packet)     => Packet::EntityMessage(packet.into_owned()),
+			Packet::GameEvent(packet)         => Packet::GameEvent(packet.into_owned()),
+			Packet::Entities(packet)          => Packet::Entities(packet.into_owned()),
+			Packet::TempEntities(packet)      => Packet::TempEntities(packet.into_owned()),
+			Packet::Prefetch(packet)          => Packet::Prefetch(packet),
+			Packet::PluginMenu(packet)        => Packet::PluginMenu(packet),
+			Packet::GameEventList(packet)     => Packet::GameEventList(packet),
+			Packet::GetCvar(packet)           => Packet::GetCvar(packet)
+		}
+	}
+}
+
+#[derive(Debug, Clone, BitDecode, BitEncode)]
 pub struct TransferFile {
 	pub transfer_id: u32,
 	pub name: String,
@@ -211,16 +318,12 @@ pub struct TransferFile {
 }
 
 impl TransferFile {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
-		Ok(TransferFile {
-			transfer_id: bits.read_u32()?,
-			name: bits.read_string()?,
-			request_or_deny: bits.read_bit()?
-		})
+	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		Self::decode(bits, version.0)
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, BitDecode, BitEncode)]
 pub struct Tick {
 	/// Server-side tick number.
 	pub number: u32,
@@ -231,17 +334,13 @@ pub struct Tick {
 }
 
 impl Tick {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
-		Ok(Tick {
-			number: bits.read_u32()?,
-			fixed_time: bits.read_u16()?,
-			fixed_time_stdev: bits.read_u16()?
-		})
+	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		Self::decode(bits, version.0)
 	}
 }
 
 #[derive(Debug, Clone)]
-pub struct SetCvars(pub Vec<(String, String)>);
+pub struct SetCvars(pub Vec<(RawString, RawString)>);
 
 impl SetCvars {
 	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
@@ -249,7 +348,10 @@ impl SetCvars {
 		let mut cvars = Vec::new();
 
 		for _ in 0..count {
-			cvars.push((bits.read_string()?, bits.read_string()?));
+			let name = bits.read_string_bytes_bounded(MAX_PATH_LENGTH)?;
+			let value = bits.read_string_bytes_bounded(MAX_PATH_LENGTH)?;
+
+			cvars.push((RawString(name), RawString(value)));
 		}
 
 		Ok(SetCvars(cvars))
@@ -324,13 +426,13 @@ pub struct ServerInfo {
 	/// Identifier of the OS that this server is running on.
 	pub os: u8,
 	/// Game directory. TF2's directory is "tf".
-	pub game_directory: String,
+	pub game_directory: RawString,
 	/// Map name. Example: `ctf_2fort`
-	pub map: String,
+	pub map: RawString,
 	/// Sky name. Example; `sky_tf2_04`
-	pub sky: String,
+	pub sky: RawString,
 	/// Host name. Not an address. Instead, this is the human readable name the server prefers to go by.
-	pub hostname: String,
+	pub hostname: RawString,
 	/// Unknown value. Supposedly not present before network_protocol 16.
 	pub _unknown1: bool
 }
@@ -338,33 +440,50 @@ pub struct ServerInfo {
 impl ServerInfo {
 	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(ServerInfo {
-			network_protocol: bits.read_u16()?,
-			server_count: bits.read_u32()?,
-			hltv: bits.read_bit()?,
-			dedicated: bits.read_bit()?,
-			client_dll_crc: bits.read_u32()?,
-			max_classes: bits.read_u16()?,
-			_unknown0: if version.0 >= 16 { Ok([
-				bits.read_u8()?, bits.read_u8()?, bits.read_u8()?, bits.read_u8()?,
-				bits.read_u8()?, bits.read_u8()?, bits.read_u8()?, bits.read_u8()?,
-				bits.read_u8()?, bits.read_u8()?, bits.read_u8()?, bits.read_u8()?,
-				bits.read_u8()?, bits.read_u8()?, bits.read_u8()?, bits.read_u8()?
-			]) } else {
-				Err(bits.read_u32()?)
+			network_protocol: bits.read_u16().context("network_protocol")?,
+			server_count: bits.read_u32().context("server_count")?,
+			hltv: bits.read_bit().context("hltv")?,
+			dedicated: bits.read_bit().context("dedicated")?,
+			client_dll_crc: bits.read_u32().context("client_dll_crc")?,
+			max_classes: bits.read_u16().context("max_classes")?,
+			_unknown0: if version.0 >= 16 {
+				let mut bytes = [0; 16];
+
+				for byte in &mut bytes {
+					*byte = bits.read_u8().context("_unknown0")?;
+				}
+
+				Ok(bytes)
+			} else {
+				Err(bits.read_u32().context("_unknown0")?)
 			},
-			slot: bits.read_u8()?,
-			max_clients: bits.read_u8()?,
-			tick_interval: bits.read_f32()?,
-			os: bits.read_u8()?,
-			game_directory: bits.read_string()?,
-			map: bits.read_string()?,
-			sky: bits.read_string()?,
-			hostname: bits.read_string()?,
-			_unknown1: if version.0 >= 16 { bits.read_bit()? } else { false }
+			slot: bits.read_u8().context("slot")?,
+			max_clients: bits.read_u8().context("max_clients")?,
+			tick_interval: bits.read_f32().context("tick_interval")?,
+			os: bits.read_u8().context("os")?,
+			game_directory: bits.read_string_bytes_bounded(MAX_PATH_LENGTH).map(RawString).context("game_directory")?,
+			map: bits.read_string_bytes_bounded(MAX_PATH_LENGTH).map(RawString).context("map")?,
+			sky: bits.read_string_bytes_bounded(MAX_PATH_LENGTH).map(RawString).context("sky")?,
+			hostname: bits.read_string_bytes_bounded(MAX_PATH_LENGTH).map(RawString).context("hostname")?,
+			_unknown1: if version.0 >= 16 { bits.read_bit().context("_unknown1")? } else { false }
 		})
 	}
 }
 
+/// Parses a `svc_SendTable` message, which holds a single table in the same format as the
+/// `DataTables` frame. The table is framed by its length in bits, so any bits after the rows are
+/// skipped.
+fn parse_send_table(bits: &mut BitReader, _version: ProtocolVersion) -> Result<DataTable, BitParseError> {
+	let needs_decoder = bits.read_bit().context("needs_decoder")?;
+	let bits_len = bits.read_u16().context("length")?;
+	let mut table = bits.read_slice(bits_len as usize).context("table")?.reader();
+
+	DataTable::parse_info(&mut table, needs_decoder).map_err(|err| match err {
+		DataTableParseError::Bits(err) => err,
+		DataTableParseError::BadRowKind { .. } => BitParseError::Invalid("unknown row kind")
+	}).context("table")
+}
+
 #[derive(Debug, Clone)]
 pub struct ClassInfo {
 	pub classes: u16,
@@ -388,17 +507,17 @@ impl ClassInfo {
 }
 
 #[derive(Debug, Clone)]
-pub struct CreateStringTable {
+pub struct CreateStringTable<D = Bits> {
 	pub name: String,
 	pub max_entries: u16,
 	pub entries: u16,
 	pub fixed_userdata_size: Option<(u16, u8)>,
 	pub compressed: bool,
-	pub data: Bits
+	pub data: D
 }
 
-impl CreateStringTable {
-	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> CreateStringTable<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, version: ProtocolVersion) -> Result<Self, BitParseError> {
 		let name = bits.read_string()?;
 		let max_entries = bits.read_u16()?;
 
@@ -419,32 +538,55 @@ impl CreateStringTable {
 
 		let compressed = bits.read_bit()?;
 
-		let data = Bits::copy_into(bits, bits_len as usize)?;
+		let data = bits.read_slice(bits_len as usize)?;
 
 		Ok(CreateStringTable { name, max_entries, entries, fixed_userdata_size, compressed, data })
 	}
 }
 
+impl<D: BitStorage> CreateStringTable<D> {
+	pub fn into_owned(self) -> CreateStringTable<Bits> {
+		CreateStringTable {
+			name: self.name,
+			max_entries: self.max_entries,
+			entries: self.entries,
+			fixed_userdata_size: self.fixed_userdata_size,
+			compressed: self.compressed,
+			data: self.data.to_bits()
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
-pub struct UpdateStringTable {
+pub struct UpdateStringTable<D = Bits> {
 	pub table_id: u8,
 	pub entries:  u16,
-	pub data:     Bits
+	pub data:     D
 }
 
-impl UpdateStringTable {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> UpdateStringTable<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(UpdateStringTable {
 			table_id: bits.read_bits(5)? as u8,
 			entries: if bits.read_bit()? { bits.read_u16()? } else { 1 },
 			data: {
 				let bits_len = bits.read_bits(20)? as usize;
-				Bits::copy_into(bits, bits_len)?
+				bits.read_slice(bits_len)?
 			}
 		})
 	}
 }
 
+impl<D: BitStorage> UpdateStringTable<D> {
+	pub fn into_owned(self) -> UpdateStringTable<Bits> {
+		UpdateStringTable {
+			table_id: self.table_id,
+			entries: self.entries,
+			data: self.data.to_bits()
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct VoiceInit {
 	pub codec: String,
@@ -470,82 +612,99 @@ pub enum VoiceSettings {
 }
 
 #[derive(Debug, Clone)]
-pub struct VoiceData {
+pub struct VoiceData<D = Bits> {
 	pub sender:    u8,
 	pub proximity: u8,
-	pub data:      Bits
+	pub data:      D
 }
 
-impl VoiceData {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> VoiceData<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(VoiceData {
 			sender: bits.read_u8()?,
 			proximity: bits.read_u8()?,
 			data: {
 				let bits_len = bits.read_u16()?;
 
-				Bits::copy_into(bits, bits_len as usize)?
+				bits.read_slice(bits_len as usize)?
 			}
 		})
 	}
 }
 
+impl<D: BitStorage> VoiceData<D> {
+	pub fn into_owned(self) -> VoiceData<Bits> {
+		VoiceData {
+			sender: self.sender,
+			proximity: self.proximity,
+			data: self.data.to_bits()
+		}
+	}
+}
+
+/// Reads a control message for HLTV spectators, which only exists in older protocols. The layout
+/// of the message is not known, and it has no length prefix, so the rest of the update is
+/// returned unparsed.
+fn parse_hltv_control<'i>(bits: &mut BitReader<'i>, version: ProtocolVersion) -> Result<BitSlice<'i>, BitParseError> {
+	if version.0 >= NO_HLTV_CONTROL_OR_TERRAIN_MOD_PROTOCOL {
+		return Err(BitParseError::Invalid("message does not exist in this protocol version"));
+	}
+
+	Ok(bits.read_slice(bits.remaining_bits())?)
+}
+
 #[derive(Debug, Clone)]
-pub enum PlaySound {
-	Reliable   (Bits),
-	Unreliable { sounds: u8, all: Bits }
+pub enum PlaySound<D = Bits> {
+	Reliable   (D),
+	Unreliable { sounds: u8, all: D }
 }
 
-impl PlaySound {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> PlaySound<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		let reliable = bits.read_bit()?;
 
 		Ok(if reliable {
 			let bits_len = bits.read_u8()?;
 
-			PlaySound::Reliable(Bits::copy_into(bits, bits_len as usize)?)
+			PlaySound::Reliable(bits.read_slice(bits_len as usize)?)
 		} else {
 			let sounds = bits.read_u8()?;
 			let bits_len = bits.read_u16()?;
 
-			PlaySound::Unreliable { sounds, all: Bits::copy_into(bits, bits_len as usize)? }
+			PlaySound::Unreliable { sounds, all: bits.read_slice(bits_len as usize)? }
 		})
 	}
 }
 
-#[derive(Debug, Clone)]
+impl<D: BitStorage> PlaySound<D> {
+	pub fn into_owned(self) -> PlaySound<Bits> {
+		match self {
+			PlaySound::Reliable(data)             => PlaySound::Reliable(data.to_bits()),
+			PlaySound::Unreliable { sounds, all } => PlaySound::Unreliable { sounds, all: all.to_bits() }
+		}
+	}
+}
+
+#[derive(Debug, Clone, BitDecode, BitEncode)]
 pub struct FixAngle {
 	relative: bool,
 	angles: (u16, u16, u16)
 }
 
 impl FixAngle {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
-		Ok(FixAngle {
-			relative: bits.read_bit()?,
-			angles: (
-				bits.read_u16()?,
-				bits.read_u16()?,
-				bits.read_u16()?
-			)
-		})
+	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		Self::decode(bits, version.0)
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, BitDecode, BitEncode)]
 pub struct CrosshairAngle {
 	angles: (u16, u16, u16)
 }
 
 impl CrosshairAngle {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
-		Ok(CrosshairAngle {
-			angles: (
-				bits.read_u16()?,
-				bits.read_u16()?,
-				bits.read_u16()?
-			)
-		})
+	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		Self::decode(bits, version.0)
 	}
 }
 
@@ -579,68 +738,131 @@ impl Decal {
 	}
 }
 
+/// Deformation of displacement terrain, such as a crater from an explosion. Only sent in older
+/// protocols.
+#[derive(Debug, Clone)]
+pub struct TerrainMod {
+	pub kind: u8,
+	pub center: (f32, f32, f32),
+	pub normal: (f32, f32, f32),
+	pub radius: f32,
+	pub strength: f32,
+	/// Bounds of the affected area.
+	pub min: (f32, f32, f32),
+	pub max: (f32, f32, f32),
+	/// Whether the terrain is pulled towards the normal instead of pushed along it.
+	pub suck_to_normal: bool,
+	/// Whether the terrain is kept from going below its original height.
+	pub stay_above_original: bool
+}
+
+impl TerrainMod {
+	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+		if version.0 >= NO_HLTV_CONTROL_OR_TERRAIN_MOD_PROTOCOL {
+			return Err(BitParseError::Invalid("message does not exist in this protocol version"));
+		}
+
+		Ok(TerrainMod {
+			kind: bits.read_u8().context("kind")?,
+			center: bits.read_vec3().context("center")?,
+			normal: bits.read_bit_vec3_normal().context("normal")?,
+			radius: bits.read_coord().context("radius")?,
+			strength: bits.read_coord().context("strength")?,
+			min: bits.read_vec3().context("min")?,
+			max: bits.read_vec3().context("max")?,
+			suck_to_normal: bits.read_bit().context("suck_to_normal")?,
+			stay_above_original: bits.read_bit().context("stay_above_original")?
+		})
+	}
+}
+
 #[derive(Debug, Clone)]
-pub struct UserMessage {
+pub struct UserMessage<D = Bits> {
 	pub channel: u8,
-	pub data:    Bits
+	pub data:    D
 }
 
-impl UserMessage {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> UserMessage<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(UserMessage {
 			channel: bits.read_u8()?,
 			data: {
 				let bits_len = bits.read_bits(11)? as usize;
-				Bits::copy_into(bits, bits_len)?
+				bits.read_slice(bits_len)?
 			}
 		})
 	}
 }
 
+impl<D: BitStorage> UserMessage<D> {
+	pub fn into_owned(self) -> UserMessage<Bits> {
+		UserMessage {
+			channel: self.channel,
+			data: self.data.to_bits()
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
-pub struct EntityMessage {
+pub struct EntityMessage<D = Bits> {
 	pub entity: EntityId,
 	pub class:  u16,
-	pub data:   Bits
+	pub data:   D
 }
 
-impl EntityMessage {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> EntityMessage<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		Ok(EntityMessage {
 			entity: bits.read_bits(11)? as u16,
 			class:  bits.read_bits(9)? as u16,
 			data: {
 				let bits_len = bits.read_bits(11)? as usize;
-				Bits::copy_into(bits, bits_len)?
+				bits.read_slice(bits_len)?
 			}
 		})
 	}
 }
 
+impl<D: BitStorage> EntityMessage<D> {
+	pub fn into_owned(self) -> EntityMessage<Bits> {
+		EntityMessage {
+			entity: self.entity,
+			class: self.class,
+			data: self.data.to_bits()
+		}
+	}
+}
+
 // First 9 bits are the event ID
 #[derive(Debug, Clone)]
-pub struct GameEvent(pub Bits);
+pub struct GameEvent<D = Bits>(pub D);
 
-impl GameEvent {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> GameEvent<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		let bits_len = bits.read_bits(11)? as usize;
 
-		Ok(GameEvent(Bits::copy_into(bits, bits_len)?))
+		Ok(GameEvent(bits.read_slice(bits_len)?))
+	}
+}
+
+impl<D: BitStorage> GameEvent<D> {
+	pub fn into_owned(self) -> GameEvent<Bits> {
+		GameEvent(self.0.to_bits())
 	}
 }
 
 #[derive(Debug, Clone)]
-pub struct Entities {
+pub struct Entities<D = Bits> {
 	pub max_entries: u16,
 	pub delta_from_tick: Option<u32>,
 	pub baseline: bool,
 	pub updated: u16,
 	pub update_baseline: bool,
-	pub data: Bits
+	pub data: D
 }
 
-impl Entities {
-	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> Entities<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, _version: ProtocolVersion) -> Result<Self, BitParseError> {
 		let max_entries = bits.read_bits(11)? as u16;
 
 		let delta_from_tick = if bits.read_bit()? {
@@ -660,28 +882,50 @@ impl Entities {
 			baseline,
 			updated,
 			update_baseline,
-			data: Bits::copy_into(bits, bits_len)?
+			data: bits.read_slice(bits_len)?
 		})
 	}
 }
 
-pub struct TempEntities {
+impl<D: BitStorage> Entities<D> {
+	pub fn into_owned(self) -> Entities<Bits> {
+		Entities {
+			max_entries: self.max_entries,
+			delta_from_tick: self.delta_from_tick,
+			baseline: self.baseline,
+			updated: self.updated,
+			update_baseline: self.update_baseline,
+			data: self.data.to_bits()
+		}
+	}
+}
+
+pub struct TempEntities<D = Bits> {
 	pub count: u8,
-	pub data:  Bits
+	pub data:  D
 }
 
-impl TempEntities {
-	pub fn parse(bits: &mut BitReader, version: ProtocolVersion) -> Result<Self, BitParseError> {
+impl<'i> TempEntities<BitSlice<'i>> {
+	pub fn parse(bits: &mut BitReader<'i>, version: ProtocolVersion) -> Result<Self, BitParseError> {
 		let count = bits.read_u8()?;
 		let bits_len = if version.0 >= 24 { bits.read_var_u32()? } else {bits.read_bits(17)? };
 
 		Ok(TempEntities {
 			count,
-			data: Bits::copy_into(bits, bits_len as usize)?
+			data: bits.read_slice(bits_len as usize)?
 		})
 	}
 }
 
+impl<D: BitStorage> TempEntities<D> {
+	pub fn into_owned(self) -> TempEntities<Bits> {
+		TempEntities {
+			count: self.count,
+			data: self.data.to_bits()
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Prefetch {
 	pub kind: bool,
@@ -715,4 +959,162 @@ impl PluginMenu {
 			}
 		})
 	}
-}
\ No newline at end of file
+}
+
+/// Asks the client for the value of a cvar, which it answers with the same cookie.
+#[derive(Debug, Clone)]
+pub struct GetCvar {
+	pub cookie: i32,
+	pub name: RawString
+}
+
+impl GetCvar {
+	pub fn parse(bits: &mut BitReader, _version: ProtocolVersion) -> Result<Self, BitParseError> {
+		Ok(GetCvar {
+			cookie: bits.read_i32()?,
+			name: bits.read_string_bytes_bounded(MAX_CVAR_NAME_LENGTH).map(RawString)?
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitstream::BitWriter;
+	use crate::data_table::{Flag, RowData};
+	use super::*;
+
+	const OLD: ProtocolVersion = ProtocolVersion(7);
+	const CURRENT: ProtocolVersion = ProtocolVersion(24);
+
+	fn parse(data: &Bits, kind: PacketKind, version: ProtocolVersion) -> Result<Packet<Bits>, BitParseError> {
+		Packet::parse_with_kind(&mut data.reader(), kind, version).map(Packet::into_owned)
+	}
+
+	#[test]
+	fn disconnect_reasons_are_read() {
+		let mut writer = BitWriter::new();
+		writer.write_string("Kicked by Console");
+
+		match parse(&writer.finish(), PacketKind::Disconnect, CURRENT).unwrap() {
+			Packet::Disconnect(reason) => assert_eq!(reason.bytes(), b"Kicked by Console"),
+			_ => panic!("expected a Disconnect packet")
+		}
+	}
+
+	#[test]
+	fn cvar_names_are_truncated_like_the_engine() {
+		let name = "a".repeat(300);
+
+		let mut writer = BitWriter::new();
+		writer.write_i32(-5);
+		writer.write_string(&name);
+		writer.write_u8(0xAB);
+		let data = writer.finish();
+
+		let mut bits = data.reader();
+
+		match Packet::parse_with_kind(&mut bits, PacketKind::GetCvar, CURRENT).unwrap() {
+			Packet::GetCvar(packet) => {
+				assert_eq!(packet.cookie, -5);
+				assert_eq!(packet.name.bytes(), &name.as_bytes()[..MAX_CVAR_NAME_LENGTH - 1]);
+			},
+			_ => panic!("expected a GetCvar packet")
+		}
+
+		assert_eq!(bits.read_u8().unwrap(), 0xAB);
+	}
+
+	#[test]
+	fn hltv_control_keeps_the_rest_of_the_update() {
+		let mut writer = BitWriter::new();
+		writer.write_u8(1);
+		writer.write_bits(0b101, 3);
+		let data = writer.finish();
+
+		match parse(&data, PacketKind::HltvControl, OLD).unwrap() {
+			Packet::UnparsedHltvControl(rest) => assert_eq!(rest.bits_len(), 11),
+			_ => panic!("expected an HltvControl packet")
+		}
+
+		assert!(parse(&data, PacketKind::HltvControl, ProtocolVersion(23)).is_ok());
+		assert!(parse(&data, PacketKind::HltvControl, CURRENT).is_err());
+	}
+
+	#[test]
+	fn terrain_mods_do_not_exist_in_the_current_protocol() {
+		let mut writer = BitWriter::new();
+		writer.write_u8(2);
+		writer.write_vec3((16.0, -32.0, 8.0));
+		writer.write_bit(false);
+		writer.write_bit(false);
+		writer.write_bit(true);
+		writer.write_coord(64.0);
+		writer.write_coord(1.0);
+		writer.write_vec3((-48.0, -96.0, -56.0));
+		writer.write_vec3((80.0, 32.0, 72.0));
+		writer.write_bit(true);
+		writer.write_bit(false);
+		let data = writer.finish();
+
+		match parse(&data, PacketKind::TerrainMod, OLD).unwrap() {
+			Packet::TerrainMod(packet) => {
+				assert_eq!(packet.kind, 2);
+				assert_eq!(packet.center, (16.0, -32.0, 8.0));
+				assert_eq!(packet.normal, (0.0, 0.0, -1.0));
+				assert_eq!((packet.radius, packet.strength), (64.0, 1.0));
+				assert_eq!((packet.min, packet.max), ((-48.0, -96.0, -56.0), (80.0, 32.0, 72.0)));
+				assert!(packet.suck_to_normal && !packet.stay_above_original);
+			},
+			_ => panic!("expected a TerrainMod packet")
+		}
+
+		assert!(matches!(parse(&data, PacketKind::TerrainMod, CURRENT).err().unwrap().root(), BitParseError::Invalid(_)));
+	}
+
+	/// A send table with an integer row, then the given bits within the length of the table.
+	fn send_table(row_kind: u32, padding: u8) -> Bits {
+		let mut table = BitWriter::new();
+		table.write_string("DT_Example");
+		table.write_bits(1, 10);
+		table.write_bits(row_kind, 5);
+		table.write_string("m_iHealth");
+		table.write_u16(1);
+		table.write_f32(0.0);
+		table.write_f32(0.0);
+		table.write_bits(10, 7);
+		table.write_bits(0, padding);
+		let table = table.finish();
+
+		let mut writer = BitWriter::new();
+		writer.write_bit(true);
+		writer.write_u16(table.bits_len() as u16);
+		writer.write_raw_bits(&table);
+		writer.write_u8(0xAB);
+
+		writer.finish()
+	}
+
+	#[test]
+	fn send_tables_are_framed_by_their_length() {
+		let data = send_table(0, 5);
+		let mut bits = data.reader();
+
+		match Packet::parse_with_kind(&mut bits, PacketKind::DataTable, CURRENT).unwrap() {
+			Packet::DataTable(table) => {
+				assert!(table.needs_decoder);
+				assert_eq!(table.name, "DT_Example");
+				assert_eq!(table.rows.len(), 1);
+				assert_eq!(table.rows[0].name, "m_iHealth");
+				assert_eq!(table.rows[0].data, RowData::Integer { bits: 10 });
+				assert!(table.rows[0].flags.has(Flag::Unsigned));
+			},
+			_ => panic!("expected a DataTable packet")
+		}
+
+		// The padding within the table is skipped
+		assert_eq!(bits.read_u8().unwrap(), 0xAB);
+
+		let err = Packet::parse_with_kind(&mut send_table(9, 0).reader(), PacketKind::DataTable, CURRENT).err().unwrap();
+		assert!(matches!(err.root(), BitParseError::Invalid("unknown row kind")));
+	}
+}
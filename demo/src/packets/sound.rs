@@ -1,4 +1,4 @@
-use bitstream::{BitReader, InsufficientBits};
+use bitstream::{BitRead, BitReader, BitWriter, InsufficientBits, BitParseError, BitDecode, BitEncode};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Sound {
@@ -23,11 +23,13 @@ impl Default for Sound {
 	}
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, BitDecode, BitEncode)]
 pub struct SoundDelta {
-	pub entity: Option<u16>, // 11 bits
-	pub sound_index: Option<u16>, // 13 bits
-	pub flags: Option<Flags>, // 9 bits
+	#[bits(11)]
+	pub entity: Option<u16>,
+	#[bits(13)]
+	pub sound_index: Option<u16>,
+	pub flags: Option<Flags>,
 	pub channel: Option<Channel>,
 	pub ambient: bool,
 	pub sentence: bool
@@ -45,31 +47,9 @@ impl SoundDelta {
 		}
 	}
 
-	pub fn parse(bits: &mut BitReader) -> Result<Self, InsufficientBits> {
-		Ok(SoundDelta {
-			entity: if bits.read_bit()? {
-				Some(bits.read_bits(11)? as u16)
-			} else {
-				None
-			},
-			sound_index: if bits.read_bit()? {
-				Some(bits.read_bits(13)? as u16)
-			} else {
-				None
-			},
-			flags: if bits.read_bit()? {
-				Some(Flags(bits.read_bits(9)? as u16))
-			} else {
-				None
-			},
-			channel: if bits.read_bit()? {
-				Some(Channel::from_id(bits.read_bits(3)? as u8))
-			} else {
-				None
-			},
-			ambient: bits.read_bit()?,
-			sentence: bits.read_bit()?
-		})
+	pub fn parse(bits: &mut BitReader) -> Result<Self, BitParseError> {
+		// The encoding of sounds does not depend on the protocol version
+		Self::decode(bits, 0)
 	}
 }
 
@@ -133,7 +113,7 @@ impl SequenceUpdate {
 	pub fn derive(old: u16, new: u16) -> Self {
 		if old == new {
 			SequenceUpdate::Unchanged
-		} else if old + 1 == new {
+		} else if old.wrapping_add(1) == new {
 			SequenceUpdate::Increment
 		} else {
 			SequenceUpdate::Full(new)
@@ -143,7 +123,7 @@ impl SequenceUpdate {
 	pub fn apply(&self, old: u16) -> u16 {
 		match *self {
 			SequenceUpdate::Unchanged => old,
-			SequenceUpdate::Increment => old + 1,
+			SequenceUpdate::Increment => old.wrapping_add(1),
 			SequenceUpdate::Full(new) => new
 		}
 	}
@@ -176,7 +156,66 @@ impl Channel {
 			_ => unreachable!()
 		}
 	}
+
+	fn id(self) -> u8 {
+		match self {
+			Channel::Auto     => 0,
+			Channel::Weapon   => 1,
+			Channel::Voice    => 2,
+			Channel::Item     => 3,
+			Channel::Body     => 4,
+			Channel::Stream   => 5,
+			Channel::Static   => 6,
+			Channel::Reserved => 7
+		}
+	}
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct Flags(pub u16);
\ No newline at end of file
+impl BitDecode for Channel {
+	fn decode(bits: &mut BitReader, _protocol: u32) -> Result<Self, BitParseError> {
+		Ok(Channel::from_id(bits.read_bits(3)? as u8))
+	}
+}
+
+impl BitEncode for Channel {
+	fn encode(&self, bits: &mut BitWriter, _protocol: u32) {
+		bits.write_bits(self.id() as u32, 3)
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, BitDecode, BitEncode)]
+pub struct Flags(#[bits(9)] pub u16);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sequence_numbers_wrap() {
+		assert_eq!(SequenceUpdate::derive(u16::MAX, 0), SequenceUpdate::Increment);
+		assert_eq!(SequenceUpdate::Increment.apply(u16::MAX), 0);
+		assert_eq!(SequenceUpdate::Full(3).apply(u16::MAX), 3);
+	}
+
+	#[test]
+	fn deltas_round_trip() {
+		let delta = SoundDelta {
+			entity: Some(2047),
+			sound_index: None,
+			flags: Some(Flags(0x101)),
+			channel: Some(Channel::Voice),
+			ambient: false,
+			sentence: true
+		};
+
+		let mut writer = BitWriter::new();
+		delta.encode(&mut writer, 0);
+
+		let bits = writer.finish();
+		assert_eq!(bits.bits_len(), 1 + 11 + 1 + 1 + 9 + 1 + 3 + 2);
+
+		let decoded = SoundDelta::parse(&mut bits.reader()).unwrap();
+		assert_eq!(decoded, delta);
+		assert_eq!(decoded.apply(&Sound::default()).channel, Channel::Voice);
+	}
+}
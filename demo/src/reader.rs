@@ -0,0 +1,252 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use crate::frame::{DemoProtocol, Frame, FrameKind, FrameParseError};
+use crate::header::{DemoHeader, DemoHeaderBuf, HeaderReadError, HEADER_LENGTH};
+use crate::index::{IndexEntry, TickIndex};
+
+/// Reads the frames of a demo one after another, keeping track of where each frame starts and
+/// whether it is part of the signon data. Iteration ends after the `Stop` frame, at the end of
+/// the input, or after the first error.
+pub struct DemoReader<R> {
+	input: R,
+	header: DemoHeaderBuf,
+	protocol: DemoProtocol,
+	/// Offset of the next frame from the start of the file.
+	position: u64,
+	/// Offset of the frame that was returned last.
+	frame_offset: u64,
+	done: bool
+}
+
+impl<R> DemoReader<R> where R: Read {
+	/// Reads the header from the start of `input`.
+	pub fn new(mut input: R) -> Result<Self, HeaderReadError> {
+		let header = DemoHeader::read(&mut input)?;
+
+		Ok(DemoReader::with_header(header, input))
+	}
+
+	/// Creates a reader for input that is positioned right after the header, for when the header
+	/// was already read separately.
+	pub fn with_header(header: DemoHeaderBuf, input: R) -> Self {
+		DemoReader {
+			input,
+			protocol: DemoProtocol::from_header(&header.as_header()),
+			header,
+			position: HEADER_LENGTH as u64,
+			frame_offset: HEADER_LENGTH as u64,
+			done: false
+		}
+	}
+
+	pub fn header(&self) -> &DemoHeaderBuf {
+		&self.header
+	}
+
+	/// The frame layout, based on the header. It can be overridden with `set_protocol` if the
+	/// guess of `DemoProtocol::from_header` is wrong.
+	pub fn protocol(&self) -> DemoProtocol {
+		self.protocol
+	}
+
+	pub fn set_protocol(&mut self, protocol: DemoProtocol) {
+		self.protocol = protocol;
+	}
+
+	/// Offset from the start of the file where the frames after the signon data start.
+	pub fn signon_end(&self) -> u64 {
+		HEADER_LENGTH as u64 + self.header.signon_length.max(0) as u64
+	}
+
+	/// Offset from the start of the file of the frame that was returned last.
+	pub fn frame_offset(&self) -> u64 {
+		self.frame_offset
+	}
+
+	/// Offset from the start of the file right after the frame that was returned last, where the
+	/// next frame starts.
+	pub fn position(&self) -> u64 {
+		self.position
+	}
+
+	/// Whether the frame that was returned last is part of the signon data, according to the
+	/// `signon_length` in the header.
+	pub fn in_signon(&self) -> bool {
+		self.frame_offset < self.signon_end()
+	}
+
+	pub fn into_inner(self) -> R {
+		self.input
+	}
+
+	fn read_frame(&mut self) -> Result<Option<Frame>, FrameParseError> {
+		let mut input = CountingReader { inner: &mut self.input, count: 0 };
+		let mut kind_id = [0; 1];
+
+		// Running out of input between frames is a clean end, unlike running out within a frame
+		let kind_id = loop {
+			match input.read(&mut kind_id) {
+				Ok(0) => return Ok(None),
+				Ok(_) => break kind_id[0],
+				Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err.into())
+			}
+		};
+
+		let kind = FrameKind::from_id(kind_id, self.protocol).ok_or(FrameParseError::BadFrameKind { kind_id })?;
+		let frame = Frame::parse_with_kind(&mut input, kind, self.protocol)?;
+
+		self.frame_offset = self.position;
+		self.position += input.count;
+
+		Ok(Some(frame))
+	}
+}
+
+impl<R> DemoReader<R> where R: Read + Seek {
+	/// Indexes every frame of the demo, see `TickIndex::build`. The reader stays where it was.
+	pub fn build_index(&mut self) -> Result<TickIndex, FrameParseError> {
+		self.input.seek(SeekFrom::Start(HEADER_LENGTH as u64))?;
+
+		let index = TickIndex::build(&mut self.input, self.protocol);
+
+		self.input.seek(SeekFrom::Start(self.position))?;
+
+		index
+	}
+
+	/// Moves the reader to the last frame with a tick before or at `tick`, so that it is the next
+	/// frame returned, and returns its index entry. If every frame comes after `tick`, the reader
+	/// is not moved and None is returned.
+	///
+	/// Skipping frames also skips the string table and entity updates in them, so the state built
+	/// up from the frames before the new position is the responsibility of the caller.
+	///
+	/// The index must have been built with the same protocol as the reader, otherwise an error of
+	/// kind `InvalidInput` is returned.
+	pub fn seek_to_tick(&mut self, index: &TickIndex, tick: u32) -> Result<Option<IndexEntry>, io::Error> {
+		if index.protocol() != self.protocol {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "index was built for a different demo protocol"));
+		}
+
+		let entry = match index.find(tick) {
+			Some(&entry) => entry,
+			None => return Ok(None)
+		};
+
+		self.input.seek(SeekFrom::Start(entry.offset))?;
+
+		self.position = entry.offset;
+		self.frame_offset = entry.offset;
+		self.done = false;
+
+		Ok(Some(entry))
+	}
+}
+
+impl<R> Iterator for DemoReader<R> where R: Read {
+	type Item = Result<Frame, FrameParseError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+
+		let result = self.read_frame();
+
+		self.done = match result {
+			Ok(Some(ref frame)) => frame.payload.kind() == FrameKind::Stop,
+			_ => true
+		};
+
+		result.transpose()
+	}
+}
+
+/// Counts the bytes read, so that the end of each frame is known.
+struct CountingReader<'r, R> {
+	inner: &'r mut R,
+	count: u64
+}
+
+impl<R> Read for CountingReader<'_, R> where R: Read {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.count += read as u64;
+
+		Ok(read)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::fixture;
+	use crate::frame::FramePayload;
+	use super::*;
+
+	#[test]
+	fn frames_are_read_with_their_offsets() {
+		let mut demo = fixture::demo();
+		let lengths = fixture::frame_lengths();
+
+		let signon_length = (lengths[0] + lengths[1]) as i32;
+		demo[HEADER_LENGTH - 4..HEADER_LENGTH].copy_from_slice(&signon_length.to_le_bytes());
+
+		// Anything after the Stop frame is not read
+		demo.push(0xFF);
+
+		let mut reader = DemoReader::new(&demo[..]).unwrap();
+		assert_eq!(reader.signon_end(), HEADER_LENGTH as u64 + signon_length as u64);
+
+		let mut offset = HEADER_LENGTH as u64;
+		let mut signon = Vec::new();
+
+		for expected in fixture::frames() {
+			let frame = reader.next().unwrap().unwrap();
+
+			assert_eq!(frame.tick, expected.tick);
+			assert_eq!(frame.payload.kind(), expected.payload.kind());
+			assert_eq!(reader.frame_offset(), offset);
+
+			offset = reader.position();
+			signon.push(reader.in_signon());
+		}
+
+		assert_eq!(signon, [true, true, false, false, false, false, false, false]);
+		assert_eq!(offset, demo.len() as u64 - 1);
+		assert!(reader.next().is_none());
+	}
+
+	#[test]
+	fn input_ending_between_frames_ends_cleanly() {
+		let demo = fixture::demo();
+		let kept: u64 = fixture::frame_lengths()[..3].iter().sum();
+
+		let frames: Vec<_> = DemoReader::new(&demo[..HEADER_LENGTH + kept as usize]).unwrap().collect();
+
+		assert_eq!(frames.len(), 3);
+		assert!(frames.iter().all(Result::is_ok));
+	}
+
+	#[test]
+	fn iteration_stops_after_an_error() {
+		let demo = fixture::demo();
+		let kept: u64 = fixture::frame_lengths()[..4].iter().sum();
+
+		let mut reader = DemoReader::new(&demo[..HEADER_LENGTH + kept as usize - 1]).unwrap();
+
+		assert!(matches!(reader.nth(2), Some(Ok(Frame { payload: FramePayload::TickSync, .. }))));
+		assert!(matches!(reader.next(), Some(Err(FrameParseError::Io(ref err))) if err.kind() == io::ErrorKind::UnexpectedEof));
+		assert!(reader.next().is_none());
+	}
+
+	#[test]
+	fn unknown_frame_kinds_are_reported() {
+		let mut demo = fixture::header().to_bytes().to_vec();
+		demo.push(12);
+
+		let mut reader = DemoReader::new(&demo[..]).unwrap();
+
+		assert!(matches!(reader.next(), Some(Err(FrameParseError::BadFrameKind { kind_id: 12 }))));
+		assert!(reader.next().is_none());
+	}
+}
@@ -0,0 +1,182 @@
+//! Recovery of demos that were cut short, for example because the game crashed while recording.
+//! Such demos are missing their `Stop` frame, and the header still has the placeholder values
+//! that were written when the recording started.
+
+use std::io::{self, Read, Write};
+use bitstream::{BitRead, BitReader};
+use crate::DemoReader;
+use crate::frame::{DemoProtocol, Frame, FrameParseError, FramePayload};
+use crate::header::DemoHeaderBuf;
+use crate::packets::{Packet, PacketKind, ProtocolVersion};
+use crate::writer::Tally;
+
+/// The result of scanning the frames of a demo with `scan`.
+#[derive(Debug, Clone)]
+pub struct Repair {
+	/// The original header, with the playback length fields replaced by the ones found by the
+	/// scan. The signon length is only replaced if it was 0.
+	pub header: DemoHeaderBuf,
+	/// Amount of bytes after the header up to the end of the last frame that could be parsed.
+	pub frames_length: u64,
+	/// Whether the frames ended with a `Stop` frame, in which case the demo was not cut short.
+	pub stopped: bool,
+	/// Tick interval announced by the server, used to compute the playback time. If the signon
+	/// data did not contain a `ServerInfo`, the playback time is left as it was.
+	pub tick_interval: Option<f32>
+}
+
+/// Reads frames from `frames`, which must be positioned right after the header, until the `Stop`
+/// frame or the first frame that cannot be parsed, and computes the header values that the game
+/// would have written. `ticks` is the tick of the last frame, `frames` is the number of update
+/// frames, including signon updates, and `playback_seconds` is `ticks * tick_interval`.
+///
+/// Frames that fail to parse are treated as the point where the demo was cut off. Only I/O errors
+/// other than an unexpected end of file are returned.
+pub fn scan<R>(header: &DemoHeaderBuf, frames: &mut R) -> Result<Repair, io::Error> where R: Read {
+	let version = ProtocolVersion(header.network_protocol as u32);
+	let mut reader = DemoReader::with_header(header.clone(), frames);
+
+	let mut tally = Tally::default();
+	let mut stopped = false;
+	let mut tick_interval = None;
+
+	while let Some(frame) = reader.next() {
+		let frame = match frame {
+			Ok(frame) => frame,
+			Err(FrameParseError::Io(err)) if err.kind() != io::ErrorKind::UnexpectedEof => return Err(err),
+			Err(_) => break
+		};
+
+		tally.record(&frame, reader.position() - reader.frame_offset());
+
+		match frame.payload {
+			FramePayload::SignonUpdate(update) if tick_interval.is_none() => {
+				tick_interval = find_tick_interval(&update.packets, version);
+			},
+			FramePayload::Stop => stopped = true,
+			_ => ()
+		}
+	}
+
+	let mut header = header.clone();
+	let signon_length = header.signon_length;
+
+	tally.apply(&mut header);
+
+	if signon_length != 0 {
+		header.signon_length = signon_length;
+	}
+
+	if let Some(tick_interval) = tick_interval {
+		header.playback_seconds = header.ticks as f32 * tick_interval;
+	}
+
+	let frames_length = tally.length;
+
+	Ok(Repair { header, frames_length, stopped, tick_interval })
+}
+
+impl Repair {
+	/// Writes the repaired demo: the corrected header, the first `frames_length` bytes of
+	/// `frames`, and a `Stop` frame if the original did not have one. `frames` must be positioned
+	/// right after the original header.
+	pub fn write_to<R, W>(&self, frames: &mut R, output: &mut W) -> Result<(), io::Error> where R: Read, W: Write {
+		self.header.write_to(output)?;
+
+		let copied = io::copy(&mut frames.take(self.frames_length), output)?;
+
+		if copied != self.frames_length {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "demo frames are shorter than when they were scanned"));
+		}
+
+		if !self.stopped {
+			let stop = Frame { tick: self.header.ticks as u32, player_slot: 0, payload: FramePayload::Stop };
+
+			stop.write(output, DemoProtocol::from_header(&self.header.as_header()))?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Looks for the `ServerInfo` packet within the packets of a signon update.
+fn find_tick_interval(packets: &[u8], version: ProtocolVersion) -> Option<f32> {
+	let mut bits = BitReader::new(packets);
+
+	while bits.has_remaining(version.packet_kind_bits() as usize) {
+		let kind = PacketKind::from_id(bits.read_bits(version.packet_kind_bits()).ok()? as u8)?;
+
+		if let Packet::ServerInfo(info) = Packet::parse_with_kind(&mut bits, kind, version).ok()? {
+			return Some(info.tick_interval);
+		}
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::fixture;
+	use crate::header::{DemoHeader, HEADER_LENGTH};
+	use super::*;
+
+	#[test]
+	fn demos_cut_short_are_completed() {
+		let demo = fixture::demo();
+		let lengths = fixture::frame_lengths();
+
+		// Cut the demo off halfway through the last update
+		let kept: u64 = lengths[..6].iter().sum();
+		let cut = &demo[HEADER_LENGTH..HEADER_LENGTH + kept as usize + 10];
+
+		let repair = scan(&fixture::header(), &mut &cut[..]).unwrap();
+
+		assert!(!repair.stopped);
+		assert_eq!(repair.frames_length, kept);
+		assert_eq!(repair.tick_interval, Some(fixture::TICK_INTERVAL));
+		assert_eq!(repair.header.ticks, 1);
+		assert_eq!(repair.header.frames, 2);
+		assert_eq!(repair.header.signon_length as u64, lengths[0] + lengths[1]);
+		assert_eq!(repair.header.playback_seconds, fixture::TICK_INTERVAL);
+
+		let mut repaired = Vec::new();
+		repair.write_to(&mut &cut[..], &mut repaired).unwrap();
+
+		let mut input = &repaired[..];
+		let header = DemoHeader::read(&mut input).unwrap();
+		assert_eq!(header.ticks, 1);
+
+		let frames: Vec<_> = DemoReader::with_header(header, input).collect::<Result<_, _>>().unwrap();
+		assert_eq!(frames.len(), 7);
+		assert!(matches!(frames[6].payload, FramePayload::Stop));
+		assert_eq!(frames[6].tick, 1);
+	}
+
+	#[test]
+	fn complete_demos_are_left_as_they_are() {
+		let demo = fixture::demo();
+		let mut header = fixture::header();
+		header.signon_length = 1234;
+
+		let repair = scan(&header, &mut &demo[HEADER_LENGTH..]).unwrap();
+
+		assert!(repair.stopped);
+		assert_eq!(repair.frames_length, (demo.len() - HEADER_LENGTH) as u64);
+		assert_eq!(repair.header.ticks, 10);
+		assert_eq!(repair.header.frames, 3);
+		assert_eq!(repair.header.signon_length, 1234);
+
+		let mut repaired = Vec::new();
+		repair.write_to(&mut &demo[HEADER_LENGTH..], &mut repaired).unwrap();
+		assert_eq!(&repaired[HEADER_LENGTH..], &demo[HEADER_LENGTH..]);
+	}
+
+	#[test]
+	fn frames_shorter_than_scanned_are_an_error() {
+		let demo = fixture::demo();
+		let repair = scan(&fixture::header(), &mut &demo[HEADER_LENGTH..]).unwrap();
+
+		let err = repair.write_to(&mut &demo[HEADER_LENGTH..demo.len() - 1], &mut Vec::new()).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+	}
+}
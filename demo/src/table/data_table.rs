@@ -1,4 +1,4 @@
-use bitstream::{BitReader, InsufficientBits, BitParseError};
+use bitstream::{BitRead, BitReader, InsufficientBits, BitParseError, CoordType};
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Debug)]
@@ -72,6 +72,13 @@ pub struct DataTable {
 impl DataTable {
 	pub fn parse(bits: &mut BitReader) -> Result<Self, DataTableParseError> {
 		let needs_decoder = bits.read_bit()?;
+
+		DataTable::parse_info(bits, needs_decoder)
+	}
+
+	/// Parses the name and rows of the table, for when `needs_decoder` is stored separately, like
+	/// in the `DataTable` packet.
+	pub fn parse_info(bits: &mut BitReader, needs_decoder: bool) -> Result<Self, DataTableParseError> {
 		let name = bits.read_string()?;
 		let entries = bits.read_bits(10)?;
 
@@ -191,8 +198,8 @@ impl Flag {
 			11 => Flag::VectorElem,
 			12 => Flag::Collapsible,
 			13 => Flag::CoordinateMp,
-			14 => Flag::CoordinateMpIntegral,
-			15 => Flag::CoordinateMpLowPrecision,
+			14 => Flag::CoordinateMpLowPrecision,
+			15 => Flag::CoordinateMpIntegral,
 			_ => return None
 		})
 	}
@@ -209,6 +216,19 @@ impl Flags {
 	pub fn iter(self) -> FlagsIter {
 		FlagsIter { flags: self, index: 0 }
 	}
+
+	/// Returns the CoordMP variant to decode the property with, if it uses CoordMP encoding.
+	pub fn coord_mp(&self) -> Option<CoordType> {
+		if self.has(Flag::CoordinateMp) {
+			Some(CoordType::Normal)
+		} else if self.has(Flag::CoordinateMpLowPrecision) {
+			Some(CoordType::LowPrecision)
+		} else if self.has(Flag::CoordinateMpIntegral) {
+			Some(CoordType::Integral)
+		} else {
+			None
+		}
+	}
 }
 
 impl Display for Flags {
@@ -1,4 +1,4 @@
-use bitstream::BitReader;
+use bitstream::{BitRead, BitReader, BitStorage};
 use crate::string_table::StringTable;
 use crate::packets::CreateStringTable;
 use snap::raw::Decoder;
@@ -39,7 +39,7 @@ pub struct NewStringTable {
 }
 
 impl NewStringTable {
-	pub fn from_packet(packet: CreateStringTable) -> Result<Self, StringTableParseError> {
+	pub fn from_packet<D: BitStorage>(packet: CreateStringTable<D>) -> Result<Self, StringTableParseError> {
 		let fixed_extra_size = packet.fixed_userdata_size.map(|(_bytes, bits)| bits);
 		let mut table = StringTable::create(packet.entries as usize, packet.max_entries as usize, fixed_extra_size);
 
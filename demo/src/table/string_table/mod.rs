@@ -1,5 +1,6 @@
 use std::collections::VecDeque;
-use bitstream::{BitReader, InsufficientBits, BitParseError};
+use std::fmt::{self, Display, Formatter};
+use bitstream::{BitRead, BitReader, InsufficientBits, BitParseError, ParseContext};
 
 mod create;
 
@@ -141,22 +142,22 @@ impl StringTable {
 		let max_index = (self.strings.len() - 1) as u32;
 
 		for _ in 0..updated {
-			let index = if bits.read_bit()? { None } else { Some(bits.read_bits(index_bits)?) };
+			let index = if bits.read_bit().context("index")? { None } else { Some(bits.read_bits(index_bits).context("index")?) };
 
 			let partial;
 			let string;
 
-			if bits.read_bit()? {
-				partial = if bits.read_bit()? {
+			if bits.read_bit().context("string")? {
+				partial = if bits.read_bit().context("partial")? {
 					Some(Partial {
-						history_index: bits.read_bits(5)? as u8,
-						matching: bits.read_bits(5)? as u8
+						history_index: bits.read_bits(5).context("partial")? as u8,
+						matching: bits.read_bits(5).context("partial")? as u8
 					})
 				} else {
 					None
 				};
 
-				string = Some(bits.read_string()?);
+				string = Some(bits.read_string().context("string")?);
 			} else {
 				partial = None;
 				string = None;
@@ -170,16 +171,16 @@ impl StringTable {
 
 			let (index, string) = tracker.read(row).map_err(StringTableParseError::InvalidHistoryIndex)?;
 
-			let extra = if bits.read_bit()? {
+			let extra = if bits.read_bit().context("extra")? {
 				match self.fixed_extra_size {
 					Some(bits_len) => {
-						let data = bits.read_bits(bits_len)?;
+						let data = bits.read_bits(bits_len).context("extra")?;
 
 						Extra::Bits { count: bits_len, data: data as u16 }
 					}
 					None => {
-						let bytes = bits.read_bits(14)? as u16;
-						let data = bits.read_u8_array(bytes as usize)?;
+						let bytes = bits.read_bits(14).context("extra")? as u16;
+						let data = bits.read_u8_array(bytes as usize).context("extra")?;
 
 						Extra::Bytes(data)
 					}
@@ -218,6 +219,12 @@ pub struct InvalidHistoryIndex {
 	len: u8
 }
 
+impl Display for InvalidHistoryIndex {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "history index {} is out of bounds for a history of {} strings", self.index, self.len)
+	}
+}
+
 struct Partial {
 	history_index: u8,
 	matching: u8
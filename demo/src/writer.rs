@@ -0,0 +1,199 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use crate::frame::{DemoProtocol, Frame, FrameKind};
+use crate::header::{DemoHeaderBuf, HEADER_LENGTH};
+
+/// Writes a demo frame by frame. The header is written first as it was given, and written again
+/// by `finish` in case it was changed in the meantime. Unless `fill_in_header` is called, the
+/// header is left as it was, so that a demo that is read and written back stays byte for byte
+/// identical.
+pub struct DemoWriter<W> {
+	output: W,
+	header: DemoHeaderBuf,
+	protocol: DemoProtocol,
+	tally: Tally
+}
+
+impl<W> DemoWriter<W> where W: Write + Seek {
+	/// Writes the header to the start of `output`.
+	pub fn new(header: DemoHeaderBuf, mut output: W) -> Result<Self, io::Error> {
+		header.write_to(&mut output)?;
+
+		let protocol = DemoProtocol::from_header(&header.as_header());
+
+		Ok(DemoWriter { output, header, protocol, tally: Tally::default() })
+	}
+
+	/// The frame layout, based on the header, see `DemoReader::protocol`.
+	pub fn protocol(&self) -> DemoProtocol {
+		self.protocol
+	}
+
+	pub fn set_protocol(&mut self, protocol: DemoProtocol) {
+		self.protocol = protocol;
+	}
+
+	pub fn header(&self) -> &DemoHeaderBuf {
+		&self.header
+	}
+
+	/// Changes the header that is written by `finish`.
+	pub fn header_mut(&mut self) -> &mut DemoHeaderBuf {
+		&mut self.header
+	}
+
+	pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+		let mut output = CountingWriter { inner: &mut self.output, count: 0 };
+		frame.write(&mut output, self.protocol)?;
+
+		let len = output.count;
+		self.tally.record(frame, len);
+
+		Ok(())
+	}
+
+	/// Sets `ticks`, `frames` and `signon_length` in the header to match the frames written so
+	/// far, the same way that the game fills them in when it stops recording. The signon data is
+	/// assumed to end at the first frame that is not a signon update, data tables or string tables
+	/// frame.
+	pub fn fill_in_header(&mut self) {
+		self.tally.apply(&mut self.header);
+	}
+
+	/// Rewrites the header, and returns the output positioned after the last frame.
+	pub fn finish(mut self) -> Result<W, io::Error> {
+		self.output.seek(SeekFrom::Start(0))?;
+		self.header.write_to(&mut self.output)?;
+		self.output.seek(SeekFrom::Start(HEADER_LENGTH as u64 + self.tally.length))?;
+
+		Ok(self.output)
+	}
+}
+
+/// The header fields that follow from the frames of a demo, collected frame by frame.
+#[derive(Debug, Default)]
+pub(crate) struct Tally {
+	/// Amount of bytes of frames after the header.
+	pub length: u64,
+	/// Length of the signon data, once the first frame after it is recorded.
+	pub signon_length: Option<u64>,
+	pub ticks: u32,
+	/// Amount of update frames, including signon updates.
+	pub frames: u32
+}
+
+impl Tally {
+	pub fn record(&mut self, frame: &Frame, len: u64) {
+		let kind = frame.payload.kind();
+
+		// The signon data consists of the frames leading up to the first regular frame.
+		let signon = matches!(kind, FrameKind::SignonUpdate | FrameKind::DataTables | FrameKind::StringTables);
+
+		if !signon && self.signon_length.is_none() {
+			self.signon_length = Some(self.length);
+		}
+
+		if matches!(kind, FrameKind::SignonUpdate | FrameKind::Update) {
+			self.frames += 1;
+		}
+
+		self.length += len;
+		self.ticks = self.ticks.max(frame.tick);
+	}
+
+	/// Fills in `ticks`, `frames` and `signon_length`, leaving `playback_seconds` alone since it
+	/// depends on the tick interval.
+	pub fn apply(&self, header: &mut DemoHeaderBuf) {
+		header.ticks = self.ticks as i32;
+		header.frames = self.frames as i32;
+		header.signon_length = self.signon_length.unwrap_or(self.length) as i32;
+	}
+}
+
+/// Counts the bytes written, so that the length of each frame is known.
+struct CountingWriter<'w, W> {
+	inner: &'w mut W,
+	count: u64
+}
+
+impl<W> Write for CountingWriter<'_, W> where W: Write {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.inner.write(buf)?;
+		self.count += written as u64;
+
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use crate::DemoReader;
+	use crate::fixture;
+	use crate::frame::{ConsoleCommandFrame, FramePayload};
+	use crate::header::DemoHeader;
+	use super::*;
+
+	fn rewrite(demo: &[u8], fill_in_header: bool) -> Vec<u8> {
+		let mut reader = DemoReader::new(demo).unwrap();
+		let mut writer = DemoWriter::new(reader.header().clone(), Cursor::new(Vec::new())).unwrap();
+
+		for frame in &mut reader {
+			writer.write_frame(&frame.unwrap()).unwrap();
+		}
+
+		if fill_in_header {
+			writer.fill_in_header();
+		}
+
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn demos_are_rewritten_byte_for_byte() {
+		let mut demo = fixture::demo();
+
+		// Values that do not match the frames must be kept as they are
+		demo[HEADER_LENGTH - 12..HEADER_LENGTH].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+		assert_eq!(rewrite(&demo, false), demo);
+	}
+
+	#[test]
+	fn console_commands_are_kept_as_they_were() {
+		let frame = Frame {
+			tick: 3,
+			player_slot: 0,
+			payload: FramePayload::ConsoleCommand(ConsoleCommandFrame::from_raw(b"say \xFF\0left over\0".to_vec()))
+		};
+
+		let mut demo = fixture::header().to_bytes().to_vec();
+		frame.write(&mut demo, fixture::protocol()).unwrap();
+
+		assert_eq!(rewrite(&demo, false), demo);
+
+		let command = match DemoReader::new(&demo[..]).unwrap().next() {
+			Some(Ok(Frame { payload: FramePayload::ConsoleCommand(command), .. })) => command,
+			other => panic!("expected a console command, got {:?}", other)
+		};
+
+		assert_eq!(command.command_bytes(), b"say \xFF");
+		assert!(command.to_str().is_err());
+	}
+
+	#[test]
+	fn header_is_filled_in_when_asked() {
+		let demo = rewrite(&fixture::demo(), true);
+		let lengths = fixture::frame_lengths();
+
+		let header = DemoHeader::read(&mut &demo[..]).unwrap();
+
+		assert_eq!(header.ticks, 10);
+		assert_eq!(header.frames, 3);
+		assert_eq!(header.signon_length as u64, lengths[0] + lengths[1]);
+		assert_eq!(&demo[HEADER_LENGTH..], &fixture::demo()[HEADER_LENGTH..]);
+	}
+}
@@ -1,6 +1,7 @@
 mod data;
 
 use super::Handler;
+use bitstream::BitSlice;
 use demo::packets::{Packet, GameEvent};
 use demo::packets::game_events::{GameEventList, GameEventInfo, Kind};
 use data::Value;
@@ -10,7 +11,7 @@ struct ShowGameEvents {
 }
 
 impl Handler for ShowGameEvents {
-	fn packet(&mut self, packet: Packet) {
+	fn packet(&mut self, packet: Packet<BitSlice>) {
 		match packet {
 			Packet::GameEventList(GameEventList(event_list)) => {
 				println!("List of game events:");
@@ -54,7 +55,7 @@ impl Handler for ShowGameEvents {
 				for &(kind, ref name) in &game_event.properties {
 					let value = match kind {
 						Kind::End    => unreachable!(),
-						Kind::Str    => Value::Str (reader.read_string().unwrap()),
+						Kind::Str    => Value::Str (reader.read_string_lossy().unwrap()),
 						Kind::F32    => Value::F32 (reader.read_f32().unwrap()),
 						Kind::I32    => Value::I32 (reader.read_i32().unwrap()),
 						Kind::I16    => Value::I16 (reader.read_i16().unwrap()),
@@ -63,7 +64,7 @@ impl Handler for ShowGameEvents {
 						Kind::Unused => unimplemented!()
 					};
 
-					values.insert(name.to_owned(), value);
+					values.insert(name.to_string(), value);
 
 
 
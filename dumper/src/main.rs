@@ -1,14 +1,18 @@
 // TODO: pub mod game_events;
 pub mod voice;
-// TODO: pub mod print_all;
-
-use demo::header::{self, DemoHeader};
-use bitstream::Bits;
+pub mod repair;
+// Only used when swapped in for DumpVoiceData below
+#[allow(dead_code)]
+pub mod print_all;
+
+use demo::DemoReader;
+use demo::header::{DemoHeader, HeaderReadError};
+use bitstream::{BitRead, BitReader, BitSlice};
 use demo::packets::{ProtocolVersion, PacketKind, Packet};
 use demo::string_table::{StringTables, Extra};
-use demo::frame::{Frame, FramePayload};
+use demo::frame::{FramePayload, UserCmdTracker};
 
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufReader};
 use std::fs::File;
 
 const MAX_PARSED_PACKETS: usize = 4096;
@@ -18,16 +22,21 @@ const SHOW_DATA_TABLES: bool = false;
 const SHOW_STRING_TABLE_CONTENTS: bool = false;
 const SHOW_FRAME_HEADER_SPAM: bool = false;
 const SHOW_COMMANDS: bool = false;
+const SHOW_USER_CMDS: bool = false;
 
 pub trait Handler {
-	fn packet(&mut self, packet: Packet);
+	fn packet(&mut self, packet: Packet<BitSlice>);
 }
 
 fn main() {
-	let path = match std::env::args().skip(1).next() {
-		Some(path) => path,
-		None => {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+
+	let path = match args.as_slice() {
+		[command, input, output] if command == "repair" => return repair::run(input, output),
+		[path] => path,
+		_ => {
 			eprintln!("Usage: demoman <file>");
+			eprintln!("       demoman repair <input> <output>");
 			return;
 		}
 	};
@@ -41,30 +50,27 @@ fn main() {
 		}
 	};
 
-	let mut file = BufReader::new(file);
-
-	let mut buf = [0; header::HEADER_LENGTH];
-
-	if let Err(err) = file.read_exact(&mut buf[0..]) {
-		eprintln!("error while reading demo file header: {:?}", err);
+	let mut frames = match DemoReader::new(BufReader::new(file)) {
+		Ok(frames) => frames,
+		Err(HeaderReadError::Io(err)) => {
+			eprintln!("error while reading demo file header: {}", err);
 
-		if err.kind() == io::ErrorKind::UnexpectedEof {
-			eprintln!("note: Demo file is too short to possibly be a valid demo file")
-		}
-
-		return
-	}
+			if err.kind() == io::ErrorKind::UnexpectedEof {
+				eprintln!("note: Demo file is too short to possibly be a valid demo file")
+			}
 
-	let demo = match DemoHeader::parse(&buf) {
-		Ok(header) => header,
+			return
+		},
 		Err(err) => {
-			eprintln!("error while reading demo file header: {:?}", err);
+			eprintln!("error while reading demo file header: {}", err);
 			eprintln!("note: Demo file had incorrect magic value, expected HL2DEMO\\0 at start of file");
 			eprintln!("note: This doesn't appear to be a valid demo file");
 
 			return
 		}
 	};
+	let header = frames.header().clone();
+	let demo = header.as_header();
 
 	println!("Demo protocol {}, carrying network protocol {}", demo.demo_protocol, demo.network_protocol);
 	println!("Server: {:?}", demo.server_name);
@@ -73,8 +79,6 @@ fn main() {
 	println!("Game directory: {:?}", demo.game_directory);
 	println!("Time: {} seconds, {} ticks, {} frames", demo.playback_seconds, demo.ticks, demo.frames);
 
-	let signon_end = header::HEADER_LENGTH as u64 + demo.signon_length as u64;
-
 	println!();
 	println!("-- START OF SIGNON DATA ({} bytes) --", demo.signon_length);
 	println!();
@@ -83,18 +87,24 @@ fn main() {
 	//let mut handler = PrintAll;
 	let mut handler = voice::DumpVoiceData;
 
+	let mut in_signon = true;
+	let mut user_cmds = UserCmdTracker::new();
+
 	// Iterate over a limited amount of packets
 	for _ in 0..MAX_PARSED_PACKETS {
-		let offset = file.seek(SeekFrom::Current(0)).unwrap();
+		let frame = match frames.next() {
+			Some(frame) => frame.unwrap(),
+			None => break
+		};
+
+		if in_signon && !frames.in_signon() {
+			in_signon = false;
 
-		if offset == signon_end {
 			println!();
 			println!("-- END OF SIGNON DATA --");
 			println!();
 		}
 
-		let frame = Frame::parse(&mut file).unwrap();
-
 		if SHOW_FRAME_HEADER_SPAM {
 			print!("T: {} ", frame.tick);
 		}
@@ -102,16 +112,23 @@ fn main() {
 		match frame.payload {
 			FramePayload::SignonUpdate(update) | FramePayload::Update(update) => {
 				if SHOW_FRAME_HEADER_SPAM {
-					println!("| Update ({} packet bytes) [OFFS:{}]", update.packets.len(), file.seek(SeekFrom::Current(0)).unwrap());
+					println!("| Update ({} packet bytes) [OFFS:{}]", update.packets.len(), frames.position());
 				}
 
 				parse_update(update.packets, &demo, &mut handler);
 			},
 			FramePayload::TickSync => println!("| Tick Sync"),
-			FramePayload::ConsoleCommand(command) => if SHOW_COMMANDS { println!("> {}", command) },
-			FramePayload::UserCmdDelta { sequence: _, frame } => {
-				let _frame = frame.parse().unwrap();
-				/*println!("| UserCmdDelta (hidden)")*/()
+			FramePayload::ConsoleCommand(command) => if SHOW_COMMANDS {
+				for command in command.parse().unwrap() {
+					println!("> {}", command);
+				}
+			},
+			FramePayload::UserCmdDelta { sequence, frame } => {
+				let delta = frame.parse().unwrap();
+
+				if let Some(command) = user_cmds.apply(sequence, &delta) {
+					if SHOW_USER_CMDS { println!("| UserCmd #{}: {:?}", sequence, command) }
+				}
 			},
 			FramePayload::DataTables(tables) => {
 				let tables = tables.parse().unwrap();
@@ -122,6 +139,11 @@ fn main() {
 				println!("| Stop");
 				break;
 			},
+			FramePayload::CustomData { callback, data } => {
+				if SHOW_FRAME_HEADER_SPAM {
+					println!("| Custom Data (callback {}, {} bytes)", callback, data.len())
+				}
+			},
 			FramePayload::StringTables(tables) => {
 				handle_string_table(tables.parse().unwrap())
 			}
@@ -145,8 +167,7 @@ for table in &tables.tables {
 }*/
 
 fn parse_update<H>(data: Vec<u8>, demo: &DemoHeader, handler: &mut H) where H: Handler {
-	let data = Bits::from_bytes(data);
-	let mut bits = data.reader();
+	let mut bits = BitReader::new(&data);
 
 	if demo.network_protocol < 10 {
 		unimplemented!("Network protocols less than 10 do not have fixed_time and fixed_time_stdev in Tick, this is not handled yet!");
@@ -154,12 +175,34 @@ fn parse_update<H>(data: Vec<u8>, demo: &DemoHeader, handler: &mut H) where H: H
 
 	let version = ProtocolVersion(demo.network_protocol as u32);
 
+	// With the trace feature, every update is printed as an annotated dump of the bits read
+	#[cfg(feature = "trace")]
+	bits.enable_trace();
+
 	while bits.has_remaining(version.packet_kind_bits() as usize) {
 		let id = bits.read_bits(version.packet_kind_bits()).unwrap();
 
 		let kind = PacketKind::from_id(id as u8).expect("Packet ID cannot be greater than 31");
 
-		handler.packet(Packet::parse_with_kind(&mut bits, kind, version).unwrap());
+		match Packet::parse_with_kind(&mut bits, kind, version) {
+			Ok(packet) => handler.packet(packet),
+			Err(err) => {
+				#[cfg(feature = "trace")]
+				print_trace(&bits);
+
+				panic!("error while parsing packet: {}", err);
+			}
+		}
+	}
+
+	#[cfg(feature = "trace")]
+	print_trace(&bits);
+}
+
+#[cfg(feature = "trace")]
+fn print_trace(bits: &BitReader) {
+	if let Some(dump) = bits.annotate() {
+		println!("{}", dump);
 	}
 }
 
@@ -170,22 +213,22 @@ fn handle_string_table(tables: StringTables) {
 
 	println!("| String Tables - {} tables", tables.0.len());
 
-	for (index, &(ref name, ref pair)) in tables.0.iter().enumerate() {
+	for (index, (name, pair)) in tables.0.iter().enumerate() {
 		print!("  #{} | {}: {} primary strings, ", index, name, pair.primary.strings.len());
-		match &pair.client {
-			&Some(ref table) => println!("{} client strings", table.strings.len()),
-			&None => println!("no client strings")
+		match pair.client {
+			Some(ref table) => println!("{} client strings", table.strings.len()),
+			None => println!("no client strings")
 		}
 
 		if !SHOW_STRING_TABLE_CONTENTS {
 			continue;
 		}
 
-		for (index, &(ref string, ref extra)) in pair.primary.strings.iter().enumerate() {
+		for (index, (string, extra)) in pair.primary.strings.iter().enumerate() {
 			print!("    #{}: {} ", index, string);
-			match extra {
-				&Extra::Bits { count, data } => println!("= (bit count: {}, bit data: {})", count, data),
-				&Extra::Bytes(ref bytes) => {
+			match *extra {
+				Extra::Bits { count, data } => println!("= (bit count: {}, bit data: {})", count, data),
+				Extra::Bytes(ref bytes) => {
 					print!("= ");
 
 					for &byte in bytes {
@@ -194,7 +237,7 @@ fn handle_string_table(tables: StringTables) {
 
 					println!()
 				},
-				&Extra::None => println!()
+				Extra::None => println!()
 			}
 		}
 	}
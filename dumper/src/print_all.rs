@@ -1,15 +1,16 @@
 use super::Handler;
+use bitstream::{BitRead, BitSlice};
 use demo::packets::{Packet, SetCvars, GameEvent, PlaySound};
 
-struct PrintAll;
+pub struct PrintAll;
 
 impl Handler for PrintAll {
-	fn packet(&mut self, packet: Packet) {
+	fn packet(&mut self, packet: Packet<BitSlice>) {
 		print!("  {:>17} | ", format!("{:?}", packet.kind()));
 
 		match packet {
 			Packet::Nop                       => println!(),
-			Packet::Disconnect                => unimplemented!(),
+			Packet::Disconnect(reason)        => println!("Reason: {:?}", reason),
 			Packet::TransferFile(packet)      => println!("{:?}", packet),
 			Packet::Tick(packet)              => println!("{:?}", packet),
 			Packet::StringCommand(packet)     => println!("{:?}", packet),
@@ -18,21 +19,21 @@ impl Handler for PrintAll {
 
 				println!("{} cvars", cvars.len());
 
-				for &(ref cvar, ref value) in &cvars {
+				for (cvar, value) in &cvars {
 					println!("  {:>17} : {:?} = {:?}", "", cvar, value);
 				}
 			},
 			Packet::SignonState(packet)       => println!("{:?}", packet),
 			Packet::Print(packet)             => println!("{:?}", packet),
 			Packet::ServerInfo(packet)        => println!("{:?}", packet),
-			Packet::DataTable                 => unimplemented!(),
+			Packet::DataTable(table)          => println!("Table: {}, Rows: {}, Needs Decoder: {}", table.name, table.rows.len(), table.needs_decoder),
 			Packet::ClassInfo(packet)         => println!("{:?}", packet),
 			Packet::Pause(paused)             => println!("Is Paused: {}", paused),
 			Packet::CreateStringTable(packet) => println!("Table: {}, Entries: {} / {:?}, Fixed Userdata Size: {:?}, Bits: {}", packet.name, packet.entries, packet.max_entries, packet.fixed_userdata_size, packet.data.bits_len()),
 			Packet::UpdateStringTable(packet) => println!("Table: {}, Entries: {}, Bits: {}", packet.table_id, packet.entries, packet.data.bits_len()),
 			Packet::VoiceInit(packet)         => println!("{:?}", packet),
 			Packet::VoiceData(packet)         => println!("Sender: {}, Proximity: {}, Bits: {}", packet.sender, packet.proximity, packet.data.bits_len()),
-			Packet::HltvControl               => unimplemented!(),
+			Packet::UnparsedHltvControl(data) => println!("Bits: {}", data.bits_len()),
 			Packet::PlaySound(packet)         => match packet {
 				PlaySound::Reliable(data)             => println!("Reliable: {} bits", data.bits_len()),
 				PlaySound::Unreliable { sounds, all } => println!("Unreliable: {} sounds, {} bits", sounds, all.bits_len())
@@ -41,7 +42,7 @@ impl Handler for PrintAll {
 			Packet::FixAngle(packet)         => println!("{:?}", packet),
 			Packet::CrosshairAngle(packet)   => println!("{:?}", packet),
 			Packet::Decal(packet)            => println!("{:?}", packet),
-			Packet::TerrainMod               => unimplemented!(),
+			Packet::TerrainMod(packet)       => println!("{:?}", packet),
 			Packet::UserMessage(packet)      => println!("Channel: {}, Bits: {}", packet.channel, packet.data.bits_len()),
 			Packet::EntityMessage(packet)    => println!("Entity: {}, Class: {}, Bits: {}", packet.entity, packet.class, packet.data.bits_len()),
 			Packet::GameEvent(packet)        => {
@@ -116,13 +117,42 @@ impl Handler for PrintAll {
 			Packet::Prefetch(packet)         => println!("{:?}", packet),
 			Packet::PluginMenu(packet)       => println!("Kind: {}, Bytes: {}", packet.kind, packet.data.len()),
 			Packet::GameEventList(packet)    => println!("{} events not shown", packet.0.len()),
-			Packet::GetCvar                  => {
-				// TODO: BROKEN?
-
-				//println!("Cookie: {}, CVar: {:?}", bits.read_u32(), bits.read_string());
+			Packet::GetCvar(packet)          => println!("Cookie: {}, CVar: {:?}", packet.cookie, packet.name)
+		}
+	}
+}
 
-				println!("  Don't know how to handle a GetCvarValue!");
-			}
+#[cfg(test)]
+mod tests {
+	use bitstream::{BitWriter, RawString};
+	use demo::packets::{GetCvar, TerrainMod};
+	use super::*;
+
+	#[test]
+	fn disconnect_hltv_terrain_and_cvar_packets_are_printed() {
+		let mut writer = BitWriter::new();
+		writer.write_u8(1);
+		let hltv = writer.finish();
+
+		let packets = vec![
+			Packet::Disconnect(RawString(b"Kicked by Console".to_vec())),
+			Packet::UnparsedHltvControl(hltv.as_slice()),
+			Packet::TerrainMod(TerrainMod {
+				kind: 0,
+				center: (0.0, 0.0, 0.0),
+				normal: (0.0, 0.0, 1.0),
+				radius: 64.0,
+				strength: 1.0,
+				min: (-64.0, -64.0, -64.0),
+				max: (64.0, 64.0, 64.0),
+				suck_to_normal: false,
+				stay_above_original: true
+			}),
+			Packet::GetCvar(GetCvar { cookie: 7, name: RawString(b"sv_cheats".to_vec()) })
+		];
+
+		for packet in packets {
+			PrintAll.packet(packet);
 		}
 	}
 }
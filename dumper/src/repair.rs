@@ -0,0 +1,80 @@
+use demo::header::DemoHeader;
+use demo::repair;
+
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use std::fs::{self, File};
+
+/// Writes a copy of the demo at `input` to `output`, with the header fixed up and a `Stop` frame
+/// added if the recording was cut short.
+pub fn run(input: &str, output: &str) {
+	let mut file = match File::open(input) {
+		Ok(file) => BufReader::new(file),
+		Err(err) => {
+			eprintln!("couldn't open demo file for reading: {}", err);
+			return;
+		}
+	};
+
+	let header = match DemoHeader::read(&mut file) {
+		Ok(header) => header,
+		Err(err) => {
+			eprintln!("error while reading demo file header: {}", err);
+			return;
+		}
+	};
+
+	let repair = match repair::scan(&header, &mut file) {
+		Ok(repair) => repair,
+		Err(err) => {
+			eprintln!("error while reading demo frames: {}", err);
+			return;
+		}
+	};
+
+	println!("Ticks: {} -> {}", header.ticks, repair.header.ticks);
+	println!("Frames: {} -> {}", header.frames, repair.header.frames);
+	println!("Time: {} -> {} seconds", header.playback_seconds, repair.header.playback_seconds);
+	println!("Signon length: {} -> {} bytes", header.signon_length, repair.header.signon_length);
+
+	if repair.tick_interval.is_none() {
+		eprintln!("warning: no ServerInfo packet was found, so the playback time was not changed");
+	}
+
+	if repair.stopped {
+		println!("The demo has a Stop frame, only the header was changed");
+	} else {
+		println!("The demo was cut short, keeping {} bytes of frames and adding a Stop frame", repair.frames_length);
+	}
+
+	let header_length = demo::header::HEADER_LENGTH as u64;
+
+	if let Err(err) = file.seek(SeekFrom::Start(header_length)) {
+		eprintln!("couldn't seek back to the start of the frames: {}", err);
+		return;
+	}
+
+	// The copy goes to a temporary file next to the output, which only replaces the output once
+	// it is complete, so that repairing a demo in place can not destroy it halfway through
+	let temp = format!("{}.tmp", output);
+
+	let mut out = match File::create(&temp) {
+		Ok(file) => BufWriter::new(file),
+		Err(err) => {
+			eprintln!("couldn't open output file for writing: {}", err);
+			return;
+		}
+	};
+
+	let written = repair.write_to(&mut file, &mut out)
+		.and_then(|()| out.into_inner().map_err(|err| err.into_error()))
+		.and_then(|out| out.sync_all());
+
+	drop(file);
+
+	if let Err(err) = written.and_then(|()| fs::rename(&temp, output)) {
+		eprintln!("error while writing repaired demo: {}", err);
+
+		// The partial copy is useless, and removing it is best effort
+		let _ = fs::remove_file(&temp);
+	}
+}
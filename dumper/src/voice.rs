@@ -1,24 +1,27 @@
+use bitstream::BitSlice;
 use demo::packets::Packet;
 use super::Handler;
 
 pub struct DumpVoiceData;
 
 impl Handler for DumpVoiceData {
-	fn packet(&mut self, packet: Packet) {
+	fn packet(&mut self, packet: Packet<BitSlice>) {
 		match packet {
 			Packet::VoiceInit(packet) => {
 				println!("{:?}", packet)
 			},
 			Packet::VoiceData(packet) => {
-				//println!("{}", packet.data.raw_bytes().len());
-				print!("[Sender: {}, Proximity: {}, Bytes: {}] ", packet.sender, packet.proximity, packet.data.raw_bytes().len());
+				let data = packet.data.to_bits();
 
-				if packet.data.bits_len() == 0 {
+				//println!("{}", data.raw_bytes().len());
+				print!("[Sender: {}, Proximity: {}, Bytes: {}] ", packet.sender, packet.proximity, data.raw_bytes().len());
+
+				if data.bits_len() == 0 {
 					println!("[Voice Data Ack]");
 					return;
 				}
 
-				for &byte in packet.data.raw_bytes().iter()/*.take(20)*/ {
+				for &byte in data.raw_bytes().iter()/*.take(20)*/ {
 					print!("{:02X} ", byte);
 				}
 